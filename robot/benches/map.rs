@@ -0,0 +1,102 @@
+use std::cmp::Ordering;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+use robot::map::{Distance, Point, PolyMap, Polygon, Segment};
+
+/// A room of `side` by `side` meters filled with square pillars a meter apart,
+/// about 4 * side * side segments
+fn pillars(side: usize) -> PolyMap {
+    let square = |x: Distance, y: Distance, size: Distance| Polygon {
+        points: vec![
+            Point { x, y },
+            Point { x: x + size, y },
+            Point {
+                x: x + size,
+                y: y + size,
+            },
+            Point { x, y: y + size },
+        ],
+        is_closed: true,
+    };
+    let mut polygons = vec![square(0., 0., side as Distance)];
+    for i in 0..side {
+        for j in 0..side {
+            polygons.push(square(i as Distance + 0.4, j as Distance + 0.4, 0.2));
+        }
+    }
+    PolyMap::new(polygons)
+}
+
+/// Range sensor like beams, from random points of the room
+fn beams(side: usize, count: usize) -> Vec<Segment> {
+    let mut rng = SmallRng::seed_from_u64(0);
+    (0..count)
+        .map(|_| {
+            let start = Point {
+                x: rng.gen_range(0., side as Distance),
+                y: rng.gen_range(0., side as Distance),
+            };
+            let angle: f32 = rng.gen_range(0., 2. * std::f32::consts::PI);
+            let end = Point {
+                x: start.x + 2. * angle.cos(),
+                y: start.y + 2. * angle.sin(),
+            };
+            Segment(start, end)
+        })
+        .collect()
+}
+
+/// What `first_intersection` did before the index
+fn linear_first_intersection(map: &PolyMap, s: &Segment) -> Option<Point> {
+    map.segments()
+        .filter_map(|seg| s.intersection(&seg))
+        .min_by(|a, b| {
+            s.0.sq_dist(*a)
+                .partial_cmp(&s.0.sq_dist(*b))
+                .unwrap_or(Ordering::Equal)
+        })
+}
+
+fn first_intersection(c: &mut Criterion) {
+    let mut group = c.benchmark_group("first_intersection");
+    for &side in [10, 30, 50].iter() {
+        let map = pillars(side);
+        let beams = beams(side, 100);
+        let segments = map.segments().count();
+        group.bench_with_input(BenchmarkId::new("indexed", segments), &map, |b, map| {
+            b.iter(|| {
+                beams
+                    .iter()
+                    .filter_map(|s| map.first_intersection(s))
+                    .count()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("linear", segments), &map, |b, map| {
+            b.iter(|| {
+                beams
+                    .iter()
+                    .filter_map(|s| linear_first_intersection(map, s))
+                    .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn build_index(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build_index");
+    group.sample_size(10);
+    for &side in [10, 50].iter() {
+        let segments = pillars(side).segments().count();
+        group.bench_with_input(BenchmarkId::from_parameter(segments), &side, |b, &side| {
+            b.iter(|| pillars(side))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, first_intersection, build_index);
+criterion_main!(benches);
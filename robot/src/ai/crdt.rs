@@ -0,0 +1,202 @@
+//! The shared map as a state-based CRDT: each cell holds a version, made of its state
+//! and its provenance, and two maps are merged by keeping the greatest version of each cell.
+//! Versions being totally ordered, the merge is commutative, associative and idempotent,
+//! so robots which received the same observations have the same map, whatever the order
+//! in which the observations were received, or how many times.
+
+use ndarray::{Array2, Zip};
+
+use crate::ai::merge::Provenance;
+use crate::ai::CellState::{self, *};
+
+/// Total orders on the versions of a cell
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Order {
+    /// The latest observation wins
+    Latest,
+    /// Blocked wins over SeenFree, which wins over Uncharted, then the latest observation wins
+    MostBlocked,
+}
+
+impl Order {
+    /// Versions compare like their keys. Uncharted cells are never newer than charted ones,
+    /// and the state breaks ties between observations with the same provenance.
+    fn key(
+        self,
+        state: CellState,
+        provenance: Provenance,
+    ) -> (CellState, bool, Provenance, CellState) {
+        match self {
+            Order::Latest => (Uncharted, state != Uncharted, provenance, state),
+            Order::MostBlocked => (state, true, provenance, state),
+        }
+    }
+}
+
+/// Merges `update` into `map`, keeping the greatest version of each cell
+pub fn join(
+    order: Order,
+    (map, provenance): (&mut Array2<CellState>, &mut Array2<Provenance>),
+    (update, update_provenance): (&Array2<CellState>, &Array2<Provenance>),
+) {
+    Zip::from(map)
+        .and(provenance)
+        .and(update)
+        .and(update_provenance)
+        .apply(|old, old_provenance, &new, &new_provenance| {
+            if order.key(new, new_provenance) > order.key(*old, *old_provenance) {
+                *old = new;
+                *old_provenance = new_provenance;
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::merge::Stamp;
+    use crate::app::AppId;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+
+    const SIZE: (usize, usize) = (6, 6);
+    const ORDERS: [Order; 2] = [Order::Latest, Order::MostBlocked];
+
+    type Replica = (Array2<CellState>, Array2<Provenance>);
+
+    fn empty() -> Replica {
+        (Array2::default(SIZE), Array2::default(SIZE))
+    }
+
+    /// Observations of a few cells by `robot`, stamps are shared to create ties
+    fn random_update(rng: &mut StdRng, robot: AppId) -> Replica {
+        let (mut map, mut provenance) = empty();
+        for _ in 0..rng.gen_range(1, 10) {
+            let xy = (rng.gen_range(0, SIZE.0), rng.gen_range(0, SIZE.1));
+            map[xy] = *[Uncharted, SeenFree, Blocked].choose(rng).unwrap();
+            provenance[xy] = Provenance {
+                stamp: rng.gen_range(1, 5) as Stamp,
+                robot,
+            };
+        }
+        (map, provenance)
+    }
+
+    fn merge(order: Order, replica: &mut Replica, update: &Replica) {
+        join(
+            order,
+            (&mut replica.0, &mut replica.1),
+            (&update.0, &update.1),
+        );
+    }
+
+    #[test]
+    fn join_test() {
+        let map = Array2::from_shape_vec((1, 3), vec![SeenFree, Blocked, Blocked]).unwrap();
+        let provenance = |stamps: [Stamp; 3]| {
+            Array2::from_shape_vec(
+                (1, 3),
+                stamps
+                    .iter()
+                    .map(|&stamp| Provenance { stamp, robot: 1 })
+                    .collect(),
+            )
+            .unwrap()
+        };
+        let update = Array2::from_shape_vec((1, 3), vec![Blocked, SeenFree, Uncharted]).unwrap();
+        let update_provenance = provenance([1, 9, 9]);
+
+        let mut latest = (map.clone(), provenance([5, 5, 5]));
+        merge(
+            Order::Latest,
+            &mut latest,
+            &(update.clone(), update_provenance.clone()),
+        );
+        assert_eq!(latest.0.as_slice().unwrap(), &[SeenFree, SeenFree, Blocked]);
+        assert_eq!(latest.1, provenance([5, 9, 5]));
+
+        let mut most_blocked = (map, provenance([5, 5, 5]));
+        merge(
+            Order::MostBlocked,
+            &mut most_blocked,
+            &(update, update_provenance),
+        );
+        assert_eq!(
+            most_blocked.0.as_slice().unwrap(),
+            &[Blocked, Blocked, Blocked]
+        );
+        assert_eq!(most_blocked.1, provenance([1, 5, 5]));
+    }
+
+    #[test]
+    fn commutative_idempotent_test() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for &order in ORDERS.iter() {
+            for _ in 0..100 {
+                let a = random_update(&mut rng, 1);
+                let b = random_update(&mut rng, 2);
+                let mut ab = a.clone();
+                merge(order, &mut ab, &b);
+                let mut ba = b.clone();
+                merge(order, &mut ba, &a);
+                assert_eq!(ab, ba);
+
+                let mut aba = ab.clone();
+                merge(order, &mut aba, &a);
+                merge(order, &mut aba, &ab);
+                assert_eq!(aba, ab);
+            }
+        }
+    }
+
+    #[test]
+    fn associative_test() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for &order in ORDERS.iter() {
+            for _ in 0..100 {
+                let a = random_update(&mut rng, 1);
+                let b = random_update(&mut rng, 2);
+                let c = random_update(&mut rng, 3);
+                let mut ab_c = a.clone();
+                merge(order, &mut ab_c, &b);
+                merge(order, &mut ab_c, &c);
+                let mut bc = b.clone();
+                merge(order, &mut bc, &c);
+                let mut a_bc = a.clone();
+                merge(order, &mut a_bc, &bc);
+                assert_eq!(ab_c, a_bc);
+            }
+        }
+    }
+
+    /// Replicas receiving the same messages, reordered and duplicated,
+    /// some of them relayed through another replica, end up with the same map
+    #[test]
+    fn convergence_test() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for &order in ORDERS.iter() {
+            for _ in 0..50 {
+                let messages: Vec<Replica> =
+                    (0..20).map(|i| random_update(&mut rng, i % 4)).collect();
+                let mut replicas = [empty(), empty(), empty()];
+                for replica in replicas.iter_mut() {
+                    let mut deliveries: Vec<&Replica> = messages.iter().collect();
+                    for _ in 0..10 {
+                        deliveries.push(messages.choose(&mut rng).unwrap());
+                    }
+                    deliveries.shuffle(&mut rng);
+                    for message in deliveries {
+                        merge(order, replica, message);
+                    }
+                }
+                // a replica relaying its whole map changes nothing
+                let relayed = replicas[0].clone();
+                merge(order, &mut replicas[1], &relayed);
+
+                assert_eq!(replicas[0], replicas[1]);
+                assert_eq!(replicas[1], replicas[2]);
+            }
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+
+use crate::ai::crdt;
+use crate::ai::CellState::{self, *};
+use crate::app::AppId;
+
+/// Milliseconds since the UNIX epoch, the robots are assumed to have synchronised clocks.
+/// On a virtual clock, milliseconds since the start of the simulation, plus one.
+pub type Stamp = u64;
+
+pub fn now() -> Stamp {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as Stamp)
+        .unwrap_or(0)
+}
+
+/// Which robot last observed a cell, and when.
+/// Cells which were never observed have a null stamp.
+/// Serialized as a pair, and only for the tiles which changed since the last map update.
+#[derive(
+    Copy, Clone, Default, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(from = "(Stamp, AppId)", into = "(Stamp, AppId)")]
+pub struct Provenance {
+    pub stamp: Stamp,
+    pub robot: AppId,
+}
+
+impl From<(Stamp, AppId)> for Provenance {
+    fn from((stamp, robot): (Stamp, AppId)) -> Self {
+        Provenance { stamp, robot }
+    }
+}
+
+impl From<Provenance> for (Stamp, AppId) {
+    fn from(provenance: Provenance) -> Self {
+        (provenance.stamp, provenance.robot)
+    }
+}
+
+/// How the map of another robot is merged into ours, named as on the command line
+#[derive(Copy, Clone, Default, Serialize, Deserialize, Debug, PartialEq)]
+pub enum MergePolicy {
+    /// Blocked always wins, SeenFree overwrites Uncharted, a CRDT
+    #[default]
+    #[serde(rename = "blocked-wins")]
+    BlockedWins,
+    /// The most recent observation of each cell wins, a CRDT
+    #[serde(rename = "lww")]
+    LastWriterWins,
+    /// Each robot votes with its last map, Blocked wins ties.
+    /// The result depends on the order of the updates.
+    #[serde(rename = "voting")]
+    Voting,
+    /// Same as voting, but the votes are weighted by the trust in each robot
+    #[serde(rename = "trust")]
+    TrustWeighted,
+}
+
+impl MergePolicy {
+    /// The merged maps converge whatever the order of the updates
+    pub fn is_crdt(self) -> bool {
+        match self {
+            MergePolicy::BlockedWins | MergePolicy::LastWriterWins => true,
+            MergePolicy::Voting | MergePolicy::TrustWeighted => false,
+        }
+    }
+}
+
+impl FromStr for MergePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blocked-wins" => Ok(MergePolicy::BlockedWins),
+            "lww" => Ok(MergePolicy::LastWriterWins),
+            "voting" => Ok(MergePolicy::Voting),
+            "trust" => Ok(MergePolicy::TrustWeighted),
+            _ => Err(format!(
+                "unknown merge policy {}, expected blocked-wins, lww, voting or trust",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MergePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            MergePolicy::BlockedWins => "blocked-wins",
+            MergePolicy::LastWriterWins => "lww",
+            MergePolicy::Voting => "voting",
+            MergePolicy::TrustWeighted => "trust",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Merges the maps of the other robots into ours, following a policy
+#[derive(Debug)]
+pub struct Merger {
+    pub policy: MergePolicy,
+    // Weight of the votes of each robot, 1 if not given
+    trust: HashMap<AppId, f32>,
+    // Last map received from each robot, for the votes
+    claims: BTreeMap<AppId, Array2<CellState>>,
+}
+
+impl Merger {
+    pub fn new(policy: MergePolicy) -> Self {
+        Merger {
+            policy,
+            trust: HashMap::new(),
+            claims: BTreeMap::new(),
+        }
+    }
+
+    /// A robot with no trust is ignored by the trust weighted policy
+    pub fn set_trust(&mut self, robot: AppId, trust: f32) {
+        self.trust.insert(robot, trust);
+    }
+
+    fn weight(&self, robot: AppId) -> f32 {
+        match self.policy {
+            MergePolicy::TrustWeighted => self.trust.get(&robot).cloned().unwrap_or(1.),
+            _ => 1.,
+        }
+    }
+
+    /// Merges `update`, sent by `sender`, into `map`. The provenance of the changed cells is updated,
+    /// `me` is the robot owning the map, whose own observations are given a vote.
+    pub fn merge(
+        &mut self,
+        me: AppId,
+        (map, provenance): (&mut Array2<CellState>, &mut Array2<Provenance>),
+        sender: AppId,
+        (update, update_provenance): (Array2<CellState>, &Array2<Provenance>),
+    ) {
+        match self.policy {
+            MergePolicy::BlockedWins => crdt::join(
+                crdt::Order::MostBlocked,
+                (map, provenance),
+                (&update, update_provenance),
+            ),
+            MergePolicy::LastWriterWins => crdt::join(
+                crdt::Order::Latest,
+                (map, provenance),
+                (&update, update_provenance),
+            ),
+            MergePolicy::Voting | MergePolicy::TrustWeighted => {
+                self.claims.insert(sender, update);
+                let voters: Vec<(f32, &Array2<CellState>)> = self
+                    .claims
+                    .iter()
+                    .filter(|&(&robot, _)| robot != me)
+                    .map(|(&robot, claim)| (self.weight(robot), claim))
+                    .collect();
+                let own_weight = self.weight(me);
+                let claim = &self.claims[&sender];
+                for ((xy, old), old_provenance) in map.indexed_iter_mut().zip(provenance.iter_mut())
+                {
+                    let (mut free, mut blocked) = (0., 0.);
+                    let mut vote = |state, weight| match state {
+                        SeenFree => free += weight,
+                        Blocked => blocked += weight,
+                        Uncharted => (),
+                    };
+                    if old_provenance.robot == me {
+                        vote(*old, own_weight);
+                    }
+                    for (weight, claim) in voters.iter() {
+                        vote(claim[xy], *weight);
+                    }
+                    let merged = if free + blocked <= 0. {
+                        *old
+                    } else if blocked >= free {
+                        Blocked
+                    } else {
+                        SeenFree
+                    };
+                    if merged != *old {
+                        *old = merged;
+                        if claim[xy] == merged {
+                            *old_provenance = update_provenance[xy];
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(cells: &[CellState]) -> Array2<CellState> {
+        Array2::from_shape_vec((1, cells.len()), cells.to_vec()).unwrap()
+    }
+
+    fn provenance(cells: &[(Stamp, AppId)]) -> Array2<Provenance> {
+        let cells = cells
+            .iter()
+            .map(|&(stamp, robot)| Provenance { stamp, robot })
+            .collect();
+        Array2::from_shape_vec((1, 3), cells).unwrap()
+    }
+
+    #[test]
+    fn blocked_wins_test() {
+        let mut merger = Merger::new(MergePolicy::BlockedWins);
+        let mut ours = map(&[SeenFree, Uncharted, Blocked]);
+        let mut ours_provenance = provenance(&[(5, 1), (0, 0), (5, 1)]);
+        let theirs = map(&[Blocked, SeenFree, SeenFree]);
+        let theirs_provenance = provenance(&[(1, 2), (1, 2), (9, 2)]);
+        merger.merge(
+            1,
+            (&mut ours, &mut ours_provenance),
+            2,
+            (theirs, &theirs_provenance),
+        );
+        assert_eq!(ours, map(&[Blocked, SeenFree, Blocked]));
+        assert_eq!(ours_provenance, provenance(&[(1, 2), (1, 2), (5, 1)]));
+    }
+
+    #[test]
+    fn last_writer_wins_test() {
+        let mut merger = Merger::new(MergePolicy::LastWriterWins);
+        let mut ours = map(&[SeenFree, Blocked, Blocked]);
+        let mut ours_provenance = provenance(&[(5, 1), (5, 1), (5, 1)]);
+        let theirs = map(&[Blocked, SeenFree, Uncharted]);
+        let theirs_provenance = provenance(&[(1, 2), (9, 2), (9, 2)]);
+        merger.merge(
+            1,
+            (&mut ours, &mut ours_provenance),
+            2,
+            (theirs, &theirs_provenance),
+        );
+        assert_eq!(ours, map(&[SeenFree, SeenFree, Blocked]));
+        assert_eq!(ours_provenance, provenance(&[(5, 1), (9, 2), (5, 1)]));
+    }
+
+    #[test]
+    fn voting_test() {
+        let mut merger = Merger::new(MergePolicy::Voting);
+        let mut ours = map(&[SeenFree, Uncharted, Uncharted]);
+        let mut ours_provenance = provenance(&[(5, 1), (0, 0), (0, 0)]);
+        let updates = [
+            (
+                3,
+                map(&[Blocked, Blocked, Blocked]),
+                provenance(&[(9, 3); 3]),
+            ),
+            (
+                2,
+                map(&[SeenFree, SeenFree, Uncharted]),
+                provenance(&[(8, 2); 3]),
+            ),
+        ];
+        for (sender, update, update_provenance) in updates.iter() {
+            merger.merge(
+                1,
+                (&mut ours, &mut ours_provenance),
+                *sender,
+                (update.clone(), update_provenance),
+            );
+            // Blocked wins the ties
+            assert_eq!(ours, map(&[Blocked, Blocked, Blocked]));
+        }
+
+        let update = map(&[SeenFree, SeenFree, SeenFree]);
+        merger.merge(
+            1,
+            (&mut ours, &mut ours_provenance),
+            4,
+            (update, &provenance(&[(7, 4); 3])),
+        );
+        assert_eq!(ours, map(&[SeenFree, SeenFree, Blocked]));
+        assert_eq!(ours_provenance, provenance(&[(7, 4), (7, 4), (9, 3)]));
+    }
+
+    #[test]
+    fn trust_weighted_test() {
+        let mut merger = Merger::new(MergePolicy::TrustWeighted);
+        // robot 3 is lying
+        merger.set_trust(3, 0.1);
+        let mut ours = map(&[SeenFree, Uncharted, SeenFree]);
+        let mut ours_provenance = provenance(&[(5, 1), (0, 0), (5, 1)]);
+        let update = map(&[Blocked, Blocked, Blocked]);
+        merger.merge(
+            1,
+            (&mut ours, &mut ours_provenance),
+            3,
+            (update, &provenance(&[(9, 3); 3])),
+        );
+        // nobody else saw the middle cell
+        assert_eq!(ours, map(&[SeenFree, Blocked, SeenFree]));
+
+        let update = map(&[SeenFree, SeenFree, Uncharted]);
+        merger.merge(
+            1,
+            (&mut ours, &mut ours_provenance),
+            2,
+            (update, &provenance(&[(8, 2); 3])),
+        );
+        assert_eq!(ours, map(&[SeenFree, SeenFree, SeenFree]));
+        assert_eq!(ours_provenance, provenance(&[(5, 1), (8, 2), (5, 1)]));
+    }
+
+    #[test]
+    fn parse_policy_test() {
+        for &policy in [
+            MergePolicy::BlockedWins,
+            MergePolicy::LastWriterWins,
+            MergePolicy::Voting,
+            MergePolicy::TrustWeighted,
+        ]
+        .iter()
+        {
+            assert_eq!(policy.to_string().parse(), Ok(policy));
+        }
+        assert!("majority".parse::<MergePolicy>().is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::thread;
+use std::time::Duration;
 
 use image::{Rgb, RgbImage};
 use imageproc::drawing::{draw_antialiased_line_segment_mut, draw_cross_mut, BresenhamLineIter};
@@ -7,14 +8,23 @@ use imageproc::pixelops::interpolate;
 use itertools::iproduct;
 use log;
 use ndarray::Array2;
-use ndarray::Axis;
+use ndarray::Zip;
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
 use crate::app::AppId;
-use crate::map::{Point, Position};
-use crate::robot::Robot;
-
+use crate::map::{Grid, Point, Position};
+use crate::mission::{Mission, Phase};
+use crate::robot::{RangeScan, RobotBackend};
+pub use merge::{MergePolicy, Provenance};
+pub use sync::{Digest, ProvenancePatch, Tile, TileId, UpdateSeq};
+
+mod crdt;
+mod merge;
 mod pathfinder;
+mod registration;
+mod sensor_model;
+mod slam;
+mod sync;
 
 const MAP_WIDTH: u32 = 2;
 const MAP_HEIGHT: u32 = 3; // = depth, i.e. dimension in front of the robot
@@ -24,8 +34,17 @@ const PIXELS_PER_METER: u32 = 100; // arbitrary precision of 1 cm
 const MAP_PWIDTH: usize = (MAP_WIDTH * PIXELS_PER_METER) as usize;
 const MAP_PHEIGHT: usize = (MAP_HEIGHT * PIXELS_PER_METER) as usize;
 const COLLISION_MERGE_DISTANCE: f32 = 0.1;
-
-#[derive(Copy, Clone, Serialize_repr, Deserialize_repr, Debug, PartialEq)]
+/// Distance from the center of the robot to its bumpers
+const BUMPER_DISTANCE: f32 = 0.02;
+/// The global registration of the map of a robot is tried at most once per period, in ms
+const GLOBAL_REGISTRATION_PERIOD: merge::Stamp = 10_000;
+/// The frame of a robot is searched again after so many failed local registrations
+const MAX_FAILED_REGISTRATIONS: usize = 3;
+
+/// Ordered from the least to the most informative
+#[derive(
+    Copy, Clone, Serialize_repr, Deserialize_repr, Debug, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
 #[repr(u8)]
 pub enum CellState {
     Uncharted,
@@ -68,26 +87,84 @@ pub struct AI {
     all_positions: HashMap<AppId, Position>,
     collisions: Vec<Point>,
     pub map_seen: Array2<CellState>,
+    // Cells found blocked by the collisions, the bumpers and the pathfinder, which the scans
+    // do not see, marked again when the map is rebuilt
+    marks: Array2<bool>,
     // Next pixel coordinates to go to
     // stored in reverse : last item of Vec is the next point
     next_targets: Vec<(u32, u32)>,
     // Used to mark area as seen between two target points
     next_steps: Vec<(u32, u32)>,
+    // The robot scans its surroundings, no need to guess what it has seen
+    has_range_sensor: bool,
+    // Corrects the odometry drift, the map is rebuilt from its poses
+    slam: slam::PoseGraph,
+    // Last position reported by the robot, in its odometry frame
+    odometry: Position,
+    // What the other robots have seen, kept to rebuild the map
+    peers_map: Array2<CellState>,
+    // Who last observed each cell of `map_seen`, and when
+    provenance: Array2<Provenance>,
+    // `map_seen` when the provenance was last updated, to find our new observations
+    stamped_map: Array2<CellState>,
+    // `provenance` when the map was last shared, only the tiles which changed since are sent
+    shared_provenance: Array2<Provenance>,
+    // Number of map updates sent
+    updates_shared: usize,
+    // A robot missed some of our updates, the next one carries the provenance of all the tiles
+    full_update: bool,
+    // The provenance of the maps of the other robots, patched by their updates
+    peers_provenance: HashMap<AppId, Array2<Provenance>>,
+    // Number of the last update of each robot, and whether none was missed since the last full one
+    peers_updates: HashMap<AppId, (usize, bool)>,
+    merger: merge::Merger,
+    // The other robots do not share our frame, their maps are registered before merging
+    align_maps: bool,
+    // Transforms bringing the frames of the other robots into ours
+    frames: HashMap<AppId, Position>,
+    // Failed local registrations of each robot since its frame was last found
+    failed_registrations: HashMap<AppId, usize>,
+    // When the frame of each robot was last searched globally
+    global_registrations: HashMap<AppId, merge::Stamp>,
+    // Where the frontiers are explored, or where to go instead
+    mission: Mission,
+    // Start position, to return to
+    home: Point,
+    // The map is drawn in output/ after each update
+    debug_images: bool,
+    // Time of the simulation stamping our observations, the wall clock is used if None
+    virtual_time: Option<Duration>,
 }
 
 /// position in meters
-fn pos_to_pixels(point: Point) -> (u32, u32) {
+pub fn pos_to_pixels(point: Point) -> (u32, u32) {
     let x = ((point.x + CENTER_X) * PIXELS_PER_METER as f32).round() as u32;
     let y = ((-point.y + CENTER_Y) * PIXELS_PER_METER as f32).round() as u32;
     (x, y)
 }
 
-fn pixels_to_pos(p: (u32, u32)) -> Point {
+/// Same as `pos_to_pixels`, but not rounded nor restricted to the map
+fn pos_to_fpixels(point: Point) -> (f32, f32) {
+    let x = (point.x + CENTER_X) * PIXELS_PER_METER as f32;
+    let y = (-point.y + CENTER_Y) * PIXELS_PER_METER as f32;
+    (x, y)
+}
+
+pub fn pixels_to_pos(p: (u32, u32)) -> Point {
     let x = p.0 as f32 / PIXELS_PER_METER as f32 - CENTER_X;
     let y = -(p.1 as f32 / PIXELS_PER_METER as f32) + CENTER_Y;
     Point { x, y }
 }
 
+/// The grid of the maps, to draw a `PolyMap` in
+pub fn grid() -> Grid {
+    Grid {
+        origin: pixels_to_pos((0, 0)),
+        resolution: 1. / PIXELS_PER_METER as f32,
+        size: (MAP_PWIDTH, MAP_PHEIGHT),
+    }
+}
+
 impl AI {
     pub fn new(app_id: AppId) -> Self {
         let mut ai = AI {
@@ -97,36 +174,62 @@ impl AI {
             collisions: Vec::new(),
             // the map is uncharted at the start
             map_seen: Array2::<CellState>::default((MAP_PWIDTH, MAP_PHEIGHT)),
+            marks: Array2::<bool>::default((MAP_PWIDTH, MAP_PHEIGHT)),
             next_targets: Vec::new(),
             next_steps: Vec::new(),
+            has_range_sensor: false,
+            slam: slam::PoseGraph::default(),
+            odometry: Position::default(),
+            peers_map: Array2::<CellState>::default((MAP_PWIDTH, MAP_PHEIGHT)),
+            provenance: Array2::<Provenance>::default((MAP_PWIDTH, MAP_PHEIGHT)),
+            stamped_map: Array2::<CellState>::default((MAP_PWIDTH, MAP_PHEIGHT)),
+            shared_provenance: Array2::<Provenance>::default((MAP_PWIDTH, MAP_PHEIGHT)),
+            updates_shared: 0,
+            full_update: true,
+            peers_provenance: HashMap::new(),
+            peers_updates: HashMap::new(),
+            merger: merge::Merger::new(MergePolicy::BlockedWins),
+            align_maps: false,
+            frames: HashMap::new(),
+            failed_registrations: HashMap::new(),
+            global_registrations: HashMap::new(),
+            mission: Mission::default(),
+            home: Point::zero(),
+            debug_images: true,
+            virtual_time: None,
         };
         ai.all_positions.insert(ai.app_id, Position::default());
 
         ai
     }
 
-    pub fn update(&mut self, robot: &mut Robot) {
+    pub fn update(&mut self, robot: &mut dyn RobotBackend) {
         let self_pos = self
             .all_positions
             .get(&self.app_id)
             .expect("self position is missing from all_positions")
             .p;
-        while let Some(step) = self.next_steps.pop() {
-            // We have reached a target, we need to mark every
-            // point from last target to current position as seen
-            if pixels_to_pos(step).sq_dist(self_pos) < 0.01 {
-                // We have marked every step until current position as seen
-                break;
-            } else {
-                self.mark_seen_circle_at_point(pixels_to_pos(step), 0.1);
+        if self.has_range_sensor {
+            // the scans mark what the robot has seen
+            self.next_steps.clear();
+        } else {
+            while let Some(step) = self.next_steps.pop() {
+                // We have reached a target, we need to mark every
+                // point from last target to current position as seen
+                if pixels_to_pos(step).sq_dist(self_pos) < 0.01 {
+                    // We have marked every step until current position as seen
+                    break;
+                } else {
+                    self.mark_seen_circle_at_point(pixels_to_pos(step), 0.1);
+                }
             }
+            self.mark_seen_circle(0.1);
         }
-        self.mark_seen_circle(0.1);
 
         if let Some(destination) = self.next_targets.pop() {
             // We still have targets to reach
             log::info!("go_to destination {:?}", destination);
-            robot.go_to(pixels_to_pos(destination));
+            robot.go_to(self.slam.to_odometry(pixels_to_pos(destination)));
         } else if let Some(target) = self.where_do_we_go() {
             // let delta = (target - self_pos).clip_norm(0.05);
             // log::info!(
@@ -148,47 +251,288 @@ impl AI {
                     self.next_targets,
                     pos_to_pixels(self_pos)
                 );
-                robot.go_to(pixels_to_pos(destination));
+                robot.go_to(self.slam.to_odometry(pixels_to_pos(destination)));
             } else {
                 log::error!(
                     "nowhere to go - pathfinding failed. marking target as blocked\nself_pos={:?} {:?}",
                     self_pos, pos_to_pixels(self_pos)
                 );
-                let (x, y) = pos_to_pixels(target);
-                self.map_seen[(x as usize, y as usize)] = Blocked;
+                self.mark_blocked(pos_to_pixels(target));
                 self.update_debug_image();
                 self.update(robot);
                 return;
             }
-        } else {
+        } else if self.mission.phase == Phase::Exploring {
             log::error!("nowhere to go from {:?}", pos_to_pixels(self_pos));
+        } else {
+            log::info!("{:?}, staying at {:?}", self.mission.phase, self_pos);
         }
 
         self.update_debug_image();
     }
 
-    pub fn merge_maps(&mut self, update: Array2<CellState>) {
-        for coords in iproduct!(
-            0..self.map_seen.len_of(Axis(0)),
-            0..self.map_seen.len_of(Axis(1))
-        ) {
-            let old = self.map_seen.get_mut(coords).unwrap();
-            match (*old, update[coords]) {
-                (_, Blocked) => *old = Blocked,
-                (Uncharted, SeenFree) => *old = SeenFree,
-                _ => (),
+    /// Draws the map in output/ after each update, for the HTML viewer
+    pub fn set_debug_images(&mut self, debug_images: bool) {
+        self.debug_images = debug_images;
+    }
+
+    /// Our observations are stamped with the time of the simulation, for reproducible runs
+    pub fn set_time(&mut self, time: Duration) {
+        self.virtual_time = Some(time);
+    }
+
+    pub fn set_home(&mut self, home: Point) {
+        self.home = home;
+    }
+
+    /// Follows a new mission, dropping the current path
+    pub fn set_mission(&mut self, mission: Mission, robot: &mut dyn RobotBackend) {
+        if mission != self.mission {
+            self.mission = mission;
+            self.next_targets.clear();
+            self.next_steps.clear();
+            self.update(robot);
+        }
+    }
+
+    /// The maps of the other robots will be registered against ours before being merged
+    pub fn set_map_alignment(&mut self, align_maps: bool) {
+        self.align_maps = align_maps;
+    }
+
+    pub fn set_merge_policy(&mut self, policy: MergePolicy) {
+        self.merger.policy = policy;
+    }
+
+    /// Weight of the votes of a robot, with the trust weighted merge policy
+    pub fn set_trust(&mut self, robot: AppId, trust: f32) {
+        self.merger.set_trust(robot, trust);
+    }
+
+    /// Our map and the provenance of the tiles which changed since it was last shared,
+    /// to be sent to the other robots
+    pub fn share_map(&mut self) -> (Array2<CellState>, UpdateSeq, Vec<ProvenancePatch>) {
+        self.stamp_observations();
+        let seq = UpdateSeq {
+            number: self.updates_shared,
+            full: self.full_update,
+        };
+        if self.full_update {
+            self.shared_provenance = Array2::default(self.provenance.dim());
+            self.full_update = false;
+        }
+        self.updates_shared += 1;
+        let ids = sync::changed(&self.shared_provenance, &self.provenance);
+        self.shared_provenance = self.provenance.clone();
+        (
+            self.map_seen.clone(),
+            seq,
+            sync::patches(&self.provenance, &ids),
+        )
+    }
+
+    /// A robot missed some of our map updates, the next one carries the provenance of all the tiles
+    pub fn request_full_update(&mut self) {
+        self.full_update = true;
+    }
+
+    /// Merges the map of another robot, its cells keep the provenance of the last patches.
+    /// Returns true when some of its updates were missed, and a full update should be requested.
+    pub fn merge_maps(
+        &mut self,
+        sender: AppId,
+        update: Array2<CellState>,
+        seq: UpdateSeq,
+        patches: &[ProvenancePatch],
+    ) -> bool {
+        let dim = update.dim();
+        let provenance = self
+            .peers_provenance
+            .entry(sender)
+            .or_insert_with(|| Array2::default(dim));
+        let last = self.peers_updates.get(&sender).cloned();
+        let stale = match last {
+            // relayed again, or overtaken by a later update
+            Some((number, in_sync)) if !seq.full && seq.number <= number => !in_sync,
+            _ => {
+                if provenance.dim() != dim || seq.full {
+                    *provenance = Array2::default(dim);
+                }
+                let in_sync = seq.full || last == Some((seq.number.wrapping_sub(1), true));
+                self.peers_updates.insert(sender, (seq.number, in_sync));
+                sync::apply(provenance, patches);
+                !in_sync
+            }
+        };
+        let provenance = provenance.clone();
+        let (update, provenance) = if self.align_maps {
+            let guess = self.frames.get(&sender).cloned();
+            if guess.is_none() && !self.global_registration_due(sender) {
+                return stale;
+            }
+            match registration::register(&self.map_seen, &update, guess) {
+                Some(frame) => {
+                    log::info!("map of {} registered at {:?}", sender, frame);
+                    self.frames.insert(sender, frame);
+                    self.failed_registrations.remove(&sender);
+                    (
+                        registration::transform_map(&update, frame),
+                        registration::transform_map(&provenance, frame),
+                    )
+                }
+                None => {
+                    log::info!("could not register the map of {}", sender);
+                    if guess.is_some() {
+                        let failed = self.failed_registrations.entry(sender).or_insert(0);
+                        *failed += 1;
+                        if *failed >= MAX_FAILED_REGISTRATIONS {
+                            log::info!("lost the frame of {}", sender);
+                            self.failed_registrations.remove(&sender);
+                            self.frames.remove(&sender);
+                        }
+                    }
+                    return stale;
+                }
+            }
+        } else {
+            (update, provenance)
+        };
+        self.merge_registered(sender, update, provenance);
+        stale
+    }
+
+    /// The tiles can be exchanged with the other robots, which needs a shared frame
+    /// and a merge policy not depending on the order of the updates
+    pub fn can_sync(&self) -> bool {
+        !self.align_maps && self.merger.policy.is_crdt()
+    }
+
+    /// Digests of the tiles of our map, for anti-entropy
+    pub fn digests(&mut self) -> Vec<Digest> {
+        self.stamp_observations();
+        sync::digests(&self.map_seen, &self.provenance)
+    }
+
+    /// Tiles which differ from the ones of a robot having sent `digests`
+    pub fn differing_tiles(&mut self, digests: &[Digest]) -> Vec<TileId> {
+        self.stamp_observations();
+        sync::differing(&self.map_seen, &self.provenance, digests)
+    }
+
+    pub fn tiles(&mut self, ids: &[TileId]) -> Vec<Tile> {
+        self.stamp_observations();
+        sync::tiles(&self.map_seen, &self.provenance, ids)
+    }
+
+    /// Merges tiles sent by another robot, ignored if we cannot sync
+    pub fn merge_tiles(&mut self, sender: AppId, tiles: &[Tile]) {
+        if !self.can_sync() {
+            return;
+        }
+        let (update, provenance) = sync::assemble(self.map_seen.dim(), tiles);
+        self.merge_registered(sender, update, provenance);
+    }
+
+    /// Merges a map already in our frame
+    fn merge_registered(
+        &mut self,
+        sender: AppId,
+        update: Array2<CellState>,
+        provenance: Array2<Provenance>,
+    ) {
+        self.stamp_observations();
+        self.merger.merge(
+            self.app_id,
+            (&mut self.map_seen, &mut self.provenance),
+            sender,
+            (update, &provenance),
+        );
+        self.stamped_map = self.map_seen.clone();
+        let app_id = self.app_id;
+        Zip::from(&mut self.peers_map)
+            .and(&self.map_seen)
+            .and(&self.provenance)
+            .apply(|peer, &cell, provenance| {
+                if provenance.robot != app_id {
+                    *peer = cell;
+                }
+            });
+        log::debug!("cells per observer: {:?}", self.observers());
+    }
+
+    /// The global registration is costly and runs on the message thread,
+    /// it is tried at most once per period for each robot
+    fn global_registration_due(&mut self, robot: AppId) -> bool {
+        let now = self.now();
+        match self.global_registrations.get(&robot) {
+            Some(&last) if now < last + GLOBAL_REGISTRATION_PERIOD => false,
+            _ => {
+                self.global_registrations.insert(robot, now);
+                true
             }
         }
     }
 
+    /// Time of the simulation, or of the wall clock
+    fn now(&self) -> merge::Stamp {
+        match self.virtual_time {
+            // the null stamp is for the cells never observed
+            Some(time) => time.as_millis() as merge::Stamp + 1,
+            None => merge::now(),
+        }
+    }
+
+    /// Our observations since the last call are stamped with the current time
+    fn stamp_observations(&mut self) {
+        let stamp = self.now();
+        let own = Provenance {
+            stamp,
+            robot: self.app_id,
+        };
+        Zip::from(&mut self.provenance)
+            .and(&self.map_seen)
+            .and(&self.stamped_map)
+            .apply(|provenance, cell, stamped| {
+                if cell != stamped {
+                    *provenance = own;
+                }
+            });
+        self.stamped_map = self.map_seen.clone();
+    }
+
+    /// Number of cells last observed by each robot, to debug bad maps
+    fn observers(&self) -> HashMap<AppId, usize> {
+        let mut observers = HashMap::new();
+        for provenance in self.provenance.iter().filter(|p| p.stamp > 0) {
+            *observers.entry(provenance.robot).or_insert(0) += 1;
+        }
+        observers
+    }
+
+    /// Our own position is reported by the odometry, and corrected before being stored
     pub fn update_robot_position(&mut self, id: AppId, pos: Position) {
-        self.all_positions.insert(id, pos);
+        if id == self.app_id {
+            self.odometry = pos;
+            self.all_positions.insert(id, self.slam.to_map(pos));
+        } else if !self.align_maps {
+            self.all_positions.insert(id, pos);
+        } else if let Some(frame) = self.frames.get(&id) {
+            self.all_positions.insert(id, frame.compose(pos));
+        }
+    }
+
+    /// Our corrected position
+    pub fn position(&self) -> Position {
+        *self
+            .all_positions
+            .get(&self.app_id)
+            .expect("self position is missing from all_positions")
     }
 
-    pub fn notify_collision(&mut self, robot: &mut Robot, point: Point) {
+    pub fn notify_collision(&mut self, robot: &mut dyn RobotBackend, point: Point) {
+        let point = self.slam.to_map(Position { p: point, a: 0. }).p;
         self.register_collision(point);
-        let (x, y) = pos_to_pixels(point);
-        self.map_seen[(x as usize, y as usize)] = Blocked;
+        self.mark_blocked(pos_to_pixels(point));
         // self.mark_seen_circle(0.1);
 
         //removing planned path - path will be re-computed
@@ -199,15 +543,68 @@ impl AI {
         self.update_debug_image();
     }
 
+    /// Updates the map with a scan made from the last known position of the robot.
+    /// When a loop is closed, the map is rebuilt from the corrected poses.
+    pub fn notify_scan(&mut self, scan: &RangeScan) {
+        self.has_range_sensor = true;
+        if self.slam.add_scan(self.odometry, scan) {
+            self.all_positions
+                .insert(self.app_id, self.slam.to_map(self.odometry));
+            self.rebuild_map();
+        }
+        let pos = self.position();
+        for beam in sensor_model::beams(pos, scan) {
+            sensor_model::integrate(&mut self.map_seen, &beam);
+        }
+    }
+
+    /// Recomputes the map from the scans of the pose graph
+    fn rebuild_map(&mut self) {
+        self.map_seen = self.peers_map.clone();
+        for node in self.slam.nodes() {
+            for beam in sensor_model::beams(node.pose, &node.scan) {
+                sensor_model::integrate(&mut self.map_seen, &beam);
+            }
+        }
+        Zip::from(&mut self.map_seen)
+            .and(&self.marks)
+            .apply(|cell, &marked| {
+                if marked {
+                    *cell = Blocked;
+                }
+            });
+        // the planned path may cross walls now
+        self.next_steps.clear();
+        self.next_targets.clear();
+    }
+
+    /// Marks the cells in front of the pressed bumpers as blocked
+    pub fn notify_bump(&mut self, angles: &[f32]) {
+        let pos = self.position();
+        for &angle in angles {
+            let contact = Point { x: 0., y: 1. }.rotate(pos.a + angle) * BUMPER_DISTANCE;
+            self.mark_blocked(pos_to_pixels(pos.p + contact));
+        }
+    }
+
+    /// Marks a cell as blocked, also once the map is rebuilt
+    fn mark_blocked(&mut self, (x, y): (u32, u32)) {
+        let xy = (x as usize, y as usize);
+        if let Some(cell) = self.map_seen.get_mut(xy) {
+            *cell = Blocked;
+            self.marks[xy] = true;
+        }
+    }
+
     fn register_collision(&mut self, new: Point) {
         let start = pos_to_pixels(new);
         let s_f32 = (start.0 as f32, start.1 as f32);
-        for &p in self.collisions.iter() {
+        for p in self.collisions.clone() {
             if (p - new).sq_norm() <= COLLISION_MERGE_DISTANCE * COLLISION_MERGE_DISTANCE {
                 let end = pos_to_pixels(p);
                 let e_f32 = (end.0 as f32, end.1 as f32);
                 for x in BresenhamLineIter::new(s_f32, e_f32) {
-                    self.map_seen[(x.0 as usize, x.1 as usize)] = Blocked;
+                    self.mark_blocked((x.0 as u32, x.1 as u32));
                 }
             }
         }
@@ -247,9 +644,16 @@ impl AI {
             .all_positions
             .get(&self.app_id)
             .expect("self position is missing from all_positions");
+        match self.mission.phase {
+            Phase::Exploring => (),
+            Phase::Complete => return None,
+            Phase::ReturningHome if pos.p.sq_dist(self.home) < 0.0025 => return None,
+            Phase::ReturningHome => return Some(self.home),
+        }
         // point a little bit in front of the robot, because i want to prioritise frontier points in front of the robot
         let front = pos.p + Point { x: 0., y: 0.05 }.rotate(pos.a);
         self.detect_frontiers()
+            .filter(|&p| self.mission.contains(p))
             .map(|p| {
                 (
                     p,
@@ -351,6 +755,9 @@ impl AI {
     }
 
     fn update_debug_image(&mut self) {
+        if !self.debug_images {
+            return;
+        }
         self.debug_counter += 1;
         // if self.debug_counter % 2 != 0 {
         //     return;
@@ -414,4 +821,94 @@ mod tests {
         println!("a={:?} pos={:?}", b, pixels_to_pos(b));
         assert_eq!(pos_to_pixels(pixels_to_pos(b)), b);
     }
+
+    #[test]
+    fn grid_test() {
+        // the ground truth is drawn in the grid, and compared to the maps cell by cell
+        let grid = grid();
+        assert_eq!(grid.size, (MAP_PWIDTH, MAP_PHEIGHT));
+        for &(x, y) in [(0., 0.), (0.31, -0.42), (-0.996, 1.49), (0.5, 1.2)].iter() {
+            let p = Point { x, y };
+            let (px, py) = pos_to_pixels(p);
+            assert_eq!(grid.cell(p), Some((px as usize, py as usize)), "{:?}", p);
+        }
+        assert_eq!(grid.cell(Point { x: 5., y: 0. }), None);
+    }
+
+    #[test]
+    fn virtual_time_test() {
+        let mut ai = AI::new(1);
+        ai.set_time(Duration::from_millis(1500));
+        ai.map_seen[(10, 20)] = CellState::Blocked;
+        let (_, _, patches) = ai.share_map();
+        let stamps: Vec<merge::Stamp> = patches
+            .iter()
+            .flat_map(|(_, provenance)| provenance.iter().map(|p| p.stamp))
+            .filter(|&stamp| stamp > 0)
+            .collect();
+        assert_eq!(stamps, vec![1501]);
+    }
+
+    #[test]
+    fn lost_update_test() {
+        let mut a = AI::new(1);
+        let mut b = AI::new(2);
+        a.set_time(Duration::from_millis(1000));
+        let (map, seq, patches) = a.share_map();
+        assert!(!b.merge_maps(1, map, seq, &patches));
+
+        a.map_seen[(10, 20)] = CellState::Blocked;
+        let lost = a.share_map();
+        a.map_seen[(50, 20)] = CellState::Blocked;
+        let (map, seq, patches) = a.share_map();
+        assert!(b.merge_maps(1, map, seq, &patches));
+        assert_ne!(b.peers_provenance[&1], a.provenance);
+        // the lost update is late, the provenance is still stale
+        let (map, seq, patches) = lost;
+        assert!(b.merge_maps(1, map, seq, &patches));
+
+        a.request_full_update();
+        let (map, seq, patches) = a.share_map();
+        assert!(!b.merge_maps(1, map, seq, &patches));
+        assert_eq!(b.peers_provenance[&1], a.provenance);
+    }
+
+    #[test]
+    fn registration_attempts_test() {
+        let mut ai = AI::new(1);
+        ai.set_map_alignment(true);
+        ai.set_time(Duration::from_secs(1));
+        let map = ai.map_seen.clone();
+        let seq = |number| UpdateSeq { number, full: true };
+        // too few blocked cells to be registered
+        ai.merge_maps(2, map.clone(), seq(0), &[]);
+        assert!(ai.global_registrations.contains_key(&2));
+        assert!(!ai.global_registration_due(2));
+        ai.set_time(Duration::from_secs(12));
+        assert!(ai.global_registration_due(2));
+
+        ai.frames.insert(2, Position::default());
+        for number in 1..MAX_FAILED_REGISTRATIONS {
+            ai.merge_maps(2, map.clone(), seq(number), &[]);
+            assert!(ai.frames.contains_key(&2));
+        }
+        ai.merge_maps(2, map, seq(MAX_FAILED_REGISTRATIONS), &[]);
+        assert!(!ai.frames.contains_key(&2));
+    }
+
+    #[test]
+    fn marks_test() {
+        let mut ai = AI::new(1);
+        ai.update_robot_position(1, Position::default());
+        ai.notify_bump(&[0.]);
+        let (x, y) = pos_to_pixels(Point {
+            x: 0.,
+            y: BUMPER_DISTANCE,
+        });
+        let contact = (x as usize, y as usize);
+        assert_eq!(ai.map_seen[contact], CellState::Blocked);
+        // as when a loop is closed
+        ai.rebuild_map();
+        assert_eq!(ai.map_seen[contact], CellState::Blocked);
+    }
 }
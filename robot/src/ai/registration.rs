@@ -0,0 +1,238 @@
+use itertools::iproduct;
+use ndarray::Array2;
+
+use crate::ai::{pixels_to_pos, pos_to_fpixels, CellState};
+use crate::map::{normalize_angle, Angle, Distance, Point, Position};
+
+/// Maps with fewer blocked cells are not registered
+const MIN_POINTS: usize = 20;
+/// The blocked cells of the other map are subsampled to this
+const MAX_POINTS: usize = 400;
+/// Part of the blocked cells of the other map which must fall on ours
+const MIN_SCORE: f32 = 0.5;
+/// Global search, when the relative frame is unknown
+const GLOBAL_WINDOW: Distance = 1.;
+const GLOBAL_STEP: Distance = 0.05;
+const GLOBAL_ANGLE_STEP: Angle = 5. * std::f32::consts::PI / 180.;
+/// Tolerance of the global search, in cells
+const GLOBAL_TOLERANCE: usize = 5;
+/// Local search, around the previous frame or the best global candidate
+const LOCAL_WINDOW: i32 = 5;
+const LOCAL_STEP: Distance = 0.01;
+const LOCAL_ANGLE_STEP: Angle = std::f32::consts::PI / 180.;
+const LOCAL_TOLERANCE: usize = 1;
+
+/// Estimates the rigid transform bringing `theirs` in the frame of `ours`,
+/// by correlative matching of the blocked cells.
+/// The search is local around `guess` if given, global otherwise.
+pub fn register(
+    ours: &Array2<CellState>,
+    theirs: &Array2<CellState>,
+    guess: Option<Position>,
+) -> Option<Position> {
+    let blocked: Vec<Point> = theirs
+        .indexed_iter()
+        .filter(|(_, &cell)| cell == CellState::Blocked)
+        .map(|((x, y), _)| pixels_to_pos((x as u32, y as u32)))
+        .collect();
+    let stride = (blocked.len() / MAX_POINTS).max(1);
+    let points: Vec<Point> = blocked.into_iter().step_by(stride).collect();
+    if points.len() < MIN_POINTS || count_blocked(ours) < MIN_POINTS {
+        return None;
+    }
+
+    let guess = match guess {
+        Some(guess) => guess,
+        None => {
+            let window = (GLOBAL_WINDOW / GLOBAL_STEP).round() as i32;
+            let nb_angles = (2. * std::f32::consts::PI / GLOBAL_ANGLE_STEP).round() as i32;
+            let reachable = dilate(ours, GLOBAL_TOLERANCE);
+            search(
+                &reachable,
+                &points,
+                Position::default(),
+                (window, GLOBAL_STEP),
+                (nb_angles / 2, GLOBAL_ANGLE_STEP),
+            )
+            .1
+        }
+    };
+
+    let reachable = dilate(ours, LOCAL_TOLERANCE);
+    let (score, transform) = search(
+        &reachable,
+        &points,
+        guess,
+        (LOCAL_WINDOW, LOCAL_STEP),
+        (LOCAL_WINDOW, LOCAL_ANGLE_STEP),
+    );
+    if score >= MIN_SCORE {
+        Some(transform)
+    } else {
+        None
+    }
+}
+
+/// Resamples `theirs` in our frame, `transform` bringing their frame into ours
+pub fn transform_map<T: Copy + Default>(theirs: &Array2<T>, transform: Position) -> Array2<T> {
+    let mut result = Array2::<T>::default(theirs.dim());
+    for ((x, y), cell) in result.indexed_iter_mut() {
+        let ours = Position {
+            p: pixels_to_pos((x as u32, y as u32)),
+            a: 0.,
+        };
+        if let Some(&state) = lookup(theirs, ours.relative_to(transform).p) {
+            *cell = state;
+        }
+    }
+    result
+}
+
+/// Best score and transform in a window around `center`,
+/// the closest transforms are preferred on ties
+fn search(
+    reachable: &Array2<bool>,
+    points: &[Point],
+    center: Position,
+    (window, step): (i32, Distance),
+    (angle_window, angle_step): (i32, Angle),
+) -> (f32, Position) {
+    let mut best = (0, center);
+    let mut angles: Vec<i32> = (-angle_window..=angle_window).collect();
+    angles.sort_by_key(|a| a.abs());
+    let mut offsets: Vec<(i32, i32)> = iproduct!(-window..=window, -window..=window).collect();
+    offsets.sort_by_key(|&(x, y)| x * x + y * y);
+
+    for a in angles {
+        let angle = normalize_angle(center.a + a as Angle * angle_step);
+        let rotated: Vec<Point> = points.iter().map(|p| p.rotate(angle)).collect();
+        for &(x, y) in offsets.iter() {
+            let translation = center.p
+                + Point {
+                    x: x as Distance * step,
+                    y: y as Distance * step,
+                };
+            let score = rotated
+                .iter()
+                .filter(|&&p| lookup(reachable, p + translation) == Some(&true))
+                .count();
+            if score > best.0 {
+                best = (
+                    score,
+                    Position {
+                        p: translation,
+                        a: angle,
+                    },
+                );
+            }
+        }
+    }
+    (best.0 as f32 / points.len() as f32, best.1)
+}
+
+/// Cells closer than `tolerance` from a blocked cell
+fn dilate(map: &Array2<CellState>, tolerance: usize) -> Array2<bool> {
+    let (width, height) = map.dim();
+    let mut result = Array2::from_elem(map.dim(), false);
+    for ((x, y), &cell) in map.indexed_iter() {
+        if cell == CellState::Blocked {
+            for n in iproduct!(
+                x.saturating_sub(tolerance)..(x + tolerance + 1).min(width),
+                y.saturating_sub(tolerance)..(y + tolerance + 1).min(height)
+            ) {
+                result[n] = true;
+            }
+        }
+    }
+    result
+}
+
+fn count_blocked(map: &Array2<CellState>) -> usize {
+    map.iter()
+        .filter(|&&cell| cell == CellState::Blocked)
+        .count()
+}
+
+fn lookup<T>(map: &Array2<T>, point: Point) -> Option<&T> {
+    let (x, y) = pos_to_fpixels(point);
+    let (x, y) = (x.round(), y.round());
+    if x < 0. || y < 0. {
+        None
+    } else {
+        map.get((x as usize, y as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::{pos_to_pixels, MAP_PHEIGHT, MAP_PWIDTH};
+
+    /// An L shaped wall and a pillar, seen from a frame where the world is at `pose`
+    fn map(pose: Position) -> Array2<CellState> {
+        let mut map = Array2::<CellState>::default((MAP_PWIDTH, MAP_PHEIGHT));
+        let walls = [
+            (Point { x: -0.6, y: 0.8 }, Point { x: 0.5, y: 0.8 }),
+            (Point { x: 0.5, y: 0.8 }, Point { x: 0.5, y: -0.4 }),
+            (Point { x: -0.3, y: -0.2 }, Point { x: -0.3, y: 0. }),
+        ];
+        for &(a, b) in walls.iter() {
+            for k in 0..=200 {
+                let world = a + (b - a) * (k as Distance / 200.);
+                let seen = pose.compose(Position { p: world, a: 0. }).p;
+                let (x, y) = pos_to_pixels(seen);
+                map[(x as usize, y as usize)] = CellState::Blocked;
+            }
+        }
+        map
+    }
+
+    #[test]
+    fn register_test() {
+        let ours = map(Position::default());
+        let transform = Position {
+            p: Point { x: 0.2, y: -0.15 },
+            a: 0.5,
+        };
+        // the world is at `transform` in our frame, so at its inverse in theirs
+        let origin = Position::default().relative_to(transform);
+        let theirs = map(origin);
+
+        let found = register(&ours, &theirs, None).expect("the maps should match");
+        let error = found.relative_to(transform);
+        assert!(error.p.norm() < 0.03, "{:?}", found);
+        assert!(error.a.abs() < 0.03, "{:?}", found);
+
+        // refined from the previous transform
+        let refined = register(&ours, &theirs, Some(found)).expect("the maps should match");
+        assert!(refined.relative_to(transform).p.norm() < 0.03);
+
+        let aligned = transform_map(&theirs, found);
+        let reachable = dilate(&ours, 2);
+        let overlap = iproduct!(0..MAP_PWIDTH, 0..MAP_PHEIGHT)
+            .filter(|&xy| aligned[xy] == CellState::Blocked)
+            .filter(|&xy| reachable[xy])
+            .count();
+        assert!(overlap as f32 > 0.8 * count_blocked(&aligned) as f32);
+
+        let empty = Array2::<CellState>::default((MAP_PWIDTH, MAP_PHEIGHT));
+        assert_eq!(register(&ours, &empty, None), None);
+    }
+
+    #[test]
+    fn transform_map_test() {
+        let mut map = Array2::<CellState>::default((MAP_PWIDTH, MAP_PHEIGHT));
+        map[(100, 150)] = CellState::Blocked;
+        assert_eq!(transform_map(&map, Position::default()), map);
+
+        let shifted = transform_map(
+            &map,
+            Position {
+                p: Point { x: 0.1, y: 0.2 },
+                a: 0.,
+            },
+        );
+        assert_eq!(shifted[(110, 130)], CellState::Blocked);
+        assert_eq!(count_blocked(&shifted), 1);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::ai::{pos_to_fpixels, CellState};
+use crate::map::{Point, Position};
+use crate::robot::RangeScan;
+use imageproc::drawing::BresenhamLineIter;
+use ndarray::Array2;
+
+use CellState::*;
+
+/// Ranges under this are ignored, the sensor is probably against a wall (m)
+const MIN_RANGE: f32 = 0.01;
+
+/// A beam traced in the grid, in pixel coordinates
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Beam {
+    pub start: (i32, i32),
+    pub end: (i32, i32),
+    /// The beam hit something at its end, otherwise it reached the maximum range
+    pub hit: bool,
+}
+
+impl Beam {
+    /// Cells crossed by the beam, both ends included.
+    /// Note that `BresenhamLineIter` may iterate from the end to the start.
+    fn cells(&self) -> impl Iterator<Item = (i32, i32)> {
+        let start = (self.start.0 as f32, self.start.1 as f32);
+        let end = (self.end.0 as f32, self.end.1 as f32);
+        BresenhamLineIter::new(start, end)
+    }
+}
+
+/// Beams of a scan made at `pose`, the pose of the sensor
+pub fn beams(pose: Position, scan: &RangeScan) -> impl Iterator<Item = Beam> + '_ {
+    let start = to_cell(pose.p);
+    scan.beams()
+        .filter(|&(_, range)| range >= MIN_RANGE)
+        .map(move |(angle, range)| {
+            let range = range.min(scan.max_range);
+            let end = pose.p + Point { x: 0., y: range }.rotate(pose.a + angle);
+            Beam {
+                start,
+                end: to_cell(end),
+                hit: range < scan.max_range,
+            }
+        })
+}
+
+/// Updates the grid with a beam: the crossed cells are free, the hit one is blocked.
+/// A beam which hit nothing only frees cells, up to its end.
+/// Cells already known to be blocked are never freed, and cells out of the grid are ignored.
+pub fn integrate(map: &mut Array2<CellState>, beam: &Beam) {
+    for xy in beam.cells() {
+        if beam.hit && xy == beam.end {
+            continue;
+        }
+        if let Some(cell) = cell_mut(map, xy) {
+            if *cell == Uncharted {
+                *cell = SeenFree;
+            }
+        }
+    }
+    if beam.hit {
+        if let Some(cell) = cell_mut(map, beam.end) {
+            *cell = Blocked;
+        }
+    }
+}
+
+fn to_cell(point: Point) -> (i32, i32) {
+    let (x, y) = pos_to_fpixels(point);
+    (x.round() as i32, y.round() as i32)
+}
+
+fn cell_mut(map: &mut Array2<CellState>, (x, y): (i32, i32)) -> Option<&mut CellState> {
+    if x < 0 || y < 0 {
+        None
+    } else {
+        map.get_mut((x as usize, y as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(map: &Array2<CellState>, state: CellState) -> usize {
+        map.iter().filter(|&&cell| cell == state).count()
+    }
+
+    #[test]
+    fn integrate_test() {
+        let mut map = Array2::<CellState>::default((10, 10));
+        // drawn from its end by BresenhamLineIter
+        let beam = Beam {
+            start: (8, 2),
+            end: (2, 2),
+            hit: true,
+        };
+        integrate(&mut map, &beam);
+        assert_eq!(map[(2, 2)], Blocked);
+        assert_eq!(count(&map, SeenFree), 6);
+        assert!((3..=8).all(|x| map[(x, 2)] == SeenFree));
+
+        // max range returns do not block anything, and do not free blocked cells
+        let beam = Beam {
+            start: (2, 8),
+            end: (2, 0),
+            hit: false,
+        };
+        integrate(&mut map, &beam);
+        assert_eq!(map[(2, 2)], Blocked);
+        assert_eq!(map[(2, 0)], SeenFree);
+        assert_eq!(count(&map, Blocked), 1);
+        assert_eq!(count(&map, SeenFree), 14);
+
+        // leaving the grid
+        let beam = Beam {
+            start: (5, 5),
+            end: (-5, 15),
+            hit: true,
+        };
+        integrate(&mut map, &beam);
+        assert_eq!(count(&map, Blocked), 1);
+        assert_eq!(map[(1, 9)], SeenFree);
+    }
+
+    #[test]
+    fn beams_test() {
+        let scan = RangeScan {
+            max_range: 0.5,
+            ranges: vec![0.2, 0.5, 0., 0.7],
+        };
+        let pose = Position {
+            p: Point::zero(),
+            a: std::f32::consts::PI / 2.,
+        };
+        let start = to_cell(pose.p);
+        let beams: Vec<Beam> = beams(pose, &scan).collect();
+        assert_eq!(beams.len(), 3);
+        // facing -x
+        assert_eq!(beams[0].end, (start.0 - 20, start.1));
+        assert!(beams[0].hit);
+        // facing -y, the y axis of the pixels goes down
+        assert_eq!(beams[1].end, (start.0, start.1 + 50));
+        assert!(!beams[1].hit);
+        // facing +y, clamped to the maximum range
+        assert_eq!(beams[2].end, (start.0, start.1 - 50));
+        assert!(!beams[2].hit);
+    }
+}
@@ -0,0 +1,521 @@
+use std::collections::HashSet;
+
+use itertools::iproduct;
+
+use crate::map::{normalize_angle, Angle, Distance, Point, Position};
+use crate::robot::RangeScan;
+
+/// A new node is added once the robot has moved that much since the last one (m)
+const KEYFRAME_DISTANCE: Distance = 0.05;
+/// ... or turned that much (rad)
+const KEYFRAME_ANGLE: Angle = 0.2;
+/// Loop closures are searched among the nodes closer than this (m)
+const LOOP_CLOSURE_DISTANCE: Distance = 0.3;
+/// Consecutive nodes are already linked by the odometry
+const LOOP_CLOSURE_MIN_NODES: usize = 20;
+/// Resolution of the grid used to match two scans (m)
+const MATCH_RESOLUTION: Distance = 0.01;
+/// Consecutive hits closer than this are assumed to be on the same wall (m)
+const MAX_WALL_GAP: Distance = 0.15;
+/// Search window of the scan matching, around the odometry guess
+const MATCH_WINDOW: i32 = 6;
+const MATCH_ANGLE_STEP: Angle = 0.025;
+const MATCH_ANGLE_WINDOW: i32 = 4;
+/// Part of the hits of a scan which must fall on the other scan hits to close a loop
+const MIN_MATCH_SCORE: f32 = 0.8;
+const MIN_MATCH_HITS: usize = 8;
+/// A loop closure agreeing that much with the graph brings nothing
+const MIN_CORRECTION: Distance = 0.01;
+const MIN_ANGLE_CORRECTION: Angle = 0.01;
+/// Variances of the odometry, per meter moved
+const ODOMETRY_TRANSLATION_VARIANCE: f64 = 0.0025;
+const ODOMETRY_ROTATION_VARIANCE: f64 = 0.01;
+/// Variances of a loop closure found by scan matching
+const LOOP_CLOSURE_TRANSLATION_VARIANCE: f64 = 0.0004;
+const LOOP_CLOSURE_ROTATION_VARIANCE: f64 = 0.0025;
+const GAUSS_NEWTON_ITERATIONS: usize = 10;
+const CONJUGATE_GRADIENT_ITERATIONS: usize = 300;
+
+type Vec3 = [f64; 3];
+type Mat3 = [[f64; 3]; 3];
+
+/// A pose of the robot, where a scan was made
+#[derive(Debug)]
+pub struct Node {
+    /// Optimised pose
+    pub pose: Position,
+    /// Pose reported by the odometry
+    pub odometry: Position,
+    pub scan: RangeScan,
+    // Where the beams hit, in the frame of the node
+    hits: Vec<Point>,
+}
+
+/// Constraint on the displacement between two nodes
+#[derive(Debug)]
+struct Edge {
+    from: usize,
+    to: usize,
+    /// Displacement expressed in the frame of `from`
+    delta: Position,
+    /// Inverse of the variances of x, y and the angle
+    information: Vec3,
+}
+
+/// 2D pose graph: the nodes are linked by the odometry and by loop closures
+/// found by scan matching, the poses are optimised with Gauss-Newton.
+/// The first node is fixed and anchors the map frame to the odometry frame.
+#[derive(Debug, Default)]
+pub struct PoseGraph {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+impl PoseGraph {
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// Corrects a pose reported by the odometry, using the last optimised node
+    pub fn to_map(&self, odometry: Position) -> Position {
+        match self.nodes.last() {
+            Some(last) => last.pose.compose(odometry.relative_to(last.odometry)),
+            None => odometry,
+        }
+    }
+
+    /// Inverse of `to_map`, for the destinations sent to the robot
+    pub fn to_odometry(&self, point: Point) -> Point {
+        match self.nodes.last() {
+            Some(last) => {
+                let pose = Position { p: point, a: 0. };
+                last.odometry.compose(pose.relative_to(last.pose)).p
+            }
+            None => point,
+        }
+    }
+
+    /// Adds a node if the robot has moved enough since the last one,
+    /// returns true when a loop was closed and the poses were optimised
+    pub fn add_scan(&mut self, odometry: Position, scan: &RangeScan) -> bool {
+        if let Some(last) = self.nodes.last() {
+            let delta = odometry.relative_to(last.odometry);
+            if delta.p.norm() < KEYFRAME_DISTANCE && delta.a.abs() < KEYFRAME_ANGLE {
+                return false;
+            }
+        }
+        let node = self.add_node(odometry, scan.clone());
+        match self.find_loop_closure(node) {
+            Some(edge) => {
+                log::info!("loop closed between nodes {} and {}", edge.from, edge.to);
+                self.edges.push(edge);
+                self.optimize();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn add_node(&mut self, odometry: Position, scan: RangeScan) -> usize {
+        let hits = scan
+            .beams()
+            .filter(|&(_, range)| range < scan.max_range)
+            .map(|(angle, range)| Point { x: 0., y: range }.rotate(angle))
+            .collect();
+        let node = Node {
+            pose: self.to_map(odometry),
+            odometry,
+            scan,
+            hits,
+        };
+        if let Some(last) = self.nodes.last() {
+            let delta = odometry.relative_to(last.odometry);
+            let dist = f64::from(delta.p.norm());
+            let translation = ODOMETRY_TRANSLATION_VARIANCE * dist + 1e-6;
+            let rotation = ODOMETRY_ROTATION_VARIANCE * (dist + f64::from(delta.a.abs())) + 1e-6;
+            self.edges.push(Edge {
+                from: self.nodes.len() - 1,
+                to: self.nodes.len(),
+                delta,
+                information: [1. / translation, 1. / translation, 1. / rotation],
+            });
+        }
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    /// Matches the scan of `node` against the one of the closest old node
+    fn find_loop_closure(&self, node: usize) -> Option<Edge> {
+        let pose = self.nodes[node].pose;
+        let (candidate, _) = self.nodes[..node.saturating_sub(LOOP_CLOSURE_MIN_NODES)]
+            .iter()
+            .enumerate()
+            .map(|(i, other)| (i, (other.pose.p - pose.p).norm()))
+            .filter(|&(_, dist)| dist < LOOP_CLOSURE_DISTANCE)
+            .min_by(|(_, d1), (_, d2)| d1.partial_cmp(d2).expect("NaN here ?"))?;
+
+        let guess = pose.relative_to(self.nodes[candidate].pose);
+        let delta = match_scans(&self.nodes[candidate].hits, &self.nodes[node].hits, guess)?;
+        let correction = delta.relative_to(guess);
+        if correction.p.norm() < MIN_CORRECTION && correction.a.abs() < MIN_ANGLE_CORRECTION {
+            return None;
+        }
+        Some(Edge {
+            from: candidate,
+            to: node,
+            delta,
+            information: [
+                1. / LOOP_CLOSURE_TRANSLATION_VARIANCE,
+                1. / LOOP_CLOSURE_TRANSLATION_VARIANCE,
+                1. / LOOP_CLOSURE_ROTATION_VARIANCE,
+            ],
+        })
+    }
+
+    /// Gauss-Newton optimisation of the poses, the first one being fixed.
+    /// The normal equations are solved by a conjugate gradient, without building the matrix.
+    pub fn optimize(&mut self) {
+        if self.nodes.len() < 2 {
+            return;
+        }
+        for _ in 0..GAUSS_NEWTON_ITERATIONS {
+            let linearized: Vec<_> = self.edges.iter().map(|e| self.linearize(e)).collect();
+
+            let mut b = vec![[0.; 3]; self.nodes.len()];
+            for (edge, (a, jb, error)) in self.edges.iter().zip(linearized.iter()) {
+                let weighted = weight(&edge.information, error);
+                add(&mut b[edge.from], &mul_transposed(a, &weighted));
+                add(&mut b[edge.to], &mul_transposed(jb, &weighted));
+            }
+            b[0] = [0.; 3];
+            for v in b.iter_mut() {
+                for x in v.iter_mut() {
+                    *x = -*x;
+                }
+            }
+
+            let dx = self.conjugate_gradient(&linearized, &b);
+            let mut largest: f64 = 0.;
+            for (node, d) in self.nodes.iter_mut().zip(dx.iter()) {
+                node.pose.p.x += d[0] as Distance;
+                node.pose.p.y += d[1] as Distance;
+                node.pose.a = normalize_angle(node.pose.a + d[2] as Angle);
+                largest = d.iter().fold(largest, |m, x| m.max(x.abs()));
+            }
+            if largest < 1e-5 {
+                break;
+            }
+        }
+    }
+
+    /// Error of an edge and its jacobians with respect to both nodes
+    fn linearize(&self, edge: &Edge) -> (Mat3, Mat3, Vec3) {
+        let from = self.nodes[edge.from].pose;
+        let to = self.nodes[edge.to].pose;
+        let (sin, cos) = f64::from(from.a).sin_cos();
+        let dx = f64::from(to.p.x - from.p.x);
+        let dy = f64::from(to.p.y - from.p.y);
+        let error = [
+            cos * dx + sin * dy - f64::from(edge.delta.p.x),
+            -sin * dx + cos * dy - f64::from(edge.delta.p.y),
+            f64::from(normalize_angle(to.a - from.a - edge.delta.a)),
+        ];
+        let a = [
+            [-cos, -sin, -sin * dx + cos * dy],
+            [sin, -cos, -cos * dx - sin * dy],
+            [0., 0., -1.],
+        ];
+        let b = [[cos, sin, 0.], [-sin, cos, 0.], [0., 0., 1.]];
+        (a, b, error)
+    }
+
+    /// Solves H x = b, H being the sum of the J^T Ω J of the edges,
+    /// with a Jacobi preconditioner
+    fn conjugate_gradient(&self, linearized: &[(Mat3, Mat3, Vec3)], b: &[Vec3]) -> Vec<Vec3> {
+        let n = self.nodes.len();
+        let product = |v: &[Vec3]| {
+            let mut result = vec![[0.; 3]; n];
+            for (edge, (a, jb, _)) in self.edges.iter().zip(linearized.iter()) {
+                let mut jv = mul(a, &v[edge.from]);
+                add(&mut jv, &mul(jb, &v[edge.to]));
+                let weighted = weight(&edge.information, &jv);
+                add(&mut result[edge.from], &mul_transposed(a, &weighted));
+                add(&mut result[edge.to], &mul_transposed(jb, &weighted));
+            }
+            // the first node is fixed
+            result[0] = [0.; 3];
+            result
+        };
+        let mut diagonal = vec![[0.; 3]; n];
+        for (edge, (a, jb, _)) in self.edges.iter().zip(linearized.iter()) {
+            for (node, j) in [(edge.from, a), (edge.to, jb)].iter() {
+                for (k, d) in diagonal[*node].iter_mut().enumerate() {
+                    *d += (0..3)
+                        .map(|r| j[r][k] * j[r][k] * edge.information[r])
+                        .sum::<f64>();
+                }
+            }
+        }
+        let precondition = |r: &[Vec3]| -> Vec<Vec3> {
+            r.iter()
+                .zip(diagonal.iter())
+                .map(|(r, d)| {
+                    let mut z = [0.; 3];
+                    for k in 0..3 {
+                        z[k] = if d[k] > 0. { r[k] / d[k] } else { 0. };
+                    }
+                    z
+                })
+                .collect()
+        };
+
+        let mut x = vec![[0.; 3]; n];
+        let mut r = b.to_vec();
+        let mut z = precondition(&r);
+        let mut p = z.clone();
+        let mut rz = dot(&r, &z);
+        let tolerance = dot(b, b) * 1e-12;
+        for _ in 0..CONJUGATE_GRADIENT_ITERATIONS {
+            if dot(&r, &r) <= tolerance {
+                break;
+            }
+            let hp = product(&p);
+            let alpha = rz / dot(&p, &hp);
+            for i in 0..n {
+                for k in 0..3 {
+                    x[i][k] += alpha * p[i][k];
+                    r[i][k] -= alpha * hp[i][k];
+                }
+            }
+            z = precondition(&r);
+            let rz_next = dot(&r, &z);
+            let beta = rz_next / rz;
+            rz = rz_next;
+            for i in 0..n {
+                for k in 0..3 {
+                    p[i][k] = z[i][k] + beta * p[i][k];
+                }
+            }
+        }
+        x
+    }
+}
+
+/// Correlative scan matching: searches the displacement around `guess`
+/// placing the most hits of `scan` on the hits of `reference`
+fn match_scans(reference: &[Point], scan: &[Point], guess: Position) -> Option<Position> {
+    if reference.len() < MIN_MATCH_HITS || scan.len() < MIN_MATCH_HITS {
+        return None;
+    }
+    let cell = |p: Point| {
+        (
+            (p.x / MATCH_RESOLUTION).round() as i32,
+            (p.y / MATCH_RESOLUTION).round() as i32,
+        )
+    };
+    // a hit matches if it is next to the walls seen by the reference,
+    // approximated by joining its close hits
+    let mut occupied = HashSet::new();
+    for (&a, &b) in reference.iter().zip(reference.iter().cycle().skip(1)) {
+        let gap = (b - a).norm();
+        let samples = if gap < MAX_WALL_GAP {
+            (gap / MATCH_RESOLUTION).ceil() as usize
+        } else {
+            0
+        };
+        for k in 0..=samples {
+            let (x, y) = cell(a + (b - a) * (k as Distance / samples.max(1) as Distance));
+            occupied.extend(iproduct!(x - 1..=x + 1, y - 1..=y + 1));
+        }
+    }
+
+    // closest displacements first, to prefer the guess when the scans are ambiguous
+    let mut offsets: Vec<(i32, i32, i32)> = iproduct!(
+        -MATCH_WINDOW..=MATCH_WINDOW,
+        -MATCH_WINDOW..=MATCH_WINDOW,
+        -MATCH_ANGLE_WINDOW..=MATCH_ANGLE_WINDOW
+    )
+    .collect();
+    offsets.sort_by_key(|&(x, y, a)| x * x + y * y + a * a);
+
+    let mut best = (0, guess);
+    for (x, y, a) in offsets {
+        let candidate = Position {
+            p: guess.p
+                + Point {
+                    x: x as Distance * MATCH_RESOLUTION,
+                    y: y as Distance * MATCH_RESOLUTION,
+                },
+            a: guess.a + a as Angle * MATCH_ANGLE_STEP,
+        };
+        let score = scan
+            .iter()
+            .filter(|&&p| {
+                let hit = candidate.compose(Position { p, a: 0. }).p;
+                occupied.contains(&cell(hit))
+            })
+            .count();
+        if score > best.0 {
+            best = (score, candidate);
+        }
+    }
+
+    if best.0 as f32 >= MIN_MATCH_SCORE * scan.len() as f32 {
+        Some(best.1)
+    } else {
+        None
+    }
+}
+
+fn mul(m: &Mat3, v: &Vec3) -> Vec3 {
+    let mut result = [0.; 3];
+    for (r, row) in m.iter().enumerate() {
+        result[r] = (0..3).map(|k| row[k] * v[k]).sum();
+    }
+    result
+}
+
+fn mul_transposed(m: &Mat3, v: &Vec3) -> Vec3 {
+    let mut result = [0.; 3];
+    for (k, x) in result.iter_mut().enumerate() {
+        *x = (0..3).map(|r| m[r][k] * v[r]).sum();
+    }
+    result
+}
+
+fn weight(information: &Vec3, v: &Vec3) -> Vec3 {
+    [
+        information[0] * v[0],
+        information[1] * v[1],
+        information[2] * v[2],
+    ]
+}
+
+fn add(to: &mut Vec3, v: &Vec3) {
+    for k in 0..3 {
+        to[k] += v[k];
+    }
+}
+
+fn dot(u: &[Vec3], v: &[Vec3]) -> f64 {
+    u.iter()
+        .zip(v.iter())
+        .map(|(u, v)| (0..3).map(|k| u[k] * v[k]).sum::<f64>())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{PolyMap, Segment};
+
+    const PI: Angle = std::f32::consts::PI;
+
+    fn empty_scan() -> RangeScan {
+        RangeScan {
+            max_range: 1.,
+            ranges: vec![],
+        }
+    }
+
+    /// Scan of `map` from `pose`, without noise
+    fn scan(map: &PolyMap, pose: Position) -> RangeScan {
+        let max_range = 2.;
+        let ranges = (0..360)
+            .map(|i| {
+                let angle = pose.a + i as Angle * 2. * PI / 360.;
+                let end = pose.p
+                    + Point {
+                        x: 0.,
+                        y: max_range,
+                    }
+                    .rotate(angle);
+                map.first_intersection(&Segment(pose.p, end))
+                    .map_or(max_range, |hit| (hit - pose.p).norm())
+            })
+            .collect();
+        RangeScan { max_range, ranges }
+    }
+
+    #[test]
+    fn optimize_test() {
+        // a 1m square, the odometry drifts to the left
+        let mut graph = PoseGraph::default();
+        let mut truth = Position::default();
+        let mut odometry = Position::default();
+        graph.add_node(odometry, empty_scan());
+        for i in 1..=40 {
+            let step = if i % 10 == 0 {
+                Position {
+                    p: Point { x: 0., y: 0.1 },
+                    a: -PI / 2.,
+                }
+            } else {
+                Position {
+                    p: Point { x: 0., y: 0.1 },
+                    a: 0.,
+                }
+            };
+            truth = truth.compose(step);
+            odometry = odometry.compose(Position {
+                p: step.p,
+                a: step.a + 0.02,
+            });
+            graph.add_node(odometry, empty_scan());
+        }
+        assert!((odometry.p - truth.p).norm() > 0.2);
+
+        // back to the start
+        graph.edges.push(Edge {
+            from: 0,
+            to: 40,
+            delta: truth,
+            information: [1e6, 1e6, 1e6],
+        });
+        graph.optimize();
+        let last = graph.nodes.last().unwrap().pose;
+        assert!((last.p - truth.p).norm() < 0.01, "{:?}", last);
+        assert!(normalize_angle(last.a - truth.a).abs() < 0.01);
+        assert_eq!(graph.nodes[0].pose, Position::default());
+        assert!((graph.to_map(odometry).p - truth.p).norm() < 0.01);
+        assert!((graph.to_odometry(truth.p) - odometry.p).norm() < 0.01);
+    }
+
+    #[test]
+    fn match_scans_test() {
+        let map = PolyMap::default();
+        // in front of a corner
+        let reference = Position {
+            p: Point { x: -0.5, y: -0.5 },
+            a: 0.,
+        };
+        let truth = Position {
+            p: Point { x: -0.4, y: -0.6 },
+            a: 0.3,
+        };
+        let mut graph = PoseGraph::default();
+        graph.add_node(reference, scan(&map, reference));
+        graph.add_node(truth, scan(&map, truth));
+
+        let delta = truth.relative_to(reference);
+        let guess = delta.compose(Position {
+            p: Point { x: 0.03, y: -0.04 },
+            a: 0.05,
+        });
+        let found = match_scans(&graph.nodes[0].hits, &graph.nodes[1].hits, guess)
+            .expect("the scans should match");
+        let error = found.relative_to(delta);
+        assert!(error.p.norm() < 0.015, "{:?}", error);
+        assert!(error.a.abs() < MATCH_ANGLE_STEP, "{:?}", error);
+
+        // too far from the guess
+        let guess = delta.compose(Position {
+            p: Point { x: 0.3, y: 0. },
+            a: 0.,
+        });
+        assert_eq!(
+            match_scans(&graph.nodes[0].hits, &graph.nodes[1].hits, guess),
+            None
+        );
+    }
+}
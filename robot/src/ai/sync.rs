@@ -0,0 +1,249 @@
+//! Anti-entropy between neighbours: the map is cut in tiles, the robots periodically
+//! send the digests of their tiles, and only the tiles whose digests differ are exchanged.
+//! The tiles are joined like whole maps, so this only applies to the CRDT merge policies.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+
+use ndarray::{s, Array2};
+use serde::{Deserialize, Serialize};
+
+use crate::ai::{CellState, Provenance};
+
+/// Side of a tile, in pixels
+const TILE_SIZE: usize = 20;
+
+/// Column and row of a tile
+pub type TileId = (usize, usize);
+pub type Digest = u64;
+/// Provenance of the cells of a tile, the map updates only carry the tiles which changed
+pub type ProvenancePatch = (TileId, Array2<Provenance>);
+
+/// Number of a map update of a robot, its patches apply to the provenance of the previous
+/// update, or to a null provenance when the update is full
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct UpdateSeq {
+    pub number: usize,
+    pub full: bool,
+}
+
+/// Cells of a tile, with their provenance
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Tile {
+    pub id: TileId,
+    pub cells: Array2<CellState>,
+    pub provenance: Array2<Provenance>,
+}
+
+fn tile_ids((width, height): (usize, usize)) -> impl Iterator<Item = TileId> {
+    let columns = width.div_ceil(TILE_SIZE);
+    let rows = height.div_ceil(TILE_SIZE);
+    (0..columns).flat_map(move |x| (0..rows).map(move |y| (x, y)))
+}
+
+/// Cells covered by a tile, the tiles on the borders may be smaller
+fn bounds((width, height): (usize, usize), (x, y): TileId) -> (Range<usize>, Range<usize>) {
+    let (x, y) = (x * TILE_SIZE, y * TILE_SIZE);
+    (
+        x..(x + TILE_SIZE).min(width),
+        y..(y + TILE_SIZE).min(height),
+    )
+}
+
+/// Digest of each tile, in the order of the tile ids
+pub fn digests(map: &Array2<CellState>, provenance: &Array2<Provenance>) -> Vec<Digest> {
+    tile_ids(map.dim())
+        .map(|id| {
+            let mut hasher = DefaultHasher::new();
+            let (xs, ys) = bounds(map.dim(), id);
+            let slice = s![xs, ys];
+            for (cell, provenance) in map.slice(slice).iter().zip(provenance.slice(slice)) {
+                cell.hash(&mut hasher);
+                provenance.hash(&mut hasher);
+            }
+            hasher.finish()
+        })
+        .collect()
+}
+
+/// Tiles whose digests differ from `theirs`, all of them if the maps do not have the same size
+pub fn differing(
+    map: &Array2<CellState>,
+    provenance: &Array2<Provenance>,
+    theirs: &[Digest],
+) -> Vec<TileId> {
+    let ours = digests(map, provenance);
+    let ids = tile_ids(map.dim());
+    if ours.len() != theirs.len() {
+        return ids.collect();
+    }
+    ids.zip(ours.iter().zip(theirs))
+        .filter(|(_, (ours, theirs))| ours != theirs)
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// Copies the tiles of a map, unknown tiles are skipped
+pub fn tiles(
+    map: &Array2<CellState>,
+    provenance: &Array2<Provenance>,
+    ids: &[TileId],
+) -> Vec<Tile> {
+    let all: Vec<TileId> = tile_ids(map.dim()).collect();
+    ids.iter()
+        .filter(|id| all.contains(id))
+        .map(|&id| {
+            let (xs, ys) = bounds(map.dim(), id);
+            let slice = s![xs, ys];
+            Tile {
+                id,
+                cells: map.slice(slice).to_owned(),
+                provenance: provenance.slice(slice).to_owned(),
+            }
+        })
+        .collect()
+}
+
+/// A map of size `dim` made of the given tiles, uncharted elsewhere.
+/// Tiles which do not fit are skipped.
+pub fn assemble(dim: (usize, usize), tiles: &[Tile]) -> (Array2<CellState>, Array2<Provenance>) {
+    let mut map = Array2::<CellState>::default(dim);
+    let mut provenance = Array2::<Provenance>::default(dim);
+    for tile in tiles {
+        let (xs, ys) = bounds(dim, tile.id);
+        if xs.start >= xs.end
+            || ys.start >= ys.end
+            || tile.cells.dim() != (xs.len(), ys.len())
+            || tile.provenance.dim() != tile.cells.dim()
+        {
+            continue;
+        }
+        let slice = s![xs, ys];
+        map.slice_mut(slice).assign(&tile.cells);
+        provenance.slice_mut(slice).assign(&tile.provenance);
+    }
+    (map, provenance)
+}
+
+/// Tiles whose provenance differs between `old` and `new`, all of them if the sizes differ
+pub fn changed(old: &Array2<Provenance>, new: &Array2<Provenance>) -> Vec<TileId> {
+    let ids = tile_ids(new.dim());
+    if old.dim() != new.dim() {
+        return ids.collect();
+    }
+    ids.filter(|&id| {
+        let (xs, ys) = bounds(new.dim(), id);
+        let slice = s![xs, ys];
+        old.slice(slice) != new.slice(slice)
+    })
+    .collect()
+}
+
+/// The provenance of the given tiles, to be sent with the map
+pub fn patches(provenance: &Array2<Provenance>, ids: &[TileId]) -> Vec<ProvenancePatch> {
+    ids.iter()
+        .map(|&id| {
+            let (xs, ys) = bounds(provenance.dim(), id);
+            (id, provenance.slice(s![xs, ys]).to_owned())
+        })
+        .collect()
+}
+
+/// Copies the patches into `provenance`, the ones which do not fit are skipped
+pub fn apply(provenance: &mut Array2<Provenance>, patches: &[ProvenancePatch]) {
+    for (id, patch) in patches {
+        let (xs, ys) = bounds(provenance.dim(), *id);
+        if xs.start >= xs.end || ys.start >= ys.end || patch.dim() != (xs.len(), ys.len()) {
+            continue;
+        }
+        provenance.slice_mut(s![xs, ys]).assign(patch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::crdt;
+    use crate::ai::CellState::*;
+
+    const SIZE: (usize, usize) = (50, 70);
+
+    type Replica = (Array2<CellState>, Array2<Provenance>);
+
+    fn observe(replica: &mut Replica, xy: (usize, usize), state: CellState, stamp: u64) {
+        replica.0[xy] = state;
+        replica.1[xy] = Provenance { stamp, robot: 1 };
+    }
+
+    fn join(replica: &mut Replica, tiles: &[Tile]) {
+        let (update, provenance) = assemble(SIZE, tiles);
+        crdt::join(
+            crdt::Order::Latest,
+            (&mut replica.0, &mut replica.1),
+            (&update, &provenance),
+        );
+    }
+
+    #[test]
+    fn tiles_test() {
+        assert_eq!(tile_ids(SIZE).count(), 3 * 4);
+        let mut replica: Replica = (Array2::default(SIZE), Array2::default(SIZE));
+        observe(&mut replica, (45, 65), Blocked, 1);
+        let border = tiles(&replica.0, &replica.1, &[(2, 3), (3, 0)]);
+        assert_eq!(border.len(), 1);
+        assert_eq!(border[0].cells.dim(), (10, 10));
+        assert_eq!(border[0].cells[(5, 5)], Blocked);
+
+        let (map, provenance) = assemble(SIZE, &border);
+        assert_eq!(map, replica.0);
+        assert_eq!(provenance, replica.1);
+    }
+
+    #[test]
+    fn anti_entropy_test() {
+        let mut a: Replica = (Array2::default(SIZE), Array2::default(SIZE));
+        let mut b = a.clone();
+        assert!(differing(&a.0, &a.1, &digests(&b.0, &b.1)).is_empty());
+
+        observe(&mut a, (1, 1), SeenFree, 1);
+        observe(&mut a, (2, 1), Blocked, 1);
+        observe(&mut b, (30, 50), SeenFree, 2);
+        // a newer observation of the same cell
+        observe(&mut b, (2, 1), SeenFree, 3);
+
+        // b receives the digests of a, sends its differing tiles and asks for a's
+        let wanted = differing(&b.0, &b.1, &digests(&a.0, &a.1));
+        assert_eq!(wanted, vec![(0, 0), (1, 2)]);
+        let pushed = tiles(&b.0, &b.1, &wanted);
+        let pulled = tiles(&a.0, &a.1, &wanted);
+        join(&mut a, &pushed);
+        join(&mut b, &pulled);
+
+        assert!(differing(&a.0, &a.1, &digests(&b.0, &b.1)).is_empty());
+        assert_eq!(a, b);
+        assert_eq!(a.0[(2, 1)], SeenFree);
+        assert_eq!(a.0[(30, 50)], SeenFree);
+    }
+
+    #[test]
+    fn patches_test() {
+        let mut replica: Replica = (Array2::default(SIZE), Array2::default(SIZE));
+        let shared = replica.1.clone();
+        observe(&mut replica, (1, 1), SeenFree, 1);
+        observe(&mut replica, (45, 65), Blocked, 2);
+        let ids = changed(&shared, &replica.1);
+        assert_eq!(ids, vec![(0, 0), (2, 3)]);
+
+        let sent = patches(&replica.1, &ids);
+        assert_eq!(sent[1].1.dim(), (10, 10));
+        let mut received = shared;
+        apply(&mut received, &sent);
+        assert_eq!(received, replica.1);
+        assert!(changed(&received, &replica.1).is_empty());
+
+        // out of the map
+        apply(&mut received, &[((3, 0), Array2::default((20, 20)))]);
+        assert_eq!(received, replica.1);
+    }
+}
@@ -1,15 +1,25 @@
 use std::collections::HashSet;
-use std::fs::{File, OpenOptions};
+use std::fs::OpenOptions;
 use std::io::Write;
 use std::path::PathBuf;
 use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
 use log;
 
-use crate::ai::AI;
-use crate::map::{Point, Position};
-use crate::messages::{Msg, MsgContent::*, MsgId};
-use crate::robot::{self, Robot};
+use crate::ai::{CellState, Digest, MergePolicy, Tile, AI};
+use crate::map::Position;
+use crate::messages::{
+    Msg,
+    MsgContent::{self, *},
+    MsgId,
+};
+use crate::metrics::{GroundTruth, MapQuality};
+use crate::mission::{Command, Mission};
+use crate::raft::{self, Raft};
+use crate::robot::{self, RobotBackend};
+use ndarray::Array2;
 
 pub type AppId = u32;
 
@@ -20,8 +30,24 @@ pub enum Event {
     RobotMessage(robot::Event),
     /// Message from another app
     DistantInput(String),
+    /// A timer of the app expired
+    Timer(Timer),
 }
 
+/// Periodic tasks of the app
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Timer {
+    /// Send the digests of our map to the neighbours
+    AntiEntropy,
+    /// Send heartbeats, or start an election without any
+    Raft,
+    /// Log the quality of our map
+    Metrics,
+}
+
+/// Period of the Raft timer
+const RAFT_TICK: Duration = Duration::from_millis(200);
+
 use Event::*;
 
 /// Holds the state of the application
@@ -31,53 +57,186 @@ pub struct App {
     // Contains the intelligence
     ai: AI,
     // Robot interface
-    robot: Robot,
-    // Output file
-    output: File,
+    robot: Box<dyn RobotBackend>,
+    // Output file, or buffer when the swarm is simulated in-process
+    output: Box<dyn Write>,
     // Tx to send asynchronous message to future-self
     self_tx: mpsc::Sender<Event>,
-    // Event handler
-    events: Events,
-    // Stores the sent messages ids to not rebroadcast them again
-    sent_messages_ids: HashSet<MsgId>,
+    // Event handler, the events are given to `handle` when the swarm is simulated in-process
+    events: Option<Events>,
+    // Stores the senders and ids of the sent messages to not rebroadcast them again
+    sent_messages_ids: HashSet<(AppId, MsgId)>,
+    // Id of our next message, numbered per sender for reproducible simulations
+    next_msg_id: MsgId,
     counter: u32,
+    // Period of the anti-entropy, disabled if None
+    sync_period: Option<Duration>,
+    // Elects a leader among the robots and agrees on the mission commands
+    raft: Raft<Command>,
+    mission: Mission,
+    // World of the simulation, and period of the log of the quality of our map against it
+    ground_truth: Option<(GroundTruth, Duration)>,
 }
 
 impl App {
-    pub fn new(id: AppId, output: PathBuf, input: PathBuf) -> Self {
+    pub fn new(
+        id: AppId,
+        output: PathBuf,
+        input: PathBuf,
+        robot: Box<dyn RobotBackend>,
+        robot_rx: mpsc::Receiver<robot::Event>,
+    ) -> Self {
         let (self_tx, self_rx) = mpsc::channel();
-        let (mut robot, robot_rx) = Robot::new();
-        robot.load_map(&"map.json".into());
         let events = Events::new(input, robot_rx, self_rx);
         let output = OpenOptions::new()
             .write(true)
             .append(true)
             .open(output)
             .expect("failed to open output file");
+        let mut app = Self::in_process(id, robot, Box::new(output));
+        app.self_tx = self_tx;
+        app.events = Some(events);
+        app
+    }
 
+    /// An app whose events are given to `handle`, and whose messages are written on `output`,
+    /// without timers nor debug images, for simulations of the swarm
+    pub fn in_process(id: AppId, robot: Box<dyn RobotBackend>, output: Box<dyn Write>) -> Self {
+        let mut ai = AI::new(id);
+        ai.set_debug_images(false);
         App {
             id,
-            ai: AI::new(id),
+            ai,
             robot,
             output,
-            self_tx,
-            events,
+            self_tx: mpsc::channel().0,
+            events: None,
             sent_messages_ids: HashSet::new(),
+            next_msg_id: 0,
             counter: 0,
+            sync_period: None,
+            raft: Raft::new(id),
+            mission: Mission::default(),
+            ground_truth: None,
         }
     }
 
-    pub fn init(&mut self, (x, y): (f32, f32)) {
-        let pos = Position {
-            p: Point { x, y },
-            a: 0.,
+    /// Period of the anti-entropy in seconds, 0 disables it
+    pub fn set_sync_period(&mut self, period: f32) {
+        self.sync_period = if period > 0. {
+            Some(Duration::from_secs_f32(period))
+        } else {
+            None
         };
+    }
+
+    /// Number of robots in the swarm for the leader election and the consensus,
+    /// the majority is computed among the robots heard of recently if None
+    pub fn set_swarm_size(&mut self, size: Option<usize>) {
+        self.raft.set_cluster_size(size);
+    }
+
+    /// The Raft term and vote are saved in `path`, and restored from it after a restart
+    pub fn set_raft_state(&mut self, path: PathBuf) -> std::io::Result<()> {
+        self.raft.persist_to(path)
+    }
+
+    /// Time of the simulation running the app on a virtual clock, stamping the map
+    pub fn set_time(&mut self, time: Duration) {
+        self.ai.set_time(time);
+    }
+
+    /// The current leader of the swarm, if any is known
+    #[allow(dead_code)]
+    pub fn leader(&self) -> Option<AppId> {
+        self.raft.leader()
+    }
+
+    /// The mission, as agreed on by the swarm
+    #[allow(dead_code)]
+    pub fn mission(&self) -> Mission {
+        self.mission
+    }
+
+    /// Proposes a mission command to the swarm, it is applied once committed
+    pub fn propose(&mut self, command: Command) {
+        log::info!("proposing {}", command);
+        let messages = self.raft.propose(command);
+        self.send_raft(messages);
+    }
+
+    /// Sends the Raft messages, and applies the newly committed commands
+    fn send_raft(&mut self, messages: Vec<raft::Message<Command>>) {
+        for message in messages {
+            let msg = self.message(self.ai.position(), Raft(message));
+            self.send_to_network(msg);
+        }
+        let committed = self.raft.take_committed();
+        for &command in committed.iter() {
+            log::info!("applying {}", command);
+            self.mission.apply(command);
+        }
+        if !committed.is_empty() {
+            self.ai.set_mission(self.mission, &mut *self.robot);
+        }
+    }
+
+    /// Sends `timer` to our future self every `period`
+    fn start_timer(&self, timer: Timer, period: Duration) {
+        let self_tx = self.self_tx.clone();
+        thread::spawn(move || loop {
+            thread::sleep(period);
+            if self_tx.send(Event::Timer(timer)).is_err() {
+                break;
+            }
+        });
+    }
+
+    /// The robots do not share the same frame, their maps must be registered
+    pub fn set_map_alignment(&mut self, align_maps: bool) {
+        self.ai.set_map_alignment(align_maps);
+    }
+
+    /// How the maps of the other robots are merged, `trust` weights their votes
+    pub fn set_merge_policy(&mut self, policy: MergePolicy, trust: &[(AppId, f32)]) {
+        self.ai.set_merge_policy(policy);
+        for &(robot, weight) in trust {
+            self.ai.set_trust(robot, weight);
+        }
+    }
+
+    /// Logs the quality of our map against the simulated world every `period`
+    pub fn set_ground_truth(&mut self, truth: GroundTruth, period: Duration) {
+        self.ground_truth = Some((truth, period));
+    }
+
+    /// Quality of our map, if the world is known
+    pub fn quality(&self) -> Option<MapQuality> {
+        self.ground_truth
+            .as_ref()
+            .map(|(truth, _)| truth.evaluate(self.map()))
+    }
+
+    pub fn init(&mut self, pos: Position) {
         self.robot.init(pos);
         self.ai.update_robot_position(self.id, pos);
+        self.ai.set_home(pos.p);
+    }
+
+    /// A message from us, with the next of our ids
+    fn message(&mut self, pos: Position, content: MsgContent) -> Msg {
+        let id = self.next_msg_id;
+        self.next_msg_id = id.wrapping_add(1);
+        Msg {
+            id,
+            sender_id: self.id,
+            pos,
+            content,
+        }
     }
 
     fn send_to_network(&mut self, msg: Msg) {
-        self.sent_messages_ids.insert(msg.id);
+        self.sent_messages_ids.insert((msg.sender_id, msg.id));
 
         let msg_str = msg
             .serialize()
@@ -93,63 +252,185 @@ impl App {
         }
     }
 
-    pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Anti-entropy with a robot having sent the digests of its map:
+    /// our differing tiles are pushed to it, and its tiles are pulled
+    fn sync_with(&mut self, robot: AppId, digests: &[Digest]) {
+        if !self.ai.can_sync() {
+            return;
+        }
+        let ids = self.ai.differing_tiles(digests);
+        if ids.is_empty() {
+            return;
+        }
+        log::debug!("{} tiles differ from {}", ids.len(), robot);
+        let tiles = self.ai.tiles(&ids);
+        self.send_tiles(robot, tiles);
+        let msg = self.message(self.ai.position(), TileRequest(robot, ids));
+        self.send_to_network(msg);
+    }
+
+    fn send_tiles(&mut self, robot: AppId, tiles: Vec<Tile>) {
+        if !tiles.is_empty() {
+            let msg = self.message(self.ai.position(), Tiles(robot, tiles));
+            self.send_to_network(msg);
+        }
+    }
+
+    /// Starts the robot and greets the other robots
+    pub fn start(&mut self) {
         self.robot.start();
-        let greeting_message = Msg::new(
-            self.id,
-            self.robot.pos,
+        let greeting_message = self.message(
+            self.robot.pos(),
             Public(format!("Hello there, I am {}!", self.id)),
         );
         self.send_to_network(greeting_message);
 
-        self.ai.update(&mut self.robot);
+        self.ai.update(&mut *self.robot);
+    }
 
+    /// The timers of the app, and their periods
+    pub fn timers(&self) -> Vec<(Timer, Duration)> {
+        let mut timers = vec![(Timer::Raft, RAFT_TICK)];
+        if let Some(period) = self.sync_period {
+            timers.push((Timer::AntiEntropy, period));
+        }
+        if let Some((_, period)) = self.ground_truth {
+            timers.push((Timer::Metrics, period));
+        }
+        timers
+    }
+
+    /// Our map, as seen and merged with the maps of the others
+    pub fn map(&self) -> &Array2<CellState> {
+        &self.ai.map_seen
+    }
+
+    pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.start();
+        for (timer, period) in self.timers() {
+            self.start_timer(timer, period);
+        }
         loop {
-            // Handle events
-            match self.events.next()? {
-                RobotMessage(msg) => {
-                    log::info!("RobotMessage {:?}", msg);
-
-                    match msg {
-                        robot::Event::Reached(p) => {
-                            self.ai.update_robot_position(self.id, p);
-                            self.ai.update(&mut self.robot);
-                        }
-                        robot::Event::Collision(p) => {
-                            self.ai.update_robot_position(self.id, p);
-                            self.ai.notify_collision(&mut self.robot, p.p);
-                        }
-                        _ => break,
-                    }
+            let event = match &self.events {
+                Some(events) => events.next()?,
+                None => panic!("the app has no input, its events must be handled in-process"),
+            };
+            self.handle(event);
+        }
+    }
 
-                    self.counter += 1;
-                    if self.counter > 10 {
-                        self.counter = 0;
-                        self.send_to_network(Msg::new(
-                            self.id,
-                            self.robot.pos,
-                            MapUpdate(self.ai.map_seen.clone()),
-                        ));
+    pub fn handle(&mut self, event: Event) {
+        match event {
+            RobotMessage(msg) => {
+                log::info!("RobotMessage {:?}", msg);
+
+                match msg {
+                    robot::Event::Reached(p) => {
+                        self.ai.update_robot_position(self.id, p);
+                        self.ai.update(&mut *self.robot);
+                    }
+                    robot::Event::Collision(p) => {
+                        // to log what triggered the collision
+                        self.robot.lacc();
+                        self.ai.update_robot_position(self.id, p);
+                        self.ai.notify_collision(&mut *self.robot, p.p);
+                    }
+                    robot::Event::Curr(p) => {
+                        // intermediate position, the robot is still moving
+                        self.ai.update_robot_position(self.id, p);
+                        return;
+                    }
+                    robot::Event::Scan(scan) => {
+                        self.ai.notify_scan(&scan);
+                        return;
+                    }
+                    robot::Event::Bump(angles) => {
+                        self.ai.notify_bump(&angles);
+                        return;
                     }
+                    robot::Event::Moved(_)
+                    | robot::Event::Turned(_)
+                    | robot::Event::Lacc(_) => return,
                 }
 
-                DistantInput(m) => {
-                    if let Ok(msg) = Msg::from_str(&m) {
-                        // do something to the decoded message
-                        if !self.sent_messages_ids.contains(&msg.id) {
-                            self.ai.update_robot_position(msg.sender_id, msg.pos);
-                            // log::info!("received, from: {} : {:?}", msg.sender_id, msg.content);
+                self.counter += 1;
+                if self.counter > 10 {
+                    self.counter = 0;
+                    let (map, seq, patches) = self.ai.share_map();
+                    let msg = self.message(self.ai.position(), MapUpdate(map, seq, patches));
+                    self.send_to_network(msg);
+                }
+            }
+
+            DistantInput(m) => {
+                if let Ok(msg) = Msg::from_str(&m) {
+                    // do something to the decoded message
+                    let fresh = !self.sent_messages_ids.contains(&(msg.sender_id, msg.id));
+                    if fresh {
+                        self.raft.observe(msg.sender_id);
+                        self.ai.update_robot_position(msg.sender_id, msg.pos);
+                        // log::info!("received, from: {} : {:?}", msg.sender_id, msg.content);
+                        if msg.content.is_relayed() {
                             self.send_to_network(msg.clone());
                         }
-                        if let MapUpdate(update) = msg.content {
-                            self.ai.merge_maps(update);
+                    }
+                    match msg.content {
+                        MapUpdate(update, seq, patches) => {
+                            let stale = self.ai.merge_maps(msg.sender_id, update, seq, &patches);
+                            if stale {
+                                log::debug!("missed map updates of {}", msg.sender_id);
+                                let request =
+                                    self.message(self.ai.position(), UpdateRequest(msg.sender_id));
+                                self.send_to_network(request);
+                            }
+                        }
+                        UpdateRequest(to) if to == self.id => self.ai.request_full_update(),
+                        Digests(digests) => self.sync_with(msg.sender_id, &digests),
+                        TileRequest(to, ids) if to == self.id => {
+                            let tiles = self.ai.tiles(&ids);
+                            self.send_tiles(msg.sender_id, tiles);
                         }
-                    } else {
-                        log::error!("could not decode {:?}", m);
+                        Raft(message) if fresh => {
+                            let replies = self.raft.handle(msg.sender_id, message);
+                            self.send_raft(replies);
+                        }
+                        Tiles(to, tiles) if to == self.id => {
+                            log::debug!("{} tiles from {}", tiles.len(), msg.sender_id);
+                            self.ai.merge_tiles(msg.sender_id, &tiles);
+                        }
+                        _ => (),
                     }
+                } else if let Ok(command) = m.parse() {
+                    // from an operator
+                    self.propose(command);
+                } else {
+                    log::error!("could not decode {:?}", m);
+                }
+            }
+
+            Timer(Timer::Raft) => {
+                let messages = self.raft.tick();
+                self.send_raft(messages);
+            }
+
+            Timer(Timer::AntiEntropy) => {
+                if self.ai.can_sync() {
+                    let digests = self.ai.digests();
+                    let msg = self.message(self.ai.position(), Digests(digests));
+                    self.send_to_network(msg);
+                }
+            }
+
+            Timer(Timer::Metrics) => {
+                if let Some(quality) = self.quality() {
+                    log::info!(
+                        "map quality: explored {:.1}%, false free {:.2}%, false blocked {:.2}%",
+                        quality.explored * 100.,
+                        quality.false_free * 100.,
+                        quality.false_blocked * 100.
+                    );
                 }
             }
         }
-        Ok(())
     }
 }
@@ -0,0 +1,249 @@
+//! Runs a scenario many times in-process, across seeds and grids of parameters, and reports
+//! what each run measured: coverage over time, quality of the maps, distance travelled,
+//! collisions, messages, bytes and time to completion.
+//! The parameters are the values of the scenario given by their dotted paths, like
+//! `--grid network.radio.range=0.5,1,2 --grid strategy.merge_policy=lww,voting`,
+//! and the runs are their cartesian product, each run with all the seeds.
+//! Writes a summary with a row per run as `<output>.csv`, and all the metrics as `<output>.json`,
+//! with `--svg` the trajectories of the robots on the map of each run as `<output>-<run>.svg`.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::Value;
+use structopt::StructOpt;
+
+use robot::map::PolyMap;
+use robot::scenario::Scenario;
+use robot::world::{Metrics, World};
+
+/// Stack of the threads of the runs, the one of a main thread, on which the apps usually run
+const STACK_SIZE: usize = 8 << 20;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "experiment")]
+pub struct Opt {
+    /// Scenario, as JSON
+    #[structopt(parse(from_os_str))]
+    scenario: PathBuf,
+
+    /// Number of runs of each combination of parameters, with consecutive seeds
+    #[structopt(short = "s", long = "seeds", default_value = "1")]
+    seeds: u64,
+
+    /// First seed, the one of the scenario by default
+    #[structopt(long = "first-seed")]
+    first_seed: Option<u64>,
+
+    /// Values of a parameter of the scenario, as path=value,value...
+    /// the values being JSON, or strings otherwise
+    #[structopt(short = "g", long = "grid", parse(try_from_str = "parse_grid"))]
+    grid: Vec<(String, Vec<Value>)>,
+
+    /// Time limit of the runs, in seconds, instead of the one of the scenario
+    #[structopt(short = "t", long = "time-limit")]
+    time_limit: Option<f32>,
+
+    /// Period of the coverage samples, in seconds
+    #[structopt(long = "sample-period", default_value = "1")]
+    sample_period: f32,
+
+    /// Number of runs at the same time
+    #[structopt(short = "j", long = "jobs", default_value = "1")]
+    jobs: usize,
+
+    /// Prefix of the result files
+    #[structopt(short = "o", long = "output", default_value = "experiment")]
+    output: String,
+
+    /// Also draws the trajectories of the robots of each run on the map, as `<output>-<run>.svg`
+    #[structopt(long = "svg")]
+    svg: bool,
+}
+
+fn parse_grid(s: &str) -> Result<(String, Vec<Value>), String> {
+    let mut parts = s.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(path), Some(values)) if !path.is_empty() => {
+            let values = values
+                .split(',')
+                .map(|v| serde_json::from_str(v).unwrap_or_else(|_| Value::from(v)))
+                .collect();
+            Ok((path.to_string(), values))
+        }
+        _ => Err(format!("expected path=value,value..., got {}", s)),
+    }
+}
+
+/// All the combinations of the values of the parameters
+fn combinations(grid: &[(String, Vec<Value>)]) -> Vec<Vec<(String, Value)>> {
+    let mut combinations = vec![vec![]];
+    for (path, values) in grid.iter() {
+        combinations = combinations
+            .iter()
+            .flat_map(|params| {
+                values.iter().map(move |value| {
+                    let mut params = params.clone();
+                    params.push((path.clone(), value.clone()));
+                    params
+                })
+            })
+            .collect();
+    }
+    combinations
+}
+
+#[derive(Serialize, Debug)]
+struct Run {
+    params: Vec<(String, Value)>,
+    seed: u64,
+    metrics: Metrics,
+}
+
+fn write_csv(path: &str, grid: &[(String, Vec<Value>)], runs: &[Run]) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for (param, _) in grid.iter() {
+        write!(file, "{},", param)?;
+    }
+    writeln!(
+        file,
+        "seed,duration,coverage,false_free,false_blocked,consistency,\
+         distance,collisions,messages,bytes,completion_time,success"
+    )?;
+    for run in runs.iter() {
+        for (_, value) in run.params.iter() {
+            match value {
+                Value::String(s) => write!(file, "{},", s)?,
+                value => write!(file, "{},", value)?,
+            }
+        }
+        let m = &run.metrics;
+        let completion = m.completion_time.map(|t| t.to_string()).unwrap_or_default();
+        writeln!(
+            file,
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            run.seed,
+            m.duration,
+            m.coverage,
+            m.quality.false_free,
+            m.quality.false_blocked,
+            m.quality.consistency,
+            m.distance,
+            m.collisions,
+            m.messages,
+            m.bytes,
+            completion,
+            m.success
+        )?;
+    }
+    Ok(())
+}
+
+fn write_svg(path: &str, map: &PathBuf, run: &Run) {
+    let map = PolyMap::from_file(map).unwrap_or_else(|e| panic!("could not load {:?}, {}", map, e));
+    let trajectories = run
+        .metrics
+        .trajectories
+        .iter()
+        .map(|(id, points)| (format!("robot {}", id), points.as_slice()));
+    fs::write(path, map.to_svg(trajectories))
+        .unwrap_or_else(|e| panic!("could not write {}, {}", path, e));
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    env_logger::init();
+
+    let first_seed = opt.first_seed.unwrap_or_else(|| {
+        Scenario::load(&opt.scenario)
+            .unwrap_or_else(|e| panic!("could not load the scenario {:?}, {}", opt.scenario, e))
+            .seed
+    });
+    let mut todo = Vec::new();
+    let mut checked_maps = HashSet::new();
+    for params in combinations(&opt.grid) {
+        for seed in first_seed..first_seed + opt.seeds {
+            let mut overrides = params.clone();
+            overrides.push(("seed".to_string(), Value::from(seed)));
+            if let Some(time_limit) = opt.time_limit {
+                overrides.push(("time_limit".to_string(), Value::from(time_limit)));
+            }
+            // the scenario is checked before starting the runs
+            let scenario = Scenario::load_with(&opt.scenario, &overrides)
+                .unwrap_or_else(|e| panic!("invalid scenario for {:?}, {}", params, e));
+            if checked_maps.insert(scenario.map.clone()) {
+                PolyMap::from_file(&scenario.map)
+                    .unwrap_or_else(|e| panic!("could not load the map {:?}, {}", scenario.map, e));
+            }
+            todo.push((params.clone(), seed, scenario));
+        }
+    }
+
+    // the worlds are not shared between threads, each run builds its own
+    let total = todo.len();
+    let todo = Arc::new(todo);
+    let next = Arc::new(AtomicUsize::new(0));
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let sample_period = Duration::from_secs_f32(opt.sample_period);
+    let workers: Vec<_> = (0..opt.jobs.max(1))
+        .map(|_| {
+            let (todo, next, results) = (todo.clone(), next.clone(), results.clone());
+            let worker = thread::Builder::new().stack_size(STACK_SIZE);
+            worker
+                .spawn(move || loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let (params, seed, scenario) = match todo.get(i) {
+                        Some(run) => run,
+                        None => return,
+                    };
+                    let world = World::new(scenario)
+                        .unwrap_or_else(|e| panic!("could not create the world, {}", e));
+                    let metrics = world.run(sample_period);
+                    log::info!(
+                        "run {}/{} {:?} seed {}: coverage {:.2} in {:.0}s, {} collisions",
+                        i + 1,
+                        total,
+                        params,
+                        seed,
+                        metrics.coverage,
+                        metrics.duration,
+                        metrics.collisions
+                    );
+                    let run = Run {
+                        params: params.clone(),
+                        seed: *seed,
+                        metrics,
+                    };
+                    results.lock().unwrap().push((i, run));
+                })
+                .expect("could not start a run")
+        })
+        .collect();
+    for worker in workers {
+        worker.join().expect("a run failed");
+    }
+
+    let mut results = results.lock().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    let runs: Vec<Run> = results.drain(..).map(|(_, run)| run).collect();
+    let csv = format!("{}.csv", opt.output);
+    write_csv(&csv, &opt.grid, &runs).unwrap_or_else(|e| panic!("could not write {}, {}", csv, e));
+    let json = format!("{}.json", opt.output);
+    let file = File::create(&json).unwrap_or_else(|e| panic!("could not create {}, {}", json, e));
+    serde_json::to_writer_pretty(BufWriter::new(file), &runs)
+        .unwrap_or_else(|e| panic!("could not write {}, {}", json, e));
+    if opt.svg {
+        for (i, (run, (_, _, scenario))) in runs.iter().zip(todo.iter()).enumerate() {
+            write_svg(&format!("{}-{}.svg", opt.output, i), &scenario.map, run);
+        }
+    }
+    let successes = runs.iter().filter(|run| run.metrics.success).count();
+    log::info!("{} runs, {} successful", runs.len(), successes);
+}
@@ -0,0 +1,139 @@
+//! Tools for the maps of the simulated worlds.
+//! `map check` reports the defects of map files, failing if any of them cannot be loaded.
+//! `map import` converts a drawing to a map, and `map export` draws a map and the trajectories
+//! of robots, both as SVG.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use structopt::StructOpt;
+
+use robot::map::{MapError, Point, PolyMap};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "map")]
+pub enum Opt {
+    /// Checks that maps can be loaded, printing their errors and warnings
+    #[structopt(name = "check")]
+    Check {
+        /// Map files
+        #[structopt(parse(from_os_str), required = true)]
+        maps: Vec<PathBuf>,
+    },
+
+    /// Converts the paths, polylines, polygons, rects and lines of an SVG drawing to a map
+    #[structopt(name = "import")]
+    Import {
+        #[structopt(parse(from_os_str))]
+        svg: PathBuf,
+
+        /// Map file, as JSON
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: PathBuf,
+
+        /// Meters per user unit of the drawing, given by its width and view box if not set
+        #[structopt(long = "scale")]
+        scale: Option<f32>,
+    },
+
+    /// Draws a map as SVG, in millimeters
+    #[structopt(name = "export")]
+    Export {
+        #[structopt(parse(from_os_str))]
+        map: PathBuf,
+
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: PathBuf,
+
+        /// Trajectories to draw on the map, as JSON: the points of each robot by its name,
+        /// like the trajectories of the metrics of `experiment`
+        #[structopt(short = "t", long = "trajectories", parse(from_os_str))]
+        trajectories: Option<PathBuf>,
+    },
+}
+
+/// Prints the defects of the map, returns whether it can be loaded
+fn check(path: &PathBuf) -> bool {
+    match PolyMap::from_file_unchecked(path) {
+        Ok(map) => report(path, &map),
+        Err(e) => {
+            println!("{}: error: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+fn report(path: &Path, map: &PolyMap) -> bool {
+    let defects = map.check();
+    for defect in defects.iter() {
+        let severity = if defect.is_error() {
+            "error"
+        } else {
+            "warning"
+        };
+        println!("{}: {}: {}", path.display(), severity, defect);
+    }
+    let valid = !defects.iter().any(|d| d.is_error());
+    if valid {
+        println!(
+            "{}: ok, {} polygons, {} segments",
+            path.display(),
+            map.polygons().len(),
+            map.segments().count()
+        );
+    }
+    valid
+}
+
+fn main() {
+    match Opt::from_args() {
+        Opt::Check { maps } => {
+            // all the maps are checked, even after an invalid one
+            let valid: Vec<bool> = maps.iter().map(check).collect();
+            if valid.contains(&false) {
+                process::exit(1);
+            }
+        }
+        Opt::Import { svg, output, scale } => {
+            let read = || -> Result<PolyMap, MapError> {
+                Ok(PolyMap::from_svg(&fs::read_to_string(&svg)?, scale)?)
+            };
+            let map = read().unwrap_or_else(|e| {
+                println!("{}: error: {}", svg.display(), e);
+                process::exit(1);
+            });
+            if !report(&svg, &map) {
+                process::exit(1);
+            }
+            map.save_to_file(&output)
+                .unwrap_or_else(|e| panic!("could not write {:?}, {}", output, e));
+        }
+        Opt::Export {
+            map,
+            output,
+            trajectories,
+        } => {
+            let map = PolyMap::from_file(&map)
+                .unwrap_or_else(|e| panic!("could not load the map {:?}, {}", map, e));
+            let trajectories: BTreeMap<String, Vec<Point>> = match trajectories {
+                Some(path) => File::open(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|file| {
+                        serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string())
+                    })
+                    .unwrap_or_else(|e| {
+                        panic!("could not load the trajectories {:?}, {}", path, e)
+                    }),
+                None => BTreeMap::new(),
+            };
+            let trajectories = trajectories
+                .iter()
+                .map(|(name, points)| (name.clone(), points.as_slice()));
+            fs::write(&output, map.to_svg(trajectories))
+                .unwrap_or_else(|e| panic!("could not write {:?}, {}", output, e));
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Relays the messages of robot processes through an emulated network.
+//! Each robot writes its messages on its own output pipe, and the relay delivers them
+//! on the input pipes of the other robots, following the network emulation parameters.
+//! With a radio in the parameters, only the robots in range of the sender receive its messages,
+//! the positions of the robots being those of their last messages.
+//! It stops after `-t` seconds, on Ctrl-C, or once all the outputs are read if none is a pipe.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use structopt::StructOpt;
+
+use robot::app::AppId;
+use robot::map::{Point, PolyMap};
+use robot::messages::Header;
+use robot::netem::{Emulator, NetemConfig};
+use robot::pipes::listen;
+use robot::radio::Radio;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "relay")]
+pub struct Opt {
+    /// A robot, as id,output,input: its messages are read from its output pipe,
+    /// and the messages of the others are written on its input pipe
+    #[structopt(short = "r", long = "robot", parse(try_from_str = "parse_robot"))]
+    robots: Vec<(AppId, PathBuf, PathBuf)>,
+
+    /// Network emulation parameters, as JSON, a perfect network if not given
+    #[structopt(short = "c", long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Map obstructing the radio, when the configuration has one
+    #[structopt(
+        short = "m",
+        long = "map",
+        default_value = "map.json",
+        parse(from_os_str)
+    )]
+    map: PathBuf,
+
+    /// Seed of the emulated losses, delays and duplications
+    #[structopt(long = "seed", default_value = "0")]
+    seed: u64,
+
+    /// Stops after this many seconds, when interrupted otherwise
+    #[structopt(short = "t", long = "duration")]
+    duration: Option<f32>,
+}
+
+fn parse_robot(s: &str) -> Result<(AppId, PathBuf, PathBuf), String> {
+    let parts: Vec<&str> = s.splitn(3, ',').collect();
+    match parts.as_slice() {
+        [id, output, input] => {
+            let id = id
+                .parse()
+                .map_err(|e| format!("invalid id {}, {}", id, e))?;
+            Ok((id, output.into(), input.into()))
+        }
+        _ => Err(format!("expected id,output,input, got {}", s)),
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    env_logger::init();
+
+    let config = match &opt.config {
+        Some(path) => NetemConfig::load(path)
+            .unwrap_or_else(|e| panic!("could not load the configuration {:?}, {}", path, e)),
+        None => NetemConfig::default(),
+    };
+    let mut radio = config.radio.map(|params| {
+        let map = PolyMap::from_file(&opt.map)
+            .unwrap_or_else(|e| panic!("could not load the map {:?}, {}", opt.map, e));
+        Radio::new(params, map, opt.seed)
+    });
+    let mut positions: HashMap<AppId, Point> = HashMap::new();
+    let mut emulator = Emulator::new(config, opt.seed);
+    let ids: Vec<AppId> = opt.robots.iter().map(|&(id, _, _)| id).collect();
+
+    let (tx, rx) = mpsc::channel();
+    for (id, output, _) in opt.robots.iter() {
+        listen(*id, output.clone(), tx.clone());
+    }
+    drop(tx);
+    let mut inputs: HashMap<AppId, File> = opt
+        .robots
+        .iter()
+        .map(|(id, _, input)| {
+            let file = OpenOptions::new()
+                .append(true)
+                .open(input)
+                .unwrap_or_else(|e| panic!("could not open the input of {}, {}", id, e));
+            (*id, file)
+        })
+        .collect();
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    {
+        let interrupted = interrupted.clone();
+        ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst))
+            .expect("could not handle Ctrl-C");
+    }
+
+    let start = Instant::now();
+    let now = || start.elapsed().as_secs_f64();
+    let (mut received, mut delivered) = (0, 0);
+    loop {
+        let wait = match emulator.next_delivery() {
+            Some(at) => Duration::from_secs_f64((at - now()).clamp(0., 0.1)),
+            None => Duration::from_millis(100),
+        };
+        let message = rx.recv_timeout(wait);
+        match message {
+            Ok((from, line)) => {
+                received += 1;
+                // the robots relay the messages of the others with their original position
+                match Header::from_json(&line) {
+                    Ok(header) if header.sender_id == from => {
+                        positions.insert(from, header.pos.p);
+                    }
+                    Ok(_) => (),
+                    Err(e) => log::warn!("could not read the header of {}, {}", from, e),
+                }
+                let receivers = match radio.as_mut() {
+                    Some(radio) => radio.receivers(from, &positions),
+                    None => ids.clone(),
+                };
+                let size = line.len() + 1;
+                emulator.broadcast(now(), from, &receivers, line, size);
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        for delivery in emulator.poll(now()) {
+            let input = inputs.get_mut(&delivery.to).expect("unknown robot");
+            if let Err(e) = writeln!(input, "{}", delivery.payload) {
+                log::error!("could not write to {}, {}", delivery.to, e);
+            } else {
+                delivered += 1;
+                log::debug!("{} -> {} at {:.3}", delivery.from, delivery.to, delivery.at);
+            }
+        }
+        log::trace!("received {}, delivered {}", received, delivered);
+
+        let timeout = opt
+            .duration
+            .is_some_and(|d| start.elapsed().as_secs_f32() >= d);
+        if timeout || interrupted.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+    log::info!("received {}, delivered {}", received, delivered);
+}
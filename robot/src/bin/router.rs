@@ -0,0 +1,295 @@
+//! Headless router forwarding the messages of the robots to the robots in range,
+//! replacing the router of the Qt simulator to run the swarm without Qt.
+//! Like the Qt router, it reads the `sender_id` and `pos` of each message, moves the sender there,
+//! and forwards the message to the other robots within range, through the emulated links of `netem.rs`.
+//! Each robot `id` reads its messages from `robot<id>_in` and writes its own on `robot<id>_out`,
+//! rather than on a single `simulIn`, on which the large messages of several robots would interleave.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use structopt::StructOpt;
+
+use robot::app::AppId;
+use robot::map::{Point, PolyMap};
+use robot::messages::Header;
+use robot::netem::{Emulator, NetemConfig};
+use robot::pipes::{listen, mkfifo};
+use robot::radio::{Radio, RadioParams};
+
+/// Period of the statistics in the log
+const STATS_PERIOD: Duration = Duration::from_secs(10);
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "router")]
+pub struct Opt {
+    /// Number of robot apps to spawn, with the ids 1 to N
+    #[structopt(short = "n", long = "spawn", default_value = "0")]
+    spawn: AppId,
+
+    /// Robot started separately, reading robot<id>_in and writing robot<id>_out
+    #[structopt(short = "a", long = "accept")]
+    accept: Vec<AppId>,
+
+    /// Start position of the spawned robots, as x,y, in the order of their ids
+    #[structopt(short = "p", long = "pose", parse(try_from_str = "parse_point"))]
+    poses: Vec<Point>,
+
+    /// Robot app to spawn, the robot binary next to the router by default
+    #[structopt(long = "robot-app", parse(from_os_str))]
+    robot_app: Option<PathBuf>,
+
+    /// Directory of the pipes and of the logs, where the robots are spawned
+    #[structopt(short = "d", long = "dir", default_value = ".", parse(from_os_str))]
+    dir: PathBuf,
+
+    /// Range of the robots, in meters
+    #[structopt(long = "range", default_value = "1")]
+    range: f32,
+
+    /// Map whose walls attenuate the signal, free space if not given
+    #[structopt(short = "m", long = "map", parse(from_os_str))]
+    map: Option<PathBuf>,
+
+    /// Rayleigh fading, the messages are otherwise always received in range
+    #[structopt(long = "fading")]
+    fading: bool,
+
+    /// Seed of the fading and of the network emulation
+    #[structopt(long = "seed", default_value = "0")]
+    seed: u64,
+
+    /// Network emulation parameters, as JSON, a perfect network if not given.
+    /// Their radio, if any, replaces --range and --fading.
+    #[structopt(short = "c", long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Stops after this many seconds, when all the spawned robots exited otherwise
+    #[structopt(short = "t", long = "duration")]
+    duration: Option<f32>,
+
+    /// Arguments of the spawned robots
+    #[structopt(raw(last = "true"))]
+    robot_args: Vec<String>,
+}
+
+fn parse_point(s: &str) -> Result<Point, String> {
+    let mut parts = s.splitn(2, ',');
+    match (parts.next().map(str::parse), parts.next().map(str::parse)) {
+        (Some(Ok(x)), Some(Ok(y))) => Ok(Point { x, y }),
+        _ => Err(format!("expected x,y, got {}", s)),
+    }
+}
+
+/// Writes the messages for a robot, without blocking the router until the robot opens its pipe
+fn deliver(id: AppId, input: PathBuf) -> mpsc::Sender<String> {
+    let (tx, rx) = mpsc::channel::<String>();
+    thread::spawn(move || {
+        let mut file = None;
+        for line in rx {
+            if file.is_none() {
+                file = OpenOptions::new().append(true).open(&input).ok();
+            }
+            let written = match file.as_mut() {
+                Some(file) => writeln!(file, "{}", line),
+                None => {
+                    log::error!("could not open the input of {}", id);
+                    continue;
+                }
+            };
+            if let Err(e) = written {
+                // the robot exited, the pipe is reopened for the next message
+                log::warn!("could not write to {}, {}", id, e);
+                file = None;
+            }
+        }
+    });
+    tx
+}
+
+#[derive(Default, Debug)]
+struct Stats {
+    /// Messages sent, by robot
+    sent: BTreeMap<AppId, usize>,
+    /// Messages delivered, by sender and receiver
+    delivered: BTreeMap<(AppId, AppId), usize>,
+    /// Messages read before the position of their sender
+    unplaced: usize,
+}
+
+impl Stats {
+    fn log(&self) {
+        for (id, sent) in self.sent.iter() {
+            let received: usize = self
+                .delivered
+                .iter()
+                .filter(|((_, to), _)| to == id)
+                .map(|(_, count)| count)
+                .sum();
+            log::info!("robot {}: sent {}, received {}", id, sent, received);
+        }
+        for ((from, to), count) in self.delivered.iter() {
+            log::info!("{} -> {}: {}", from, to, count);
+        }
+        let sent: usize = self.sent.values().sum();
+        let delivered: usize = self.delivered.values().sum();
+        log::info!(
+            "sent {}, delivered {}, without position {}",
+            sent,
+            delivered,
+            self.unplaced
+        );
+    }
+}
+
+/// The map attenuating the signal, free space without walls if not given
+fn load_map(path: Option<&PathBuf>) -> PolyMap {
+    match path {
+        Some(path) => PolyMap::from_file(path)
+            .unwrap_or_else(|e| panic!("could not load the map {:?}, {}", path, e)),
+        None => PolyMap::new(vec![]),
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    env_logger::init();
+
+    let map = load_map(opt.map.as_ref());
+    let config = match &opt.config {
+        Some(path) => NetemConfig::load(path)
+            .unwrap_or_else(|e| panic!("could not load the configuration {:?}, {}", path, e)),
+        None => NetemConfig::default(),
+    };
+    let params = config.radio.unwrap_or(RadioParams {
+        range: opt.range,
+        fading: opt.fading,
+        ..RadioParams::default()
+    });
+    let mut radio = Radio::new(params, map, opt.seed);
+    let mut emulator = Emulator::new(config, opt.seed);
+
+    let spawned: Vec<AppId> = (1..=opt.spawn).collect();
+    let ids: Vec<AppId> = spawned.iter().chain(opt.accept.iter()).cloned().collect();
+    let pipe = |id, end| opt.dir.join(format!("robot{}_{}", id, end));
+    let (tx, rx) = mpsc::channel();
+    let mut inputs = HashMap::new();
+    for &id in ids.iter() {
+        mkfifo(&pipe(id, "in"));
+        mkfifo(&pipe(id, "out"));
+        listen(id, pipe(id, "out"), tx.clone());
+        inputs.insert(id, deliver(id, pipe(id, "in")));
+    }
+    drop(tx);
+
+    let robot_app = opt.robot_app.clone().unwrap_or_else(|| {
+        std::env::current_exe()
+            .expect("could not find the router binary")
+            .with_file_name("robot")
+    });
+    // the spawned robots are placed before their first message
+    let mut positions: HashMap<AppId, Point> = HashMap::new();
+    let mut children: Vec<(AppId, Child)> = spawned
+        .iter()
+        .map(|&id| {
+            let pose = opt.poses.get(id as usize - 1).cloned().unwrap_or_default();
+            // the pipes are relative to the directory of the robots
+            let child = Command::new(&robot_app)
+                .current_dir(&opt.dir)
+                .arg(format!("--input=robot{}_in", id))
+                .arg(format!("--output=robot{}_out", id))
+                .arg(format!("--name={}", id))
+                .arg(format!("--logfile=robot{}.log", id))
+                .arg(format!("-x={}", pose.x))
+                .arg(format!("-y={}", pose.y))
+                .args(&opt.robot_args)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap_or_else(|e| panic!("could not spawn {:?}, {}", robot_app, e));
+            log::info!("spawned robot {} at {:?}", id, pose);
+            positions.insert(id, pose);
+            (id, child)
+        })
+        .collect();
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    {
+        let interrupted = interrupted.clone();
+        ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst))
+            .expect("could not handle Ctrl-C");
+    }
+
+    let start = Instant::now();
+    let mut last_stats = start;
+    let mut stats = Stats::default();
+    let now = || start.elapsed().as_secs_f64();
+    loop {
+        let wait = match emulator.next_delivery() {
+            Some(at) => Duration::from_secs_f64((at - now()).clamp(0., 0.1)),
+            None => Duration::from_millis(100),
+        };
+        match rx.recv_timeout(wait) {
+            Ok((from, line)) => {
+                *stats.sent.entry(from).or_insert(0) += 1;
+                match Header::from_json(&line) {
+                    // the robots relay the messages of the others with their original position
+                    Ok(header) if header.sender_id == from => {
+                        positions.insert(from, header.pos.p);
+                    }
+                    Ok(_) => (),
+                    Err(e) => log::warn!("could not read the header of {}, {}", from, e),
+                }
+                if !positions.contains_key(&from) {
+                    stats.unplaced += 1;
+                }
+                let receivers = radio.receivers(from, &positions);
+                let size = line.len() + 1;
+                emulator.broadcast(now(), from, &receivers, line, size);
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        for delivery in emulator.poll(now()) {
+            let (from, to) = (delivery.from, delivery.to);
+            log::debug!("Message from {} transmitted to {}", from, to);
+            *stats.delivered.entry((from, to)).or_insert(0) += 1;
+            // the receiver's thread only stops with the router
+            let _ = inputs[&to].send(delivery.payload);
+        }
+
+        if last_stats.elapsed() >= STATS_PERIOD {
+            last_stats = Instant::now();
+            stats.log();
+        }
+        let timeout = opt
+            .duration
+            .is_some_and(|d| start.elapsed().as_secs_f32() >= d);
+        children.retain_mut(|(id, child)| match child.try_wait() {
+            Ok(Some(status)) => {
+                log::info!("robot {} exited, {}", id, status);
+                false
+            }
+            _ => true,
+        });
+        let all_exited = !spawned.is_empty() && children.is_empty();
+        if timeout || all_exited || interrupted.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+
+    for (id, mut child) in children {
+        if let Err(e) = child.kill().and_then(|_| child.wait()) {
+            log::error!("could not stop robot {}, {}", id, e);
+        }
+    }
+    stats.log();
+}
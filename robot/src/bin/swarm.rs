@@ -0,0 +1,272 @@
+//! Runs a swarm of robot processes described by a scenario, headless.
+//! The supervisor creates the pipes of the robots in the run directory, starts the router
+//! forwarding their messages and the robots, each logging in its own file, restarts the processes
+//! which crash, and stops everything after the time limit or on Ctrl-C.
+//! The robots load their part of the scenario themselves, and the router emulates its network.
+
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use structopt::StructOpt;
+
+use robot::map::PolyMap;
+use robot::pipes::mkfifo;
+use robot::scenario::Scenario;
+
+/// Time given to the processes to exit when interrupted
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "swarm")]
+pub struct Opt {
+    /// Scenario, as JSON
+    #[structopt(parse(from_os_str))]
+    scenario: PathBuf,
+
+    /// Directory of the run, with the pipes and the logs
+    #[structopt(short = "d", long = "dir", default_value = "run", parse(from_os_str))]
+    dir: PathBuf,
+
+    /// Stops after this many seconds, instead of the time limit of the scenario
+    #[structopt(short = "t", long = "duration")]
+    duration: Option<f32>,
+
+    /// A process crashing more often is not restarted
+    #[structopt(long = "max-restarts", default_value = "3")]
+    max_restarts: usize,
+
+    /// Directory of the robot and router binaries, the one of the supervisor by default
+    #[structopt(long = "bin-dir", parse(from_os_str))]
+    bin_dir: Option<PathBuf>,
+}
+
+/// A process of the swarm, run in the directory of the run
+struct Process {
+    name: String,
+    program: PathBuf,
+    args: Vec<String>,
+    log_level: String,
+    /// Standard error of the process, appended to across the restarts
+    log: PathBuf,
+    child: Option<Child>,
+    restarts: usize,
+}
+
+impl Process {
+    fn new(name: String, program: PathBuf, args: Vec<String>, log_level: String) -> Self {
+        Process {
+            log: format!("{}.log", name).into(),
+            name,
+            program,
+            args,
+            log_level,
+            child: None,
+            restarts: 0,
+        }
+    }
+
+    fn start(&mut self, dir: &Path) {
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(&self.log))
+            .unwrap_or_else(|e| panic!("could not open the log of {}, {}", self.name, e));
+        let child = Command::new(&self.program)
+            .current_dir(dir)
+            .args(&self.args)
+            .env("RUST_LOG", &self.log_level)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(log)
+            .spawn()
+            .unwrap_or_else(|e| panic!("could not start {} {:?}, {}", self.name, self.program, e));
+        log::info!("started {}, pid {}", self.name, child.id());
+        self.child = Some(child);
+    }
+
+    /// Restarts the process if it crashed, returns whether it is still running
+    fn supervise(&mut self, dir: &Path, max_restarts: usize) -> bool {
+        let status = match self.child.as_mut().map(Child::try_wait) {
+            None => return false,
+            Some(Ok(None)) => return true,
+            Some(Ok(Some(status))) => status,
+            Some(Err(e)) => panic!("could not wait for {}, {}", self.name, e),
+        };
+        self.child = None;
+        if status.success() {
+            log::info!("{} exited", self.name);
+        } else if self.restarts < max_restarts {
+            self.restarts += 1;
+            log::warn!(
+                "{} crashed, {}, restart {}",
+                self.name,
+                status,
+                self.restarts
+            );
+            self.start(dir);
+        } else {
+            log::error!("{} crashed, {}, not restarted", self.name, status);
+        }
+        self.child.is_some()
+    }
+
+    /// Interrupts the process, and kills it if it does not exit in time
+    fn stop(&mut self) {
+        let mut child = match self.child.take() {
+            Some(child) => child,
+            None => return,
+        };
+        let interrupted = Command::new("kill")
+            .arg("-INT")
+            .arg(child.id().to_string())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false);
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        while interrupted && Instant::now() < deadline {
+            match child.try_wait() {
+                Ok(None) => thread::sleep(Duration::from_millis(10)),
+                _ => return,
+            }
+        }
+        if let Err(e) = child.kill().and_then(|_| child.wait()) {
+            log::error!("could not stop {}, {}", self.name, e);
+        }
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    env_logger::init();
+
+    let scenario = Scenario::load(&opt.scenario)
+        .unwrap_or_else(|e| panic!("could not load the scenario {:?}, {}", opt.scenario, e));
+    // rather than each robot failing on it
+    PolyMap::from_file(&scenario.map)
+        .unwrap_or_else(|e| panic!("could not load the map {:?}, {}", scenario.map, e));
+    let dir = &opt.dir;
+    fs::create_dir_all(dir).unwrap_or_else(|e| panic!("could not create {:?}, {}", dir, e));
+    // the processes run in the directory of the run
+    let absolute = |path: &Path| {
+        fs::canonicalize(path).unwrap_or_else(|e| panic!("could not find {:?}, {}", path, e))
+    };
+    let (scenario_path, map) = (absolute(&opt.scenario), absolute(&scenario.map));
+    let network = File::create(dir.join("network.json"))
+        .unwrap_or_else(|e| panic!("could not create the network configuration, {}", e));
+    serde_json::to_writer_pretty(network, &scenario.network)
+        .unwrap_or_else(|e| panic!("could not write the network configuration, {}", e));
+
+    let bin_dir = opt.bin_dir.clone().unwrap_or_else(|| {
+        let exe = std::env::current_exe().expect("could not find the supervisor binary");
+        exe.parent().expect("no binary directory").to_path_buf()
+    });
+    let mut pipes = Vec::new();
+    let mut router_args = vec![
+        "--dir=.".to_string(),
+        format!("--map={}", map.display()),
+        "--config=network.json".to_string(),
+        format!("--seed={}", scenario.seed),
+    ];
+    let mut robots: Vec<Process> = Vec::new();
+    for robot in scenario.robots.iter() {
+        let (input, output) = (
+            format!("robot{}_in", robot.id),
+            format!("robot{}_out", robot.id),
+        );
+        mkfifo(&dir.join(&input));
+        mkfifo(&dir.join(&output));
+        router_args.push(format!("--accept={}", robot.id));
+        // kept across the restarts of the robot, not across the runs
+        let raft_state = format!("robot{}_raft.json", robot.id);
+        let _ = fs::remove_file(dir.join(&raft_state));
+        let args = [
+            format!("--input={}", input),
+            format!("--output={}", output),
+            format!("--name={}", robot.id),
+            format!("--scenario={}", scenario_path.display()),
+            format!("--raft-state={}", raft_state),
+        ];
+        let args = args
+            .iter()
+            .chain(scenario.args.iter())
+            .chain(robot.args.iter())
+            .cloned()
+            .collect();
+        pipes.push(input);
+        pipes.push(output);
+        robots.push(Process::new(
+            format!("robot{}", robot.id),
+            bin_dir.join("robot"),
+            args,
+            scenario.log_level(robot),
+        ));
+    }
+    let mut router = Process::new(
+        "router".to_string(),
+        bin_dir.join("router"),
+        router_args,
+        "info".to_string(),
+    );
+
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let stop = stop.clone();
+        ctrlc::set_handler(move || stop.store(true, Ordering::SeqCst))
+            .expect("could not handle Ctrl-C");
+    }
+
+    router.start(dir);
+    for robot in robots.iter_mut() {
+        robot.start(dir);
+    }
+    let start = Instant::now();
+    loop {
+        thread::sleep(Duration::from_millis(100));
+        // the processes interrupted by Ctrl-C are not restarted
+        if stop.load(Ordering::SeqCst) {
+            log::info!("interrupted");
+            break;
+        }
+        if opt
+            .duration
+            .or(scenario.time_limit)
+            .is_some_and(|d| start.elapsed().as_secs_f32() >= d)
+        {
+            log::info!("time limit reached");
+            break;
+        }
+        router.supervise(dir, opt.max_restarts);
+        let mut running = 0;
+        for robot in robots.iter_mut() {
+            if robot.supervise(dir, opt.max_restarts) {
+                running += 1;
+            }
+        }
+        if running == 0 {
+            log::info!("all the robots exited");
+            break;
+        }
+    }
+
+    for robot in robots.iter_mut() {
+        robot.stop();
+    }
+    router.stop();
+    for pipe in pipes.iter() {
+        if let Err(e) = fs::remove_file(dir.join(pipe)) {
+            log::warn!("could not remove {}, {}", pipe, e);
+        }
+    }
+    let restarts: usize = robots.iter().map(|r| r.restarts).sum();
+    log::info!(
+        "ran {} robots for {:.1}s, {} restarts",
+        robots.len(),
+        start.elapsed().as_secs_f32(),
+        restarts + router.restarts
+    );
+}
@@ -0,0 +1,17 @@
+//! Distributed mapping by a swarm of robots.
+//! The `robot` binary runs one robot, the other binaries help running and testing the swarm.
+
+pub mod ai;
+pub mod app;
+mod events;
+pub mod map;
+pub mod messages;
+pub mod metrics;
+pub mod mission;
+pub mod netem;
+pub mod pipes;
+pub mod radio;
+pub mod raft;
+pub mod robot;
+pub mod scenario;
+pub mod world;
@@ -1,18 +1,17 @@
 use std::fs::OpenOptions;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use gag::Redirect;
 use log;
 use structopt::StructOpt;
 
-mod ai;
-mod app;
-mod events;
-mod map;
-mod messages;
-mod robot;
-
-use app::{App, AppId};
+use robot::ai::MergePolicy;
+use robot::app::{App, AppId};
+use robot::map::{Point, PolyMap, Position};
+use robot::metrics::{GroundTruth, DEFAULT_TOLERANCE};
+use robot::robot::{NoiseParams, RangeSensorParams, RobotBackend, SerialRobot, SimRobot};
+use robot::scenario::{Scenario, Strategy};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "netchat")]
@@ -38,6 +37,91 @@ pub struct Opt {
 
     #[structopt(short = "y", default_value = "0")]
     init_y: f32,
+
+    /// Map of the simulated world
+    #[structopt(long = "map", default_value = "map.json", parse(from_os_str))]
+    map: PathBuf,
+
+    /// Scenario giving the map and the strategy, and the start position, noise, range sensor
+    /// and seed of the robot --name, instead of the other flags
+    #[structopt(long = "scenario", parse(from_os_str))]
+    scenario: Option<PathBuf>,
+
+    /// Serial port of a real robot, the robot is simulated if not given
+    #[structopt(long = "serial", parse(from_os_str))]
+    serial: Option<PathBuf>,
+
+    /// Standard deviation of the simulated odometry translation error over one meter
+    #[structopt(long = "translation-noise", default_value = "0")]
+    translation_noise: f32,
+
+    /// Standard deviation of the simulated odometry rotation error over one radian
+    #[structopt(long = "rotation-noise", default_value = "0")]
+    rotation_noise: f32,
+
+    /// Systematic heading error of the simulated odometry, in radians per meter
+    #[structopt(long = "drift", default_value = "0")]
+    drift: f32,
+
+    /// Maximum distance counted by the simulated wheels slipping on a collision
+    #[structopt(long = "slip", default_value = "0")]
+    slip: f32,
+
+    /// Number of beams of the simulated range sensor, 0 disables it
+    #[structopt(long = "beams", default_value = "32")]
+    beams: usize,
+
+    /// Maximum range of the simulated range sensor, in meters
+    #[structopt(long = "max-range", default_value = "0.5")]
+    max_range: f32,
+
+    /// Standard deviation of the simulated range sensor error, in meters
+    #[structopt(long = "range-noise", default_value = "0.005")]
+    range_noise: f32,
+
+    /// Registers the maps of the other robots before merging them,
+    /// for robots which do not know their relative start positions
+    #[structopt(long = "align-maps")]
+    align_maps: bool,
+
+    /// How the maps of the other robots are merged: blocked-wins, lww, voting or trust
+    #[structopt(long = "merge-policy", default_value = "blocked-wins")]
+    merge_policy: MergePolicy,
+
+    /// Trust in another robot for the trust merge policy, as id=weight, 1 by default
+    #[structopt(long = "trust", parse(try_from_str = "parse_trust"))]
+    trust: Vec<(AppId, f32)>,
+
+    /// Period of the anti-entropy with the neighbours, in seconds, 0 disables it.
+    /// Only with the blocked-wins and lww merge policies, without --align-maps.
+    #[structopt(long = "sync-period", default_value = "1")]
+    sync_period: f32,
+
+    /// Number of robots in the swarm, for the leader election and the consensus on the mission.
+    /// If not given, the majority is computed among the robots heard of recently.
+    #[structopt(long = "swarm-size")]
+    swarm_size: Option<usize>,
+
+    /// File where the Raft term and vote are saved, restored when the robot restarts
+    #[structopt(long = "raft-state", parse(from_os_str))]
+    raft_state: Option<PathBuf>,
+
+    /// Seed of the simulated noise, random if not given
+    #[structopt(long = "seed")]
+    seed: Option<u64>,
+
+    /// Period of the log of the quality of the map against the map of the simulated world,
+    /// in seconds, 0 disables it
+    #[structopt(long = "metrics-period", default_value = "0")]
+    metrics_period: f32,
+}
+
+fn parse_trust(s: &str) -> Result<(AppId, f32), String> {
+    let mut parts = s.splitn(2, '=');
+    match (parts.next().map(str::parse), parts.next().map(str::parse)) {
+        (Some(Ok(id)), Some(Ok(weight))) => Ok((id, weight)),
+        _ => Err(format!("expected id=weight, got {}", s)),
+    }
 }
 
 fn main() {
@@ -59,12 +143,81 @@ fn main() {
         _stderr_redirect_handle = Some(Redirect::stderr(log).unwrap());
     }
 
-    let mut app = App::new(
-        opt.app_id.unwrap_or_else(rand::random),
-        opt.output,
-        opt.input,
-    );
-    app.init((opt.init_x, opt.init_y));
+    let app_id = opt.app_id.unwrap_or_else(rand::random);
+    let mut start = Position {
+        p: Point {
+            x: opt.init_x,
+            y: opt.init_y,
+        },
+        a: 0.,
+    };
+    let mut map = opt.map;
+    let mut noise = NoiseParams {
+        translation: opt.translation_noise,
+        rotation: opt.rotation_noise,
+        drift: opt.drift,
+        slip: opt.slip,
+    };
+    let mut sensor = RangeSensorParams {
+        nb_beams: opt.beams,
+        max_range: opt.max_range,
+        noise: opt.range_noise,
+        ..RangeSensorParams::default()
+    };
+    let mut seed = opt.seed;
+    let mut strategy = Strategy {
+        merge_policy: opt.merge_policy,
+        sync_period: opt.sync_period,
+        align_maps: opt.align_maps,
+        swarm_size: opt.swarm_size,
+    };
+    if let Some(path) = opt.scenario {
+        let scenario = Scenario::load(&path)
+            .unwrap_or_else(|e| panic!("failed to load scenario {:?}, {}", path, e));
+        let spec = scenario
+            .robot(app_id)
+            .unwrap_or_else(|| panic!("robot {} is not in scenario {:?}", app_id, path));
+        start = scenario.start(spec);
+        map = scenario.map.clone();
+        noise = scenario.noise(spec);
+        sensor = scenario.sensor(spec);
+        seed = Some(scenario.seed(spec));
+        strategy = scenario.strategy;
+    }
+
+    let (robot, robot_rx): (Box<dyn RobotBackend>, _) = match opt.serial {
+        Some(port) => {
+            let (robot, robot_rx) = SerialRobot::open(&port)
+                .unwrap_or_else(|e| panic!("failed to open serial port {:?}, {}", port, e));
+            (Box::new(robot), robot_rx)
+        }
+        None => {
+            let (mut robot, robot_rx) = SimRobot::new();
+            robot
+                .load_map(&map)
+                .unwrap_or_else(|e| panic!("failed to load map {:?}, {}", map, e));
+            robot.set_noise(noise, seed);
+            robot.set_range_sensor(sensor);
+            (Box::new(robot), robot_rx)
+        }
+    };
+
+    let mut app = App::new(app_id, opt.output, opt.input, robot, robot_rx);
+    app.set_map_alignment(strategy.align_maps);
+    app.set_merge_policy(strategy.merge_policy, &opt.trust);
+    app.set_sync_period(strategy.sync_period);
+    app.set_swarm_size(strategy.swarm_size);
+    if let Some(path) = opt.raft_state {
+        app.set_raft_state(path.clone())
+            .unwrap_or_else(|e| panic!("failed to load the Raft state {:?}, {}", path, e));
+    }
+    app.init(start);
+    if opt.metrics_period > 0. {
+        let world = PolyMap::from_file(&map)
+            .unwrap_or_else(|e| panic!("failed to load map {:?}, {}", map, e));
+        let truth = GroundTruth::new(&world, &[start.p], DEFAULT_TOLERANCE);
+        app.set_ground_truth(truth, Duration::from_secs_f32(opt.metrics_period));
+    }
 
     if let Err(e) = app.run() {
         log::error!("Something went wrong {}", e);
@@ -0,0 +1,292 @@
+//! Validation of the maps, so that a bad map file is reported when it is loaded
+//! rather than breaking the simulation.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use super::{Point, PolyMap, Polygon, SvgError, EPSILON};
+
+/// A defect of a polygon of a map, the polygons and their points being given by their indices
+#[derive(Debug, PartialEq)]
+pub enum Defect {
+    /// Without any point
+    Empty { polygon: usize },
+    /// A single point, which is no wall
+    SinglePoint { polygon: usize },
+    /// A coordinate is NaN or infinite
+    NonFinite { polygon: usize, point: usize },
+    /// A point is the same as the previous one, the first being after the last of a closed polygon
+    DuplicatePoint { polygon: usize, point: usize },
+    /// A closed polygon enclosing no surface
+    Degenerate { polygon: usize },
+    /// Two edges crossing each other, which is allowed but likely a mistake
+    SelfIntersection {
+        polygon: usize,
+        edges: (usize, usize),
+        at: Point,
+    },
+}
+
+impl Defect {
+    /// The map cannot be used with this defect, the others are warnings
+    pub fn is_error(&self) -> bool {
+        !matches!(self, Defect::SelfIntersection { .. })
+    }
+}
+
+impl fmt::Display for Defect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Defect::Empty { polygon } => write!(f, "polygon {} has no point", polygon),
+            Defect::SinglePoint { polygon } => write!(f, "polygon {} has a single point", polygon),
+            Defect::NonFinite { polygon, point } => write!(
+                f,
+                "point {} of polygon {} has a coordinate which is not finite",
+                point, polygon
+            ),
+            Defect::DuplicatePoint { polygon, point } => write!(
+                f,
+                "point {} of polygon {} is the same as the previous one",
+                point, polygon
+            ),
+            Defect::Degenerate { polygon } => {
+                write!(f, "closed polygon {} encloses no surface", polygon)
+            }
+            Defect::SelfIntersection { polygon, edges, at } => write!(
+                f,
+                "edges {} and {} of polygon {} cross at ({}, {})",
+                edges.0, edges.1, polygon, at.x, at.y
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Svg(SvgError),
+    /// The defects of the map, at least one of them being an error
+    Invalid(Vec<Defect>),
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::Io(e) => write!(f, "{}", e),
+            MapError::Json(e) => write!(f, "invalid JSON, {}", e),
+            MapError::Svg(e) => write!(f, "invalid SVG, {}", e),
+            MapError::Invalid(defects) => {
+                write!(f, "invalid map")?;
+                for defect in defects.iter().filter(|d| d.is_error()) {
+                    write!(f, ", {}", defect)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for MapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MapError::Io(e) => Some(e),
+            MapError::Json(e) => Some(e),
+            MapError::Svg(e) => Some(e),
+            MapError::Invalid(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for MapError {
+    fn from(e: io::Error) -> Self {
+        MapError::Io(e)
+    }
+}
+
+impl From<SvgError> for MapError {
+    fn from(e: SvgError) -> Self {
+        MapError::Svg(e)
+    }
+}
+
+impl From<serde_json::Error> for MapError {
+    fn from(e: serde_json::Error) -> Self {
+        MapError::Json(e)
+    }
+}
+
+fn check_polygon(index: usize, polygon: &Polygon, defects: &mut Vec<Defect>) {
+    let points = &polygon.points;
+    match points.len() {
+        0 => return defects.push(Defect::Empty { polygon: index }),
+        1 => return defects.push(Defect::SinglePoint { polygon: index }),
+        _ => (),
+    }
+    let non_finite: Vec<Defect> = points
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| !p.x.is_finite() || !p.y.is_finite())
+        .map(|(point, _)| Defect::NonFinite {
+            polygon: index,
+            point,
+        })
+        .collect();
+    if !non_finite.is_empty() {
+        // nothing more can be said about the shape of the polygon
+        return defects.extend(non_finite);
+    }
+    let closing = if polygon.is_closed { Some(0) } else { None };
+    let duplicates: Vec<Defect> = (1..points.len())
+        .chain(closing)
+        .filter(|&i| points[i] == points[(i + points.len() - 1) % points.len()])
+        .map(|point| Defect::DuplicatePoint {
+            polygon: index,
+            point,
+        })
+        .collect();
+    if polygon.is_closed && is_flat(points) {
+        defects.push(Defect::Degenerate { polygon: index });
+    }
+    if !duplicates.is_empty() {
+        // the edges on both sides of a duplicate point meet, without crossing
+        return defects.extend(duplicates);
+    }
+    defects.extend(polygon.self_intersections().into_iter().map(|(i, j, at)| {
+        Defect::SelfIntersection {
+            polygon: index,
+            edges: (i, j),
+            at,
+        }
+    }));
+}
+
+/// All the points are on a line
+fn is_flat(points: &[Point]) -> bool {
+    let first = points[0];
+    match points.iter().find(|&&p| p != first) {
+        None => true,
+        Some(&second) => {
+            let direction = first.vec_to(second);
+            let length = direction.sq_norm().sqrt();
+            points
+                .iter()
+                .all(|&p| direction.cross_prod(first.vec_to(p)).abs() < EPSILON * length)
+        }
+    }
+}
+
+impl PolyMap {
+    /// The defects of the polygons of the map, errors and warnings
+    pub fn check(&self) -> Vec<Defect> {
+        let mut defects = Vec::new();
+        for (index, polygon) in self.polygons.iter().enumerate() {
+            check_polygon(index, polygon, &mut defects);
+        }
+        defects
+    }
+
+    /// Fails with all the defects of the map if any of them is an error
+    pub fn validate(&self) -> Result<(), MapError> {
+        let defects = self.check();
+        if defects.iter().any(Defect::is_error) {
+            Err(MapError::Invalid(defects))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn polygon(points: &[(f32, f32)], is_closed: bool) -> Polygon {
+        Polygon {
+            points: points.iter().map(|&(x, y)| Point { x, y }).collect(),
+            is_closed,
+        }
+    }
+
+    #[test]
+    fn check_test() {
+        let square = polygon(&[(0., 0.), (2., 0.), (2., 2.), (0., 2.)], true);
+        let wall = polygon(&[(3., 0.), (3., 2.)], false);
+        let map = PolyMap::new(vec![square, wall]);
+        assert_eq!(map.check(), vec![]);
+        assert!(map.validate().is_ok());
+
+        let map = PolyMap::new(vec![
+            polygon(&[], false),
+            polygon(&[(1., 1.)], true),
+            polygon(&[(0., 0.), (f32::NAN, 1.), (2., 0.)], true),
+            polygon(&[(0., 0.), (1., 0.), (1., 0.), (1., 1.), (0., 0.)], true),
+            polygon(&[(0., 0.), (1., 1.), (2., 2.)], true),
+            polygon(&[(0., 0.), (2., 2.), (2., 0.), (0., 2.)], true),
+        ]);
+        assert_eq!(
+            map.check(),
+            vec![
+                Defect::Empty { polygon: 0 },
+                Defect::SinglePoint { polygon: 1 },
+                Defect::NonFinite {
+                    polygon: 2,
+                    point: 1
+                },
+                Defect::DuplicatePoint {
+                    polygon: 3,
+                    point: 2
+                },
+                Defect::DuplicatePoint {
+                    polygon: 3,
+                    point: 0
+                },
+                Defect::Degenerate { polygon: 4 },
+                Defect::SelfIntersection {
+                    polygon: 5,
+                    edges: (0, 2),
+                    at: Point { x: 1., y: 1. }
+                },
+            ]
+        );
+        match map.validate() {
+            Err(MapError::Invalid(defects)) => assert_eq!(defects.len(), 7),
+            other => panic!("expected an invalid map, got {:?}", other),
+        }
+
+        // the duplicate point of an open polygon is not closing it
+        let open = polygon(&[(0., 0.), (1., 0.), (0., 0.)], false);
+        assert_eq!(PolyMap::new(vec![open]).check(), vec![]);
+
+        // a crossing alone is a warning
+        let bowtie = polygon(&[(0., 0.), (2., 2.), (2., 0.), (0., 2.)], true);
+        let map = PolyMap::new(vec![bowtie]);
+        assert_eq!(map.check().len(), 1);
+        assert!(map.validate().is_ok());
+    }
+
+    #[test]
+    fn from_file_test() {
+        let path = std::env::temp_dir().join(format!("bad_map_{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"polygons": [{"points": [{"x": 1.0, "y": 2.0}], "is_closed": true}]}"#,
+        )
+        .unwrap();
+        let error = PolyMap::from_file(&path).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid map, polygon 0 has a single point"
+        );
+        assert_eq!(
+            PolyMap::from_file_unchecked(&path).unwrap().polygons.len(),
+            1
+        );
+
+        std::fs::write(&path, "{").unwrap();
+        assert!(matches!(PolyMap::from_file(&path), Err(MapError::Json(_))));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(PolyMap::from_file(&path), Err(MapError::Io(_))));
+    }
+}
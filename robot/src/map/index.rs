@@ -0,0 +1,161 @@
+//! Uniform grid over the walls of a map, so that a segment is only tested
+//! against the walls in the cells it crosses.
+
+use super::{Distance, Point, Segment, EPSILON};
+
+#[derive(Debug, Default)]
+pub struct SegmentIndex {
+    /// The walls, indexed by the cells
+    segments: Vec<Segment>,
+    /// Lower left corner of the grid
+    origin: Point,
+    cell_size: Distance,
+    /// Number of columns and of rows
+    size: (usize, usize),
+    /// Indices of the segments crossing each cell, row by row
+    cells: Vec<Vec<u32>>,
+}
+
+impl SegmentIndex {
+    pub fn new(segments: Vec<Segment>) -> Self {
+        let mut points = segments.iter().flat_map(|s| vec![s.0, s.1]);
+        let first = match points.next() {
+            Some(first) => first,
+            None => return SegmentIndex::default(),
+        };
+        let (min, max) = points.fold((first, first), |(min, max), p| {
+            (
+                Point {
+                    x: min.x.min(p.x),
+                    y: min.y.min(p.y),
+                },
+                Point {
+                    x: max.x.max(p.x),
+                    y: max.y.max(p.y),
+                },
+            )
+        });
+        // about as many cells as segments
+        let (width, height) = (max.x - min.x, max.y - min.y);
+        let n = segments.len() as Distance;
+        let cell_size = (width * height / n)
+            .sqrt()
+            .max(width.max(height) / n)
+            .max(EPSILON);
+        let size = (
+            (width / cell_size).floor() as usize + 1,
+            (height / cell_size).floor() as usize + 1,
+        );
+        let mut index = SegmentIndex {
+            segments: Vec::new(),
+            origin: min,
+            cell_size,
+            size,
+            cells: vec![Vec::new(); size.0 * size.1],
+        };
+        for (i, segment) in segments.iter().enumerate() {
+            for cell in index.cells_crossed(segment) {
+                index.cells[cell].push(i as u32);
+            }
+        }
+        index.segments = segments;
+        index
+    }
+
+    /// Cells crossed by the segment, padded not to miss any by a rounding error
+    fn cells_crossed(&self, s: &Segment) -> Vec<usize> {
+        let mut crossed = Vec::new();
+        if self.cells.is_empty() {
+            return crossed;
+        }
+        let to_grid = |p: Point| {
+            (
+                (p.x - self.origin.x) / self.cell_size,
+                (p.y - self.origin.y) / self.cell_size,
+            )
+        };
+        let (a, b) = (to_grid(s.0), to_grid(s.1));
+        let (last_column, last_row) = ((self.size.0 - 1) as f32, (self.size.1 - 1) as f32);
+        let pad = 1e-3;
+        let (bottom, top) = (a.1.min(b.1) - pad, a.1.max(b.1) + pad);
+        if top < 0. || bottom > last_row + 1. {
+            return crossed;
+        }
+        let rows = bottom.max(0.).floor() as usize..=top.min(last_row).floor() as usize;
+        for row in rows {
+            // the part of the segment in the row
+            let (x0, x1) = if (b.1 - a.1).abs() < EPSILON {
+                (a.0, b.0)
+            } else {
+                let x_at = |y: f32| a.0 + (y - a.1) / (b.1 - a.1) * (b.0 - a.0);
+                let low = (row as f32).max(bottom);
+                let high = (row as f32 + 1.).min(top);
+                (x_at(low), x_at(high))
+            };
+            let (left, right) = (x0.min(x1) - pad, x0.max(x1) + pad);
+            if right < 0. || left > last_column + 1. {
+                continue;
+            }
+            let columns = left.max(0.).floor() as usize..=right.min(last_column).floor() as usize;
+            crossed.extend(columns.map(|column| row * self.size.0 + column));
+        }
+        crossed
+    }
+
+    /// The walls which may intersect the segment, each once
+    pub fn candidates(&self, s: &Segment) -> impl Iterator<Item = &Segment> {
+        let mut ids: Vec<u32> = self
+            .cells_crossed(s)
+            .into_iter()
+            .flat_map(|cell| self.cells[cell].iter().cloned())
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.into_iter().map(move |i| &self.segments[i as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(x0: Distance, y0: Distance, x1: Distance, y1: Distance) -> Segment {
+        Segment(Point { x: x0, y: y0 }, Point { x: x1, y: y1 })
+    }
+
+    #[test]
+    fn candidates_test() {
+        // a comb of vertical walls
+        let walls: Vec<Segment> = (0..100)
+            .map(|i| segment(i as Distance, 0., i as Distance, 1.))
+            .chain(Some(segment(0., 2., 99., 2.)))
+            .collect();
+        let index = SegmentIndex::new(walls);
+        assert!(index.size.0 * index.size.1 <= 2 * 101);
+
+        let short = segment(10.5, 0.5, 12.5, 0.5);
+        let candidates: Vec<_> = index.candidates(&short).collect();
+        assert!(candidates.len() < 10);
+        let crossed = candidates
+            .iter()
+            .filter(|wall| short.intersection(wall).is_some())
+            .count();
+        assert_eq!(crossed, 2);
+
+        // along the horizontal wall, and through the ends of all the teeth
+        assert_eq!(index.candidates(&segment(-1., 2., 100., 2.)).count(), 1);
+        let along = segment(-1., 1., 100., 1.);
+        assert_eq!(
+            index
+                .candidates(&along)
+                .filter(|wall| along.intersection(wall).is_some())
+                .count(),
+            100
+        );
+
+        // out of the grid
+        assert_eq!(index.candidates(&segment(-5., -5., -1., 5.)).count(), 0);
+        assert_eq!(index.candidates(&segment(0., 3., 99., 3.)).count(), 0);
+        assert_eq!(SegmentIndex::new(vec![]).candidates(&short).count(), 0);
+    }
+}
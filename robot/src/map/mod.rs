@@ -1,16 +1,25 @@
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::error::Error;
 use std::fs::{File, OpenOptions};
 use std::io::{BufReader, Write};
 use std::ops::{Add, Div, Mul, Sub};
 use std::path::PathBuf;
+use std::sync::OnceLock;
 
+mod check;
+mod index;
 mod polygon;
+mod raster;
+mod svg;
+pub use check::{Defect, MapError};
+use index::SegmentIndex;
 pub use polygon::Polygon;
+pub use raster::Grid;
+pub use svg::SvgError;
 
 /// Approximated zero
 const EPSILON: Distance = 1e-6;
+const PI: Angle = std::f32::consts::PI;
 
 /// Whatever
 pub type Distance = f32;
@@ -149,6 +158,7 @@ impl Point {
     }
 }
 
+#[derive(Copy, Clone, Debug)]
 pub struct Segment(pub Point, pub Point);
 
 impl Segment {
@@ -182,6 +192,33 @@ impl Segment {
             Some(self.0 + t * r)
         }
     }
+
+    /// Point of the segment closest to `p`
+    pub fn closest_point(&self, p: Point) -> Point {
+        let d = self.0.vec_to(self.1);
+        let sq_len = d.sq_norm();
+        if sq_len < EPSILON * EPSILON {
+            return self.0;
+        }
+        let t = (self.0.vec_to(p).dot_prod(d) / sq_len).clamp(0., 1.);
+        self.0 + t * d
+    }
+
+    pub fn distance(&self, p: Point) -> Distance {
+        p.sq_dist(self.closest_point(p)).sqrt()
+    }
+}
+
+/// Wraps an angle in ]-PI, PI]
+pub fn normalize_angle(a: Angle) -> Angle {
+    let a = a % (2. * PI);
+    if a > PI {
+        a - 2. * PI
+    } else if a <= -PI {
+        a + 2. * PI
+    } else {
+        a
+    }
 }
 
 #[derive(Default, Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
@@ -190,12 +227,53 @@ pub struct Position {
     pub a: Angle,
 }
 
+impl Position {
+    /// Applies `delta`, a displacement expressed in the frame of `self`
+    pub fn compose(self, delta: Position) -> Position {
+        Position {
+            p: self.p + delta.p.rotate(self.a),
+            a: normalize_angle(self.a + delta.a),
+        }
+    }
+
+    /// Displacement from `origin` to `self`, expressed in the frame of `origin`
+    pub fn relative_to(self, origin: Position) -> Position {
+        Position {
+            p: (self.p - origin.p).rotate(-origin.a),
+            a: normalize_angle(self.a - origin.a),
+        }
+    }
+}
+
+/// The polygons are only read once the map is built, the index of their segments being built
+/// when it is first queried
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PolyMap {
-    pub polygons: Vec<Polygon>,
+    polygons: Vec<Polygon>,
+    #[serde(skip)]
+    index: OnceLock<SegmentIndex>,
 }
 
 impl PolyMap {
+    /// A map whose segments are indexed at once
+    pub fn new(polygons: Vec<Polygon>) -> Self {
+        let map = PolyMap {
+            polygons,
+            index: OnceLock::new(),
+        };
+        map.index();
+        map
+    }
+
+    pub fn polygons(&self) -> &[Polygon] {
+        &self.polygons
+    }
+
+    fn index(&self) -> &SegmentIndex {
+        self.index
+            .get_or_init(|| SegmentIndex::new(self.segments().collect()))
+    }
+
     /// Returns an iterator over the map's segments
     pub fn segments(&self) -> impl Iterator<Item = Segment> + '_ {
         self.polygons.iter().flat_map(|p| p.segments())
@@ -205,32 +283,84 @@ impl PolyMap {
     /// from the first end (.0) of the segment to the other (.1)
     /// i.e. the closest intersection with one of the map's segments
     pub fn first_intersection(&self, s: &Segment) -> Option<(Point)> {
-        self.segments()
-            .filter_map(|seg| s.intersection(&seg))
+        self.index()
+            .candidates(s)
+            .filter_map(|seg| s.intersection(seg))
             .map(|p| (p, s.0.sq_dist(p)))
             .min_by(|(_, d1), (_, d2)| d1.partial_cmp(d2).unwrap_or(Ordering::Equal))
             .map(|(pt, _)| pt)
     }
 
+    /// Number of walls crossed by the segment,
+    /// a corner of the map being crossed once although it ends two segments
+    pub fn count_intersections(&self, s: &Segment) -> usize {
+        let mut distances: Vec<f32> = self
+            .index()
+            .candidates(s)
+            .filter_map(|seg| s.intersection(seg))
+            .map(|p| s.0.sq_dist(p).sqrt())
+            .collect();
+        distances.sort_by(|d1, d2| d1.partial_cmp(d2).unwrap_or(Ordering::Equal));
+        distances.dedup_by(|d1, d2| (*d1 - *d2).abs() < EPSILON);
+        distances.len()
+    }
+
+    /// The closed polygon encloses the others, it is the outer wall of the world
+    fn is_outer_wall(&self, polygon: &Polygon) -> bool {
+        polygon.is_closed
+            && self.polygons.iter().any(|other| {
+                !std::ptr::eq(other, polygon) && other.points.iter().all(|&p| polygon.contains(p))
+            })
+    }
+
+    /// The point is neither inside an obstacle, a closed polygon, nor out of the outer wall
+    pub fn is_free(&self, p: Point) -> bool {
+        self.polygons
+            .iter()
+            .filter(|polygon| polygon.is_closed)
+            .all(|polygon| polygon.contains(p) == self.is_outer_wall(polygon))
+    }
+
+    /// Distance from the point to the closest wall, infinite without any
+    pub fn clearance(&self, p: Point) -> Distance {
+        self.polygons
+            .iter()
+            .filter_map(|polygon| polygon.distance(p))
+            .fold(f32::INFINITY, Distance::min)
+    }
+
     #[allow(dead_code)]
-    pub fn save_to_file(&self, path: &PathBuf) -> Result<(), Box<Error>> {
+    pub fn save_to_file(&self, path: &PathBuf) -> Result<(), MapError> {
         //Saving message history to file
         let mut save_file = OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
-            .open(path)
-            .expect("Failed to create history file");
+            .open(path)?;
 
         let pretty_str = serde_json::to_string_pretty(&self)?;
         save_file.write_all(format!("{}\n", pretty_str).as_bytes())?;
         Ok(())
     }
-    pub fn from_file(path: &PathBuf) -> Result<Self, Box<Error>> {
+
+    /// Loads a map, failing on the errors found by `check`
+    pub fn from_file(path: &PathBuf) -> Result<Self, MapError> {
+        let map = Self::from_file_unchecked(path)?;
+        map.validate()?;
+        map.index();
+        Ok(map)
+    }
+
+    /// Loads a map whatever its defects, from JSON or from SVG with the scale of the document
+    pub fn from_file_unchecked(path: &PathBuf) -> Result<Self, MapError> {
+        if path.extension().is_some_and(|extension| extension == "svg") {
+            let svg = std::fs::read_to_string(path)?;
+            return Ok(PolyMap::from_svg(&svg, None)?);
+        }
         let file = File::open(path)?;
         let reader = BufReader::new(file);
 
-        let map = serde_json::from_reader(reader)?;
+        let map: PolyMap = serde_json::from_reader(reader)?;
         Ok(map)
     }
 }
@@ -256,9 +386,7 @@ impl Default for PolyMap {
             is_closed: true,
         };
 
-        PolyMap {
-            polygons: vec![p1, p2],
-        }
+        PolyMap::new(vec![p1, p2])
     }
 }
 
@@ -276,6 +404,32 @@ mod tests {
         assert_eq!((p1 - p2).angle().to_degrees(), -90.);
     }
 
+    #[test]
+    fn normalize_angle_test() {
+        assert!((normalize_angle(3. * PI / 2.) + PI / 2.).abs() < 1e-5);
+        assert!((normalize_angle(-3. * PI / 2.) - PI / 2.).abs() < 1e-5);
+        assert!((normalize_angle(PI) - PI).abs() < 1e-5);
+        assert!(normalize_angle(4. * PI).abs() < 1e-5);
+    }
+
+    #[test]
+    fn position_test() {
+        let origin = Position {
+            p: Point { x: 1., y: 2. },
+            a: PI / 2.,
+        };
+        let delta = Position {
+            p: Point { x: 0., y: 1. },
+            a: PI,
+        };
+        let pose = origin.compose(delta);
+        assert_eq!(pose.p, Point { x: 0., y: 2. });
+        assert!((pose.a + PI / 2.).abs() < 1e-5);
+        let back = pose.relative_to(origin);
+        assert_eq!(back.p, delta.p);
+        assert!((back.a - PI).abs() < 1e-5);
+    }
+
     #[test]
     fn intersection_test() {
         let s1 = Segment(Point { x: 0., y: 0. }, Point { x: 2., y: 2. });
@@ -309,6 +463,94 @@ mod tests {
         assert_eq!(None, s2.intersection(&s1));
     }
 
+    #[test]
+    fn segment_distance_test() {
+        let s = Segment(Point { x: 0., y: 0. }, Point { x: 2., y: 0. });
+        assert_eq!(
+            s.closest_point(Point { x: 1., y: 1. }),
+            Point { x: 1., y: 0. }
+        );
+        assert_eq!(s.distance(Point { x: 1., y: -1. }), 1.);
+        // beyond the ends
+        assert_eq!(s.closest_point(Point { x: 3., y: 4. }), s.1);
+        assert_eq!(s.distance(Point { x: -3., y: 4. }), 5.);
+
+        let point = Segment(Point { x: 1., y: 1. }, Point { x: 1., y: 1. });
+        assert_eq!(point.distance(Point { x: 4., y: 5. }), 5.);
+    }
+
+    #[test]
+    fn free_test() {
+        let map = PolyMap::default();
+        assert!(map.is_free(Point { x: -5., y: -5. }));
+        assert!(!map.is_free(Point { x: 1., y: 1. }));
+        assert!(!map.is_free(Point { x: 11., y: 0. }));
+        assert_eq!(map.clearance(Point { x: -1., y: 1. }), 1.);
+        assert_eq!(map.clearance(Point { x: 2., y: 1.5 }), 0.5);
+
+        let empty = PolyMap::new(vec![]);
+        assert!(empty.is_free(Point::zero()));
+        assert_eq!(empty.clearance(Point::zero()), f32::INFINITY);
+    }
+
+    #[test]
+    fn indexed_intersection_test() {
+        use rand::rngs::SmallRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let point = |rng: &mut SmallRng| Point {
+            x: rng.gen_range(-10., 10.),
+            y: rng.gen_range(-10., 10.),
+        };
+        let walls: Vec<Polygon> = (0..500)
+            .map(|_| {
+                let start = point(&mut rng);
+                let end = start + (point(&mut rng) - start) * 0.1;
+                Polygon {
+                    points: vec![start, end],
+                    is_closed: false,
+                }
+            })
+            .collect();
+        let map = PolyMap::new(walls);
+        for _ in 0..500 {
+            let s = Segment(point(&mut rng), point(&mut rng));
+            let expected = map
+                .segments()
+                .filter_map(|seg| s.intersection(&seg))
+                .min_by(|a, b| {
+                    s.0.sq_dist(*a)
+                        .partial_cmp(&s.0.sq_dist(*b))
+                        .unwrap_or(Ordering::Equal)
+                });
+            assert_eq!(map.first_intersection(&s), expected);
+        }
+    }
+
+    #[test]
+    fn count_intersections_test() {
+        let square = Polygon {
+            points: vec![
+                Point { x: 0., y: 0. },
+                Point { x: 1., y: 0. },
+                Point { x: 1., y: 1. },
+                Point { x: 0., y: 1. },
+            ],
+            is_closed: true,
+        };
+        let map = PolyMap::new(vec![square]);
+        let across = Segment(Point { x: -1., y: 0.5 }, Point { x: 2., y: 0.5 });
+        assert_eq!(map.count_intersections(&across), 2);
+        let inside = Segment(Point { x: 0.2, y: 0.5 }, Point { x: 2., y: 0.5 });
+        assert_eq!(map.count_intersections(&inside), 1);
+        let outside = Segment(Point { x: -1., y: 2. }, Point { x: 2., y: 2. });
+        assert_eq!(map.count_intersections(&outside), 0);
+        // through two opposite corners
+        let diagonal = Segment(Point { x: -1., y: -1. }, Point { x: 2., y: 2. });
+        assert_eq!(map.count_intersections(&diagonal), 2);
+    }
+
     #[test]
     fn save_test() {
         let path: PathBuf = "test_map_aljjbdbclwhblaszblxaksjxsa.json".into();
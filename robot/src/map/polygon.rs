@@ -1,4 +1,4 @@
-use super::{Point, Segment};
+use super::{Distance, Point, Segment};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -14,6 +14,127 @@ impl Polygon {
             curr: 0,
         }
     }
+
+    /// The edges of the polygon, closed or not, whatever its number of points
+    fn edges(&self) -> impl Iterator<Item = (Point, Point)> + '_ {
+        let len = self.points.len();
+        let nb_edges = if self.is_closed {
+            len
+        } else {
+            len.saturating_sub(1)
+        };
+        self.points
+            .iter()
+            .cloned()
+            .zip(self.points.iter().cycle().skip(1).cloned())
+            .take(nb_edges)
+    }
+
+    /// Number of times the closed polygon winds around the point, counterclockwise,
+    /// 0 for an open one
+    pub fn winding_number(&self, p: Point) -> i32 {
+        if !self.is_closed {
+            return 0;
+        }
+        let mut winding = 0;
+        for (a, b) in self.edges() {
+            // > 0 when p is on the left of the edge
+            let side = a.vec_to(b).cross_prod(a.vec_to(p));
+            if a.y <= p.y {
+                if b.y > p.y && side > 0. {
+                    winding += 1;
+                }
+            } else if b.y <= p.y && side < 0. {
+                winding -= 1;
+            }
+        }
+        winding
+    }
+
+    /// The point is inside the closed polygon, by the non-zero winding rule
+    pub fn contains(&self, p: Point) -> bool {
+        self.winding_number(p) != 0
+    }
+
+    /// Distance from the point to the edges, or to the only point of the polygon
+    pub fn distance(&self, p: Point) -> Option<Distance> {
+        match self.points.as_slice() {
+            [] => None,
+            [only] => Some(p.sq_dist(*only).sqrt()),
+            _ => self
+                .edges()
+                .map(|(a, b)| Segment(a, b).distance(p))
+                .fold(None, |min, d| {
+                    Some(min.map_or(d, |min: Distance| min.min(d)))
+                }),
+        }
+    }
+
+    /// Area enclosed by the closed polygon, positive when its points go counterclockwise,
+    /// 0 for an open one
+    pub fn signed_area(&self) -> Distance {
+        if !self.is_closed {
+            return 0.;
+        }
+        self.edges().map(|(a, b)| a.cross_prod(b)).sum::<Distance>() / 2.
+    }
+
+    pub fn area(&self) -> Distance {
+        self.signed_area().abs()
+    }
+
+    /// Center of mass of the surface enclosed by the closed polygon, None without any surface
+    pub fn centroid(&self) -> Option<Point> {
+        let area = self.signed_area();
+        if area.abs() < super::EPSILON {
+            return None;
+        }
+        let sum = self
+            .edges()
+            .fold(Point::zero(), |sum, (a, b)| sum + (a + b) * a.cross_prod(b));
+        Some(sum / (6. * area))
+    }
+
+    /// Lower left and upper right corners of the smallest box containing the polygon
+    pub fn bbox(&self) -> Option<(Point, Point)> {
+        let first = *self.points.first()?;
+        Some(self.points.iter().fold((first, first), |(min, max), p| {
+            (
+                Point {
+                    x: min.x.min(p.x),
+                    y: min.y.min(p.y),
+                },
+                Point {
+                    x: max.x.max(p.x),
+                    y: max.y.max(p.y),
+                },
+            )
+        }))
+    }
+
+    /// Edges crossing each other, as their indices and where they cross,
+    /// the consecutive edges meeting at their shared point not being counted
+    pub fn self_intersections(&self) -> Vec<(usize, usize, Point)> {
+        let edges: Vec<Segment> = self.edges().map(|(a, b)| Segment(a, b)).collect();
+        let last = edges.len().saturating_sub(1);
+        let mut crossings = Vec::new();
+        for (i, first) in edges.iter().enumerate() {
+            for (j, second) in edges.iter().enumerate().skip(i + 2) {
+                if self.is_closed && i == 0 && j == last {
+                    continue;
+                }
+                if let Some(p) = first.intersection(second) {
+                    crossings.push((i, j, p));
+                }
+            }
+        }
+        crossings
+    }
+
+    /// None of the edges cross each other
+    pub fn is_simple(&self) -> bool {
+        self.self_intersections().is_empty()
+    }
 }
 
 pub struct IterPolygon<'a> {
@@ -26,8 +147,8 @@ impl<'a> Iterator for IterPolygon<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         let len = self.polygon.points.len();
-        // case len = 1, and 2 are special
-        if (self.curr >= len - 1 && (!self.polygon.is_closed || len < 3)) || self.curr >= len {
+        // case len = 0, 1, and 2 are special
+        if (self.curr + 1 >= len && (!self.polygon.is_closed || len < 3)) || self.curr >= len {
             None
         } else {
             let seg = Segment(
@@ -39,3 +160,136 @@ impl<'a> Iterator for IterPolygon<'a> {
         }
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod tests {
+    use super::*;
+
+    fn polygon(points: &[(Distance, Distance)], is_closed: bool) -> Polygon {
+        Polygon {
+            points: points.iter().map(|&(x, y)| Point { x, y }).collect(),
+            is_closed,
+        }
+    }
+
+    #[test]
+    fn contains_test() {
+        let square = polygon(&[(0., 0.), (2., 0.), (2., 2.), (0., 2.)], true);
+        assert_eq!(square.winding_number(Point { x: 1., y: 1. }), 1);
+        assert!(square.contains(Point { x: 1.9, y: 0.1 }));
+        assert!(!square.contains(Point { x: 3., y: 1. }));
+        assert!(!square.contains(Point { x: 1., y: -0.1 }));
+
+        // clockwise
+        let square = polygon(&[(0., 0.), (0., 2.), (2., 2.), (2., 0.)], true);
+        assert_eq!(square.winding_number(Point { x: 1., y: 1. }), -1);
+
+        // the hole of a U
+        let u = polygon(
+            &[
+                (0., 0.),
+                (3., 0.),
+                (3., 3.),
+                (2., 3.),
+                (2., 1.),
+                (1., 1.),
+                (1., 3.),
+                (0., 3.),
+            ],
+            true,
+        );
+        assert!(u.contains(Point { x: 0.5, y: 2. }));
+        assert!(!u.contains(Point { x: 1.5, y: 2. }));
+
+        // a star winding twice around its center
+        let star = polygon(
+            &[(0., 2.), (1.2, -1.6), (-1.9, 0.6), (1.9, 0.6), (-1.2, -1.6)],
+            true,
+        );
+        assert_eq!(star.winding_number(Point::zero()).abs(), 2);
+        assert!(star.contains(Point::zero()));
+
+        let open = polygon(&[(0., 0.), (2., 0.), (2., 2.), (0., 2.)], false);
+        assert!(!open.contains(Point { x: 1., y: 1. }));
+    }
+
+    #[test]
+    fn distance_test() {
+        let square = polygon(&[(0., 0.), (2., 0.), (2., 2.), (0., 2.)], true);
+        assert_eq!(square.distance(Point { x: 1., y: 1.5 }), Some(0.5));
+        assert_eq!(square.distance(Point { x: 5., y: 6. }), Some(5.));
+        assert_eq!(square.distance(Point { x: -1., y: 1. }), Some(1.));
+
+        // the closing edge of an open polygon is not a wall
+        let open = polygon(&[(0., 0.), (2., 0.), (2., 2.), (0., 2.)], false);
+        assert_eq!(open.distance(Point { x: -1., y: 1. }), Some(2f32.sqrt()));
+
+        let dot = polygon(&[(1., 1.)], true);
+        assert_eq!(dot.distance(Point { x: 4., y: 5. }), Some(5.));
+        assert_eq!(polygon(&[], true).distance(Point::zero()), None);
+    }
+
+    #[test]
+    fn area_test() {
+        let square = polygon(&[(0., 0.), (2., 0.), (2., 2.), (0., 2.)], true);
+        assert_eq!(square.signed_area(), 4.);
+        assert_eq!(square.centroid(), Some(Point { x: 1., y: 1. }));
+        assert_eq!(
+            square.bbox(),
+            Some((Point { x: 0., y: 0. }, Point { x: 2., y: 2. }))
+        );
+
+        let triangle = polygon(&[(0., 0.), (0., 3.), (3., 0.)], true);
+        assert_eq!(triangle.signed_area(), -4.5);
+        assert_eq!(triangle.area(), 4.5);
+        assert_eq!(triangle.centroid(), Some(Point { x: 1., y: 1. }));
+
+        let line = polygon(&[(0., 0.), (1., 1.), (2., 2.)], true);
+        assert_eq!(line.area(), 0.);
+        assert_eq!(line.centroid(), None);
+        let open = polygon(&[(0., 0.), (2., 0.), (2., 2.)], false);
+        assert_eq!(open.area(), 0.);
+        assert_eq!(
+            open.bbox(),
+            Some((Point { x: 0., y: 0. }, Point { x: 2., y: 2. }))
+        );
+        assert_eq!(polygon(&[], true).bbox(), None);
+    }
+
+    #[test]
+    fn segments_test() {
+        let square = polygon(&[(0., 0.), (2., 0.), (2., 2.), (0., 2.)], true);
+        assert_eq!(square.segments().count(), 4);
+        let open = polygon(&[(0., 0.), (2., 0.), (2., 2.), (0., 2.)], false);
+        assert_eq!(open.segments().count(), 3);
+        assert_eq!(polygon(&[(0., 0.), (2., 0.)], true).segments().count(), 1);
+        assert_eq!(polygon(&[(0., 0.)], true).segments().count(), 0);
+        assert_eq!(polygon(&[], true).segments().count(), 0);
+        assert_eq!(polygon(&[], false).segments().count(), 0);
+    }
+
+    #[test]
+    fn self_intersection_test() {
+        let square = polygon(&[(0., 0.), (2., 0.), (2., 2.), (0., 2.)], true);
+        assert!(square.is_simple());
+
+        // the diagonals of the square
+        let bowtie = polygon(&[(0., 0.), (2., 2.), (2., 0.), (0., 2.)], true);
+        assert_eq!(
+            bowtie.self_intersections(),
+            vec![(0, 2, Point { x: 1., y: 1. })]
+        );
+
+        // only the closing edge crosses another one
+        let z = polygon(&[(0., 0.), (2., 0.), (0., 2.), (2., 2.)], false);
+        assert!(z.is_simple());
+        let closed = polygon(&[(0., 0.), (2., 0.), (0., 2.), (2., 2.)], true);
+        assert_eq!(
+            closed.self_intersections(),
+            vec![(1, 3, Point { x: 1., y: 1. })]
+        );
+        let zigzag = polygon(&[(0., 0.), (2., 2.), (2., 0.), (0., 2.)], false);
+        assert!(!zigzag.is_simple());
+    }
+}
@@ -0,0 +1,255 @@
+//! Rasterisation of a `PolyMap` into an occupancy grid, like the maps of the robots.
+
+use ndarray::Array2;
+
+use super::{Distance, Point, PolyMap, Polygon, Segment};
+use crate::ai::CellState;
+
+/// Square cells of `resolution` meters, the cell (0, 0) being centered on `origin`.
+/// As in the images of the maps, the x axis of the grid is the one of the world,
+/// and its y axis points down.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Grid {
+    pub origin: Point,
+    pub resolution: Distance,
+    /// Number of columns and of rows
+    pub size: (usize, usize),
+}
+
+impl Grid {
+    /// Coordinates of the point in the grid, the cells being centered on integers
+    pub fn to_grid(&self, p: Point) -> (f32, f32) {
+        (
+            (p.x - self.origin.x) / self.resolution,
+            (self.origin.y - p.y) / self.resolution,
+        )
+    }
+
+    /// Center of a cell
+    pub fn center(&self, (x, y): (usize, usize)) -> Point {
+        Point {
+            x: self.origin.x + x as Distance * self.resolution,
+            y: self.origin.y - y as Distance * self.resolution,
+        }
+    }
+
+    /// Cell containing the point, if in the grid
+    pub fn cell(&self, p: Point) -> Option<(usize, usize)> {
+        let (x, y) = self.to_grid(p);
+        self.index((x.round() as i64, y.round() as i64))
+    }
+
+    fn index(&self, (x, y): (i64, i64)) -> Option<(usize, usize)> {
+        if x < 0 || y < 0 || x as usize >= self.size.0 || y as usize >= self.size.1 {
+            None
+        } else {
+            Some((x as usize, y as usize))
+        }
+    }
+
+    /// Marks the cells of the grid crossed by the segment, visiting them in order
+    fn draw_segment(&self, segment: &Segment, cells: &mut Array2<CellState>) {
+        // shifted so that the cells span from an integer to the next one
+        let (a, b) = (self.to_grid(segment.0), self.to_grid(segment.1));
+        let (a, b) = ((a.0 + 0.5, a.1 + 0.5), (b.0 + 0.5, b.1 + 0.5));
+        let (mut x, mut y) = (a.0.floor() as i64, a.1.floor() as i64);
+        let end = (b.0.floor() as i64, b.1.floor() as i64);
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let step = (dx.signum() as i64, dy.signum() as i64);
+        // distance along the segment, as a fraction, to the next vertical and horizontal borders
+        let next_border = |start: f32, cell: i64, d: f32| {
+            if d == 0. {
+                f32::INFINITY
+            } else {
+                let border = if d > 0. { cell + 1 } else { cell };
+                (border as f32 - start) / d
+            }
+        };
+        let (mut t_x, mut t_y) = (next_border(a.0, x, dx), next_border(a.1, y, dy));
+        let (delta_x, delta_y) = (1. / dx.abs(), 1. / dy.abs());
+        // bounded by the number of borders crossed, whatever the rounding errors
+        let steps = (end.0 - x).abs() + (end.1 - y).abs();
+        for _ in 0..=steps {
+            if let Some(cell) = self.index((x, y)) {
+                cells[cell] = CellState::Blocked;
+            }
+            if (x, y) == end {
+                break;
+            }
+            if t_x < t_y {
+                x += step.0;
+                t_x += delta_x;
+            } else {
+                y += step.1;
+                t_y += delta_y;
+            }
+        }
+    }
+
+    /// Whether the center of each cell is inside the closed polygon, by the even-odd rule
+    fn fill(&self, polygon: &Polygon) -> Array2<bool> {
+        let mut inside = Array2::from_elem(self.size, false);
+        let points: Vec<(f32, f32)> = polygon.points.iter().map(|&p| self.to_grid(p)).collect();
+        for row in 0..self.size.1 {
+            let y = row as f32;
+            let mut crossings: Vec<f32> = points
+                .iter()
+                .zip(points.iter().cycle().skip(1))
+                .filter(|(a, b)| (a.1 <= y) != (b.1 <= y))
+                .map(|(a, b)| a.0 + (y - a.1) / (b.1 - a.1) * (b.0 - a.0))
+                .collect();
+            crossings.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            for pair in crossings.chunks(2) {
+                if let [from, to] = pair {
+                    let from = from.ceil().max(0.) as usize;
+                    let to = (to.ceil().max(0.) as usize).min(self.size.0);
+                    for column in from..to {
+                        inside[(column, row)] = true;
+                    }
+                }
+            }
+        }
+        inside
+    }
+}
+
+impl PolyMap {
+    /// The walls of the map, the other cells being uncharted
+    pub fn rasterize_edges(&self, grid: &Grid) -> Array2<CellState> {
+        let mut cells = Array2::default(grid.size);
+        for segment in self.segments() {
+            grid.draw_segment(&segment, &mut cells);
+        }
+        cells
+    }
+
+    /// The map as seen by a robot knowing it all: the walls and the insides of the closed
+    /// polygons are blocked, the other cells free.
+    /// A closed polygon enclosing others is the outer wall of the world, its outside is blocked.
+    pub fn rasterize(&self, grid: &Grid) -> Array2<CellState> {
+        let mut cells = self.rasterize_edges(grid);
+        let mut blocked = Array2::from_elem(grid.size, false);
+        for polygon in self.polygons.iter().filter(|p| p.is_closed) {
+            let bounds = self.is_outer_wall(polygon);
+            let inside = grid.fill(polygon);
+            blocked.zip_mut_with(&inside, |blocked, &inside| *blocked |= inside != bounds);
+        }
+        cells.zip_mut_with(&blocked, |cell, &blocked| {
+            if *cell == CellState::Uncharted {
+                *cell = if blocked {
+                    CellState::Blocked
+                } else {
+                    CellState::SeenFree
+                };
+            }
+        });
+        cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn example() -> PolyMap {
+        PolyMap::from_file(&PathBuf::from("map_example.json")).unwrap()
+    }
+
+    #[test]
+    fn grid_test() {
+        let grid = Grid {
+            origin: Point { x: -1., y: 2. },
+            resolution: 0.5,
+            size: (5, 4),
+        };
+        assert_eq!(grid.cell(Point { x: -1., y: 2. }), Some((0, 0)));
+        assert_eq!(grid.cell(Point { x: 0.1, y: 0.6 }), Some((2, 3)));
+        assert_eq!(grid.center((2, 3)), Point { x: 0., y: 0.5 });
+        assert_eq!(grid.cell(Point { x: -1.5, y: 0. }), None);
+        assert_eq!(grid.cell(Point { x: 0., y: 0. }), None);
+    }
+
+    #[test]
+    fn rasterize_edges_test() {
+        let grid = Grid {
+            origin: Point { x: -1., y: 3. },
+            resolution: 0.1,
+            size: (80, 50),
+        };
+        let cells = example().rasterize_edges(&grid);
+        let at = |x, y| cells[grid.cell(Point { x, y }).unwrap()];
+        // the walls of the rectangle from (0, 0) to (5, 2)
+        assert_eq!(at(0., 1.), CellState::Blocked);
+        assert_eq!(at(2.5, 2.), CellState::Blocked);
+        assert_eq!(at(5., 0.3), CellState::Blocked);
+        assert_eq!(at(2.5, 1.), CellState::Uncharted);
+        assert_eq!(at(-0.5, 1.), CellState::Uncharted);
+        let blocked = cells.iter().filter(|&&c| c == CellState::Blocked).count();
+        assert_eq!(blocked, 2 * (51 + 21) - 4);
+
+        // a diagonal crosses a cell in each row and column, the cells touching by their sides
+        let diagonal = PolyMap::new(vec![Polygon {
+            points: vec![Point { x: -0.93, y: 2.9 }, Point { x: 3.07, y: 0.11 }],
+            is_closed: false,
+        }]);
+        let cells = diagonal.rasterize_edges(&grid);
+        let blocked: Vec<_> = cells
+            .indexed_iter()
+            .filter(|(_, &c)| c == CellState::Blocked)
+            .map(|(xy, _)| xy)
+            .collect();
+        assert!(blocked.len() >= 41 + 29 - 1);
+        for xy in blocked.iter() {
+            assert!(blocked.iter().any(|&(x, y)| (x, y) != *xy
+                && x.max(xy.0) - x.min(xy.0) + y.max(xy.1) - y.min(xy.1) == 1));
+        }
+    }
+
+    #[test]
+    fn rasterize_test() {
+        let grid = Grid {
+            origin: Point { x: -1., y: 3. },
+            resolution: 0.1,
+            size: (80, 50),
+        };
+        let cells = example().rasterize(&grid);
+        let at = |x, y| cells[grid.cell(Point { x, y }).unwrap()];
+        assert_eq!(at(2.5, 1.), CellState::Blocked);
+        assert_eq!(at(0., 1.), CellState::Blocked);
+        assert_eq!(at(-0.5, 1.), CellState::SeenFree);
+        assert_eq!(at(5.5, 2.5), CellState::SeenFree);
+        assert_eq!(at(2.5, -1.), CellState::SeenFree);
+        let blocked = cells.iter().filter(|&&c| c == CellState::Blocked).count();
+        assert_eq!(blocked, 51 * 21);
+        assert!(cells.iter().all(|&c| c != CellState::Uncharted));
+
+        // out of the outer wall of the world
+        let grid = Grid {
+            origin: Point { x: -12., y: 12. },
+            resolution: 1.,
+            size: (25, 25),
+        };
+        let cells = example().rasterize(&grid);
+        let at = |x, y| cells[grid.cell(Point { x, y }).unwrap()];
+        assert_eq!(at(-11., 0.), CellState::Blocked);
+        assert_eq!(at(0., 11.), CellState::Blocked);
+        assert_eq!(at(-10., 0.), CellState::Blocked);
+        assert_eq!(at(-5., -5.), CellState::SeenFree);
+        assert_eq!(at(3., 1.), CellState::Blocked);
+
+        // an obstacle alone
+        let grid = Grid {
+            origin: Point { x: -1., y: 1.5 },
+            resolution: 0.01,
+            size: (200, 300),
+        };
+        let obstacle = PolyMap::from_file(&PathBuf::from("map.json")).unwrap();
+        let cells = obstacle.rasterize(&grid);
+        let at = |x, y| cells[grid.cell(Point { x, y }).unwrap()];
+        assert_eq!(at(0.05, 1.), CellState::Blocked);
+        assert_eq!(at(0.3, 1.), CellState::Blocked);
+        assert_eq!(at(0.5, 1.), CellState::SeenFree);
+        assert_eq!(at(-0.5, -1.), CellState::SeenFree);
+    }
+}
@@ -0,0 +1,665 @@
+//! SVG import and export of the maps, so that the worlds can be drawn in an editor
+//! and the runs shared as pictures.
+//! The y axis of the SVG points down, it is flipped keeping the origin. The walls are the
+//! paths, polylines, polygons, rects and lines, the elements of class `trajectory`
+//! being skipped so that an exported map can be edited and imported again.
+
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+use roxmltree::{Document, Node};
+use svgtypes::{Length, LengthUnit, PathParser, PathSegment, PointsParser, Transform, ViewBox};
+
+use super::{Distance, Point, PolyMap, Polygon};
+
+/// Segments approximating a curve or an arc
+const CURVE_STEPS: usize = 16;
+/// Meters in an inch, and in a user unit of the exported maps, the millimeter
+const INCH: f64 = 0.0254;
+const EXPORT_UNIT: f64 = 0.001;
+/// Colors of the trajectories, in turn
+const COLORS: [&str; 6] = [
+    "#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b",
+];
+
+/// Points in user units, and whether they are closed
+type Shape = (Vec<(f64, f64)>, bool);
+
+#[derive(Debug)]
+pub enum SvgError {
+    Xml(roxmltree::Error),
+    /// An attribute of an element which could not be parsed
+    Attribute {
+        element: String,
+        attribute: &'static str,
+        value: String,
+    },
+}
+
+impl fmt::Display for SvgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SvgError::Xml(e) => write!(f, "{}", e),
+            SvgError::Attribute {
+                element,
+                attribute,
+                value,
+            } => write!(f, "invalid {} \"{}\" of a {}", attribute, value, element),
+        }
+    }
+}
+
+impl std::error::Error for SvgError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SvgError::Xml(e) => Some(e),
+            SvgError::Attribute { .. } => None,
+        }
+    }
+}
+
+fn invalid(node: Node, attribute: &'static str) -> SvgError {
+    SvgError::Attribute {
+        element: node.tag_name().name().to_string(),
+        attribute,
+        value: node.attribute(attribute).unwrap_or_default().to_string(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Length in meters of a unit, the relative ones being taken as pixels
+fn meters_per(unit: LengthUnit) -> f64 {
+    match unit {
+        LengthUnit::In => INCH,
+        LengthUnit::Cm => 0.01,
+        LengthUnit::Mm => 0.001,
+        LengthUnit::Pt => INCH / 72.,
+        LengthUnit::Pc => INCH / 6.,
+        _ => INCH / 96.,
+    }
+}
+
+/// Meters per user unit, given by the width of the document and its view box
+fn document_scale(svg: Node) -> Result<f64, SvgError> {
+    let width = match svg.attribute("width") {
+        Some(width) => Length::from_str(width).map_err(|_| invalid(svg, "width"))?,
+        None => return Ok(meters_per(LengthUnit::Px)),
+    };
+    let view_box = match svg.attribute("viewBox") {
+        Some(view_box) => ViewBox::from_str(view_box).map_err(|_| invalid(svg, "viewBox"))?,
+        None => return Ok(meters_per(LengthUnit::Px)),
+    };
+    match width.unit {
+        LengthUnit::Percent | LengthUnit::Em | LengthUnit::Ex => Ok(meters_per(LengthUnit::Px)),
+        unit => Ok(width.number * meters_per(unit) / view_box.w),
+    }
+}
+
+/// A number of user units
+fn number(node: Node, attribute: &'static str) -> Result<f64, SvgError> {
+    match node.attribute(attribute) {
+        None => Ok(0.),
+        Some(value) => match Length::from_str(value) {
+            Ok(Length {
+                number,
+                unit: LengthUnit::None,
+            })
+            | Ok(Length {
+                number,
+                unit: LengthUnit::Px,
+            }) => Ok(number),
+            _ => Err(invalid(node, attribute)),
+        },
+    }
+}
+
+/// `outer` applied after `inner`
+fn compose(outer: &Transform, inner: &Transform) -> Transform {
+    Transform::new(
+        outer.a * inner.a + outer.c * inner.b,
+        outer.b * inner.a + outer.d * inner.b,
+        outer.a * inner.c + outer.c * inner.d,
+        outer.b * inner.c + outer.d * inner.d,
+        outer.a * inner.e + outer.c * inner.f + outer.e,
+        outer.b * inner.e + outer.d * inner.f + outer.f,
+    )
+}
+
+/// Points of a cubic Bézier curve, after its start
+fn cubic(p0: (f64, f64), p1: (f64, f64), p2: (f64, f64), p3: (f64, f64)) -> Vec<(f64, f64)> {
+    (1..=CURVE_STEPS)
+        .map(|i| {
+            let t = i as f64 / CURVE_STEPS as f64;
+            let u = 1. - t;
+            let (w0, w1, w2, w3) = (u * u * u, 3. * u * u * t, 3. * u * t * t, t * t * t);
+            (
+                w0 * p0.0 + w1 * p1.0 + w2 * p2.0 + w3 * p3.0,
+                w0 * p0.1 + w1 * p1.1 + w2 * p2.1 + w3 * p3.1,
+            )
+        })
+        .collect()
+}
+
+/// Points of a quadratic Bézier curve, after its start
+fn quadratic(p0: (f64, f64), p1: (f64, f64), p2: (f64, f64)) -> Vec<(f64, f64)> {
+    let control = |p: f64, c: f64| p + 2. / 3. * (c - p);
+    cubic(
+        p0,
+        (control(p0.0, p1.0), control(p0.1, p1.1)),
+        (control(p2.0, p1.0), control(p2.1, p1.1)),
+        p2,
+    )
+}
+
+/// Points of an elliptical arc of the path data, after its start,
+/// by the conversion to its center of the SVG specification
+fn arc(
+    from: (f64, f64),
+    (rx, ry): (f64, f64),
+    rotation: f64,
+    large_arc: bool,
+    sweep: bool,
+    to: (f64, f64),
+) -> Vec<(f64, f64)> {
+    if from == to {
+        return vec![];
+    }
+    let (mut rx, mut ry) = (rx.abs(), ry.abs());
+    if rx == 0. || ry == 0. {
+        return vec![to];
+    }
+    let (sin, cos) = rotation.to_radians().sin_cos();
+    let (dx, dy) = ((from.0 - to.0) / 2., (from.1 - to.1) / 2.);
+    let (x1, y1) = (cos * dx + sin * dy, -sin * dx + cos * dy);
+    // the radii are scaled up when too small to join the ends
+    let lambda = (x1 * x1) / (rx * rx) + (y1 * y1) / (ry * ry);
+    if lambda > 1. {
+        rx *= lambda.sqrt();
+        ry *= lambda.sqrt();
+    }
+    let numerator = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+    let denominator = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+    let sign = if large_arc == sweep { -1. } else { 1. };
+    let coef = sign * (numerator / denominator).max(0.).sqrt();
+    let (cx1, cy1) = (coef * rx * y1 / ry, -coef * ry * x1 / rx);
+    let center = (
+        cos * cx1 - sin * cy1 + (from.0 + to.0) / 2.,
+        sin * cx1 + cos * cy1 + (from.1 + to.1) / 2.,
+    );
+    let angle =
+        |(ux, uy): (f64, f64), (vx, vy): (f64, f64)| (ux * vy - uy * vx).atan2(ux * vx + uy * vy);
+    let u = ((x1 - cx1) / rx, (y1 - cy1) / ry);
+    let v = ((-x1 - cx1) / rx, (-y1 - cy1) / ry);
+    let start = angle((1., 0.), u);
+    let mut delta = angle(u, v);
+    if !sweep && delta > 0. {
+        delta -= 2. * PI;
+    } else if sweep && delta < 0. {
+        delta += 2. * PI;
+    }
+    let mut points: Vec<(f64, f64)> = (1..CURVE_STEPS)
+        .map(|i| {
+            let (sin_t, cos_t) = (start + delta * i as f64 / CURVE_STEPS as f64).sin_cos();
+            (
+                center.0 + rx * cos_t * cos - ry * sin_t * sin,
+                center.1 + rx * cos_t * sin + ry * sin_t * cos,
+            )
+        })
+        .collect();
+    points.push(to);
+    points
+}
+
+/// The subpaths of the path data, as their points and whether they are closed
+fn path(node: Node) -> Result<Vec<Shape>, SvgError> {
+    let mut subpaths = Vec::new();
+    let mut points: Vec<(f64, f64)> = Vec::new();
+    let (mut current, mut start) = ((0., 0.), (0., 0.));
+    // the control points of the previous curve, reflected by the smooth ones
+    let (mut last_cubic, mut last_quadratic) = (None, None);
+    let d = node.attribute("d").unwrap_or_default();
+    for segment in PathParser::from(d) {
+        let segment = segment.map_err(|_| invalid(node, "d"))?;
+        let at = |abs: bool, x: f64, y: f64| {
+            if abs {
+                (x, y)
+            } else {
+                (current.0 + x, current.1 + y)
+            }
+        };
+        let reflect = |control: Option<(f64, f64)>| match control {
+            Some((x, y)) => (2. * current.0 - x, 2. * current.1 - y),
+            None => current,
+        };
+        let (mut next_cubic, mut next_quadratic) = (None, None);
+        match segment {
+            PathSegment::MoveTo { abs, x, y } => {
+                if points.len() > 1 {
+                    subpaths.push((points, false));
+                }
+                current = at(abs, x, y);
+                start = current;
+                points = vec![current];
+            }
+            PathSegment::LineTo { abs, x, y } => {
+                current = at(abs, x, y);
+                points.push(current);
+            }
+            PathSegment::HorizontalLineTo { abs, x } => {
+                current = (if abs { x } else { current.0 + x }, current.1);
+                points.push(current);
+            }
+            PathSegment::VerticalLineTo { abs, y } => {
+                current = (current.0, if abs { y } else { current.1 + y });
+                points.push(current);
+            }
+            PathSegment::CurveTo {
+                abs,
+                x1,
+                y1,
+                x2,
+                y2,
+                x,
+                y,
+            } => {
+                let (c1, c2, end) = (at(abs, x1, y1), at(abs, x2, y2), at(abs, x, y));
+                points.extend(cubic(current, c1, c2, end));
+                next_cubic = Some(c2);
+                current = end;
+            }
+            PathSegment::SmoothCurveTo { abs, x2, y2, x, y } => {
+                let (c1, c2, end) = (reflect(last_cubic), at(abs, x2, y2), at(abs, x, y));
+                points.extend(cubic(current, c1, c2, end));
+                next_cubic = Some(c2);
+                current = end;
+            }
+            PathSegment::Quadratic { abs, x1, y1, x, y } => {
+                let (c, end) = (at(abs, x1, y1), at(abs, x, y));
+                points.extend(quadratic(current, c, end));
+                next_quadratic = Some(c);
+                current = end;
+            }
+            PathSegment::SmoothQuadratic { abs, x, y } => {
+                let (c, end) = (reflect(last_quadratic), at(abs, x, y));
+                points.extend(quadratic(current, c, end));
+                next_quadratic = Some(c);
+                current = end;
+            }
+            PathSegment::EllipticalArc {
+                abs,
+                rx,
+                ry,
+                x_axis_rotation,
+                large_arc,
+                sweep,
+                x,
+                y,
+            } => {
+                let end = at(abs, x, y);
+                points.extend(arc(
+                    current,
+                    (rx, ry),
+                    x_axis_rotation,
+                    large_arc,
+                    sweep,
+                    end,
+                ));
+                current = end;
+            }
+            PathSegment::ClosePath { .. } => {
+                if points.len() > 1 {
+                    subpaths.push((points, true));
+                }
+                current = start;
+                points = vec![current];
+            }
+        }
+        last_cubic = next_cubic;
+        last_quadratic = next_quadratic;
+    }
+    if points.len() > 1 {
+        subpaths.push((points, false));
+    }
+    Ok(subpaths)
+}
+
+/// The walls drawn by an element, in its user units
+fn shapes(node: Node) -> Result<Vec<Shape>, SvgError> {
+    let points =
+        |node: Node| PointsParser::from(node.attribute("points").unwrap_or_default()).collect();
+    Ok(match node.tag_name().name() {
+        "path" => path(node)?,
+        "polyline" => vec![(points(node), false)],
+        "polygon" => vec![(points(node), true)],
+        "line" => {
+            let start = (number(node, "x1")?, number(node, "y1")?);
+            vec![(
+                vec![start, (number(node, "x2")?, number(node, "y2")?)],
+                false,
+            )]
+        }
+        "rect" => {
+            let (x, y) = (number(node, "x")?, number(node, "y")?);
+            let (width, height) = (number(node, "width")?, number(node, "height")?);
+            let corners = vec![
+                (x, y),
+                (x + width, y),
+                (x + width, y + height),
+                (x, y + height),
+            ];
+            vec![(corners, true)]
+        }
+        _ => vec![],
+    })
+}
+
+/// Walks the tree, `transform` being the one from the user units of the node to the meters
+/// of the map
+fn import(node: Node, transform: &Transform, polygons: &mut Vec<Polygon>) -> Result<(), SvgError> {
+    if node
+        .attribute("class")
+        .unwrap_or_default()
+        .split_whitespace()
+        .any(|c| c == "trajectory")
+    {
+        return Ok(());
+    }
+    if let "defs" | "clipPath" | "mask" | "marker" | "pattern" | "symbol" = node.tag_name().name() {
+        return Ok(());
+    }
+    let transform = match node.attribute("transform") {
+        Some(value) => {
+            let inner = Transform::from_str(value).map_err(|_| invalid(node, "transform"))?;
+            compose(transform, &inner)
+        }
+        None => *transform,
+    };
+    for (shape, is_closed) in shapes(node)? {
+        let mut points: Vec<Point> = shape
+            .iter()
+            .map(|&(x, y)| Point {
+                x: (transform.a * x + transform.c * y + transform.e) as Distance,
+                y: (transform.b * x + transform.d * y + transform.f) as Distance,
+            })
+            .collect();
+        // the editors often repeat the first point to close a shape
+        points.dedup();
+        if is_closed && points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+        polygons.push(Polygon { points, is_closed });
+    }
+    for child in node.children().filter(Node::is_element) {
+        import(child, &transform, polygons)?;
+    }
+    Ok(())
+}
+
+impl PolyMap {
+    /// Reads the walls of an SVG document, `scale` being the meters per user unit,
+    /// given by the width and view box of the document if not set
+    pub fn from_svg(svg: &str, scale: Option<Distance>) -> Result<Self, SvgError> {
+        let document = Document::parse(svg).map_err(SvgError::Xml)?;
+        let root = document.root_element();
+        let scale = match scale {
+            Some(scale) => scale as f64,
+            None => document_scale(root)?,
+        };
+        let flip = Transform::new(scale, 0., 0., -scale, 0., 0.);
+        let mut polygons = Vec::new();
+        import(root, &flip, &mut polygons)?;
+        Ok(PolyMap::new(polygons))
+    }
+
+    /// The map, with the trajectories of the robots on it, in millimeters
+    pub fn to_svg<'a>(
+        &self,
+        trajectories: impl IntoIterator<Item = (String, &'a [Point])>,
+    ) -> String {
+        let trajectories: Vec<_> = trajectories.into_iter().collect();
+        // rounded to the precision of the map, and without -0
+        let to_svg = |p: &Point| {
+            (
+                (p.x as f64 / EXPORT_UNIT) as f32 + 0.,
+                (-p.y as f64 / EXPORT_UNIT) as f32 + 0.,
+            )
+        };
+        let all_points = self
+            .polygons
+            .iter()
+            .flat_map(|polygon| polygon.points.iter())
+            .chain(trajectories.iter().flat_map(|(_, points)| points.iter()))
+            .map(to_svg);
+        let (mut min, mut max) = (
+            (f32::INFINITY, f32::INFINITY),
+            (f32::NEG_INFINITY, f32::NEG_INFINITY),
+        );
+        for (x, y) in all_points {
+            min = (min.0.min(x), min.1.min(y));
+            max = (max.0.max(x), max.1.max(y));
+        }
+        if min.0 > max.0 {
+            min = (0., 0.);
+            max = (0., 0.);
+        }
+        let size = (max.0 - min.0).max(max.1 - min.1).max(1.);
+        let margin = size / 20.;
+        let (width, height) = (max.0 - min.0 + 2. * margin, max.1 - min.1 + 2. * margin);
+        let stroke = size / 400.;
+        let list = |points: &[Point]| {
+            points
+                .iter()
+                .map(|p| {
+                    let (x, y) = to_svg(p);
+                    format!("{},{}", x, y)
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        let mut svg = vec![
+            format!(
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}mm\" height=\"{}mm\" \
+                 viewBox=\"{} {} {} {}\">",
+                width,
+                height,
+                min.0 - margin,
+                min.1 - margin,
+                width,
+                height
+            ),
+            format!(
+                "  <g id=\"map\" fill=\"none\" stroke=\"black\" stroke-width=\"{}\" \
+                 stroke-linejoin=\"round\">",
+                2. * stroke
+            ),
+        ];
+        for polygon in self.polygons.iter() {
+            let element = if polygon.is_closed {
+                "polygon"
+            } else {
+                "polyline"
+            };
+            svg.push(format!(
+                "    <{} points=\"{}\"/>",
+                element,
+                list(&polygon.points)
+            ));
+        }
+        svg.push("  </g>".to_string());
+        svg.push(format!(
+            "  <g id=\"trajectories\" class=\"trajectory\" fill=\"none\" stroke-width=\"{}\" \
+             stroke-linejoin=\"round\">",
+            stroke
+        ));
+        for (i, (name, points)) in trajectories.iter().enumerate() {
+            let color = COLORS[i % COLORS.len()];
+            svg.push(format!("    <g stroke=\"{}\" fill=\"{}\">", color, color));
+            svg.push(format!("      <title>{}</title>", escape(name)));
+            svg.push(format!(
+                "      <polyline fill=\"none\" points=\"{}\"/>",
+                list(points)
+            ));
+            // a ring at the start of the trajectory, a dot at its end
+            if let (Some(first), Some(last)) = (points.first(), points.last()) {
+                let ((x0, y0), (x1, y1)) = (to_svg(first), to_svg(last));
+                svg.push(format!(
+                    "      <circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"none\"/>",
+                    x0,
+                    y0,
+                    4. * stroke
+                ));
+                svg.push(format!(
+                    "      <circle cx=\"{}\" cy=\"{}\" r=\"{}\"/>",
+                    x1,
+                    y1,
+                    4. * stroke
+                ));
+            }
+            svg.push("    </g>".to_string());
+        }
+        svg.push("  </g>".to_string());
+        svg.push("</svg>\n".to_string());
+        svg.join("\n")
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod tests {
+    use super::*;
+
+    fn close(a: Point, b: Point) -> bool {
+        a.sq_dist(b).sqrt() < 1e-4
+    }
+
+    #[test]
+    fn import_test() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10cm" height="5cm" viewBox="0 0 100 50">
+            <rect x="0" y="0" width="100" height="50"/>
+            <g transform="translate(10, 20)">
+                <polyline points="0,0 10,0 10,10"/>
+                <polygon points="20 0 30 0 30 10 20 0" transform="scale(2)"/>
+            </g>
+            <path d="M 50 10 h 10 v 10 H 50 z m 20 0 l 10 0"/>
+            <path d="M 0 40 A 5 5 0 0 1 10 40"/>
+            <line x1="90" y1="0" x2="90" y2="50"/>
+            <defs><rect width="5" height="5"/></defs>
+            <g class="trajectory"><polyline points="1,1 2,2"/></g>
+            <circle cx="5" cy="5" r="1"/>
+        </svg>"#;
+        let map = PolyMap::from_svg(svg, None).unwrap();
+        let p = |x, y| Point { x, y };
+        let points: Vec<(&Vec<Point>, bool)> = map
+            .polygons
+            .iter()
+            .map(|polygon| (&polygon.points, polygon.is_closed))
+            .collect();
+        assert_eq!(points.len(), 7);
+        // a millimeter per user unit, the y axis flipped
+        assert_eq!(
+            points[0],
+            (
+                &vec![p(0., 0.), p(0.1, 0.), p(0.1, -0.05), p(0., -0.05)],
+                true
+            )
+        );
+        assert_eq!(
+            points[1],
+            (&vec![p(0.01, -0.02), p(0.02, -0.02), p(0.02, -0.03)], false)
+        );
+        // the repeated first point is dropped
+        assert_eq!(
+            points[2],
+            (&vec![p(0.05, -0.02), p(0.07, -0.02), p(0.07, -0.04)], true)
+        );
+        assert_eq!(
+            points[3],
+            (
+                &vec![
+                    p(0.05, -0.01),
+                    p(0.06, -0.01),
+                    p(0.06, -0.02),
+                    p(0.05, -0.02)
+                ],
+                true
+            )
+        );
+        // the relative move is from the start of the closed subpath
+        assert_eq!(points[4], (&vec![p(0.07, -0.01), p(0.08, -0.01)], false));
+        // a half circle
+        let (half_circle, is_closed) = points[5];
+        assert!(!is_closed);
+        assert_eq!(half_circle.len(), CURVE_STEPS + 1);
+        let center = p(0.005, -0.04);
+        assert!(half_circle
+            .iter()
+            .all(|&q| (q.sq_dist(center).sqrt() - 0.005).abs() < 1e-6));
+        assert!(close(half_circle[CURVE_STEPS / 2], p(0.005, -0.035)));
+        assert_eq!(points[6], (&vec![p(0.09, 0.), p(0.09, -0.05)], false));
+
+        // without units, pixels unless scaled
+        let svg = r#"<svg><rect x="0" y="0" width="96" height="96"/></svg>"#;
+        let map = PolyMap::from_svg(svg, None).unwrap();
+        assert_eq!(map.polygons[0].points[2], p(0.0254, -0.0254));
+        let map = PolyMap::from_svg(svg, Some(0.01)).unwrap();
+        assert_eq!(map.polygons[0].points[2], p(0.96, -0.96));
+    }
+
+    #[test]
+    fn curve_test() {
+        let svg =
+            r#"<svg><path d="M 0 0 C 0 10 10 10 10 0 S 20 -10 20 0 Q 25 10 30 0 T 40 0"/></svg>"#;
+        let map = PolyMap::from_svg(svg, Some(1.)).unwrap();
+        let points = &map.polygons[0].points;
+        assert_eq!(points.len(), 4 * CURVE_STEPS + 1);
+        let p = |x, y| Point { x, y };
+        assert!(close(points[CURVE_STEPS / 2], p(5., -7.5)));
+        // the smooth curve mirrors the previous one
+        assert!(close(points[3 * CURVE_STEPS / 2], p(15., 7.5)));
+        assert!(close(points[2 * CURVE_STEPS], p(20., 0.)));
+        assert!(close(points[5 * CURVE_STEPS / 2], p(25., -5.)));
+        assert!(close(points[7 * CURVE_STEPS / 2], p(35., 5.)));
+        assert!(close(points[4 * CURVE_STEPS], p(40., 0.)));
+    }
+
+    #[test]
+    fn invalid_test() {
+        assert!(matches!(
+            PolyMap::from_svg("<svg><rect></svg>", None),
+            Err(SvgError::Xml(_))
+        ));
+        let error = PolyMap::from_svg(r#"<svg><rect width="10%"/></svg>"#, None).unwrap_err();
+        assert_eq!(error.to_string(), "invalid width \"10%\" of a rect");
+        assert!(PolyMap::from_svg(r#"<svg><path d="M 0 0 L 1"/></svg>"#, None).is_err());
+    }
+
+    #[test]
+    fn export_test() {
+        let map = PolyMap::default();
+        let trajectory = vec![Point { x: 0., y: 0. }, Point { x: 1., y: 0.5 }];
+        let svg = map.to_svg(vec![("robot 1".to_string(), trajectory.as_slice())]);
+        assert!(svg.contains("<title>robot 1</title>"));
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"22000mm\""));
+        assert!(svg.contains("points=\"0,0 1000,-500\""));
+
+        // the trajectories are not walls
+        let imported = PolyMap::from_svg(&svg, None).unwrap();
+        assert_eq!(imported.polygons.len(), map.polygons.len());
+        for (a, b) in imported.polygons.iter().zip(map.polygons.iter()) {
+            assert_eq!(a.is_closed, b.is_closed);
+            assert_eq!(a.points.len(), b.points.len());
+            assert!(a
+                .points
+                .iter()
+                .zip(b.points.iter())
+                .all(|(&p, &q)| close(p, q)));
+        }
+    }
+}
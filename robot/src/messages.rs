@@ -1,10 +1,14 @@
 use serde::{Deserialize, Serialize};
 
 use super::app::AppId;
-use crate::ai::CellState;
+use crate::ai::{CellState, Digest, ProvenancePatch, Tile, TileId, UpdateSeq};
 use crate::map::Position;
+use crate::mission::Command;
+use crate::raft;
 use ndarray::Array2;
 
+use MsgContent::*;
+
 pub type MsgId = u32;
 
 /// Defines message type
@@ -12,7 +16,28 @@ pub type MsgId = u32;
 pub enum MsgContent {
     Private(AppId, String),
     Public(String),
-    MapUpdate(Array2<CellState>),
+    /// The map of the sender, with the provenance of the tiles which changed
+    MapUpdate(Array2<CellState>, UpdateSeq, Vec<ProvenancePatch>),
+    /// The given robot should send the provenance of all its tiles, the sender missed updates
+    UpdateRequest(AppId),
+    /// Digests of the tiles of the map of the sender, for anti-entropy
+    Digests(Vec<Digest>),
+    /// Tiles of the given robot wanted by the sender
+    TileRequest(AppId, Vec<TileId>),
+    /// Tiles of the sender for the given robot
+    Tiles(AppId, Vec<Tile>),
+    /// Consensus on the mission
+    Raft(raft::Message<Command>),
+}
+
+impl MsgContent {
+    /// Anti-entropy is between neighbours, these messages are not relayed
+    pub fn is_relayed(&self) -> bool {
+        match self {
+            Digests(_) | TileRequest(..) | Tiles(..) => false,
+            Private(..) | Public(_) | MapUpdate(..) | UpdateRequest(_) | Raft(_) => true,
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
@@ -35,11 +60,26 @@ impl Msg {
     pub fn serialize(&self) -> serde_json::Result<String> {
         serde_json::to_string(self)
     }
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(json: &str) -> serde_json::Result<Msg> {
         serde_json::from_str(json)
     }
 }
 
+/// The sender and position of a message, without decoding its content,
+/// for the routers and relays forwarding the messages by range
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct Header {
+    pub sender_id: AppId,
+    pub pos: Position,
+}
+
+impl Header {
+    pub fn from_json(json: &str) -> serde_json::Result<Header> {
+        serde_json::from_str(json)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -62,5 +102,9 @@ mod tests {
         println!("deserialized = {:?}", deserialized);
 
         assert_eq!(msg, deserialized);
+
+        let header = Header::from_json(&serialized).expect("failed to read the header");
+        assert_eq!(header.sender_id, msg.sender_id);
+        assert_eq!(header.pos, msg.pos);
     }
 }
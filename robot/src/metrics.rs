@@ -0,0 +1,311 @@
+//! Quality of the maps of the robots, against the ground truth of the simulated world.
+//! The ground truth is the `PolyMap` of the world drawn in the grid of `AI`: its walls are blocked,
+//! the cells reachable from the start positions of the robots without crossing a wall are free,
+//! and the others, inside the obstacles or out of the world, cannot be seen.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use ndarray::{Array2, Zip};
+use serde::Serialize;
+
+use crate::ai::{self, CellState};
+use crate::app::AppId;
+use crate::map::{Distance, Point, PolyMap};
+
+/// Distance to the ground truth within which a cell is not counted wrong,
+/// the walls of the robots being drawn a cell or two off
+pub const DEFAULT_TOLERANCE: Distance = 0.02;
+
+/// Quality of the map of a robot, as fractions
+#[derive(Clone, Copy, Default, Serialize, Debug, PartialEq)]
+pub struct MapQuality {
+    /// Free cells of the world which are known
+    pub explored: f32,
+    /// Cells seen free which are not
+    pub false_free: f32,
+    /// Cells seen blocked which are free
+    pub false_blocked: f32,
+}
+
+/// Quality of the maps of the swarm, as fractions
+#[derive(Clone, Default, Serialize, Debug, PartialEq)]
+pub struct SwarmQuality {
+    /// Free cells of the world known by at least one robot
+    pub explored: f32,
+    /// Averages of the robots
+    pub false_free: f32,
+    pub false_blocked: f32,
+    /// Agreement of the maps of the robots, on average over the pairs of robots
+    pub consistency: f32,
+    pub robots: BTreeMap<AppId, MapQuality>,
+}
+
+/// The world in the grid of `AI`
+pub struct GroundTruth {
+    cells: Array2<CellState>,
+    /// Cells within the tolerance of a free cell, and of a blocked one
+    near_free: Array2<bool>,
+    near_blocked: Array2<bool>,
+    free: usize,
+}
+
+/// Cells within `radius` cells of the cells of the mask
+fn dilate(mask: &Array2<bool>, radius: usize) -> Array2<bool> {
+    let (width, height) = mask.dim();
+    let mut rows = Array2::from_elem(mask.dim(), false);
+    for ((x, y), &set) in mask.indexed_iter() {
+        if set {
+            for dx in x.saturating_sub(radius)..(x + radius + 1).min(width) {
+                rows[(dx, y)] = true;
+            }
+        }
+    }
+    let mut dilated = Array2::from_elem(mask.dim(), false);
+    for ((x, y), &set) in rows.indexed_iter() {
+        if set {
+            for dy in y.saturating_sub(radius)..(y + radius + 1).min(height) {
+                dilated[(x, dy)] = true;
+            }
+        }
+    }
+    dilated
+}
+
+impl GroundTruth {
+    /// Draws the world of the robots starting at `starts`
+    pub fn new(map: &PolyMap, starts: &[Point], tolerance: Distance) -> Self {
+        let grid = ai::grid();
+        let mut cells = map.rasterize_edges(&grid);
+
+        // the free space is flooded from the start positions, the walls stopping the flood
+        let mut queue: VecDeque<(usize, usize)> = starts
+            .iter()
+            .filter_map(|&start| grid.cell(start))
+            .collect();
+        while let Some((x, y)) = queue.pop_front() {
+            if cells[(x, y)] != CellState::Uncharted {
+                continue;
+            }
+            cells[(x, y)] = CellState::SeenFree;
+            let neighbours = [
+                (x.wrapping_sub(1), y),
+                (x + 1, y),
+                (x, y.wrapping_sub(1)),
+                (x, y + 1),
+            ];
+            for &(nx, ny) in neighbours.iter() {
+                if nx < grid.size.0 && ny < grid.size.1 && cells[(nx, ny)] == CellState::Uncharted {
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+
+        let radius = (tolerance / grid.resolution).round() as usize;
+        let free = cells.iter().filter(|&&c| c == CellState::SeenFree).count();
+        GroundTruth {
+            near_free: dilate(&cells.map(|&c| c == CellState::SeenFree), radius),
+            near_blocked: dilate(&cells.map(|&c| c == CellState::Blocked), radius),
+            cells,
+            free,
+        }
+    }
+
+    /// The world, the cells which cannot be seen being uncharted
+    pub fn cells(&self) -> &Array2<CellState> {
+        &self.cells
+    }
+
+    /// Fraction of the free cells known by at least one of the maps
+    pub fn explored<'a>(&self, maps: impl IntoIterator<Item = &'a Array2<CellState>>) -> f32 {
+        let mut known = Array2::from_elem(self.cells.dim(), false);
+        for map in maps {
+            Zip::from(&mut known)
+                .and(map)
+                .apply(|known, &c| *known |= c != CellState::Uncharted);
+        }
+        let mut explored = 0;
+        Zip::from(&known).and(&self.cells).apply(|&known, &truth| {
+            if known && truth == CellState::SeenFree {
+                explored += 1;
+            }
+        });
+        ratio(explored, self.free)
+    }
+
+    pub fn evaluate(&self, map: &Array2<CellState>) -> MapQuality {
+        let (mut seen_free, mut false_free) = (0, 0);
+        let (mut seen_blocked, mut false_blocked) = (0, 0);
+        Zip::from(map)
+            .and(&self.near_free)
+            .and(&self.near_blocked)
+            .apply(|&c, &near_free, &near_blocked| match c {
+                CellState::SeenFree => {
+                    seen_free += 1;
+                    false_free += !near_free as usize;
+                }
+                CellState::Blocked => {
+                    seen_blocked += 1;
+                    false_blocked += !near_blocked as usize;
+                }
+                CellState::Uncharted => (),
+            });
+        MapQuality {
+            explored: self.explored(Some(map)),
+            false_free: ratio(false_free, seen_free),
+            false_blocked: ratio(false_blocked, seen_blocked),
+        }
+    }
+
+    pub fn evaluate_swarm<'a>(
+        &self,
+        maps: impl IntoIterator<Item = (AppId, &'a Array2<CellState>)>,
+    ) -> SwarmQuality {
+        let maps: Vec<_> = maps.into_iter().collect();
+        let robots: BTreeMap<_, _> = maps
+            .iter()
+            .map(|&(id, map)| (id, self.evaluate(map)))
+            .collect();
+        let mean = |f: fn(&MapQuality) -> f32| {
+            robots.values().map(f).sum::<f32>() / robots.len().max(1) as f32
+        };
+        let agreements: Vec<f32> = maps
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (_, a))| maps[i + 1..].iter().map(move |(_, b)| (*a, *b)))
+            .filter_map(|(a, b)| consistency(a, b))
+            .collect();
+        SwarmQuality {
+            explored: self.explored(maps.iter().map(|&(_, map)| map)),
+            false_free: mean(|q| q.false_free),
+            false_blocked: mean(|q| q.false_blocked),
+            consistency: if agreements.is_empty() {
+                1.
+            } else {
+                agreements.iter().sum::<f32>() / agreements.len() as f32
+            },
+            robots,
+        }
+    }
+}
+
+fn ratio(count: usize, total: usize) -> f32 {
+    if total == 0 {
+        0.
+    } else {
+        count as f32 / total as f32
+    }
+}
+
+/// Fraction of the cells known by both maps on which they agree, None if they share none
+pub fn consistency(a: &Array2<CellState>, b: &Array2<CellState>) -> Option<f32> {
+    let (mut shared, mut agreeing) = (0, 0);
+    Zip::from(a).and(b).apply(|&a, &b| {
+        if a != CellState::Uncharted && b != CellState::Uncharted {
+            shared += 1;
+            agreeing += (a == b) as usize;
+        }
+    });
+    if shared == 0 {
+        None
+    } else {
+        Some(ratio(agreeing, shared))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod tests {
+    use super::*;
+    use crate::ai::pos_to_pixels;
+    use crate::map::Polygon;
+
+    fn room() -> PolyMap {
+        let square = |size: Distance| Polygon {
+            points: vec![
+                Point { x: -size, y: -size },
+                Point { x: -size, y: size },
+                Point { x: size, y: size },
+                Point { x: size, y: -size },
+            ],
+            is_closed: true,
+        };
+        PolyMap::new(vec![square(0.5), square(0.1)])
+    }
+
+    fn cell_at(x: Distance, y: Distance) -> (usize, usize) {
+        let (x, y) = pos_to_pixels(Point { x, y });
+        (x as usize, y as usize)
+    }
+
+    #[test]
+    fn ground_truth_test() {
+        let truth = GroundTruth::new(&room(), &[Point { x: 0.3, y: 0.3 }], 0.);
+        let cells = truth.cells();
+        assert_eq!(cells[cell_at(0.3, -0.3)], CellState::SeenFree);
+        assert_eq!(cells[cell_at(0.5, 0.2)], CellState::Blocked);
+        assert_eq!(cells[cell_at(-0.1, 0.)], CellState::Blocked);
+        // inside the obstacle, and out of the room
+        assert_eq!(cells[cell_at(0., 0.)], CellState::Uncharted);
+        assert_eq!(cells[cell_at(0.8, 0.)], CellState::Uncharted);
+
+        let perfect = truth.evaluate(cells);
+        assert_eq!(
+            perfect,
+            MapQuality {
+                explored: 1.,
+                false_free: 0.,
+                false_blocked: 0.
+            }
+        );
+        let uncharted = Array2::default(cells.dim());
+        assert_eq!(truth.evaluate(&uncharted), MapQuality::default());
+    }
+
+    #[test]
+    fn quality_test() {
+        let truth = GroundTruth::new(&room(), &[Point { x: 0.3, y: 0.3 }], DEFAULT_TOLERANCE);
+        let dim = truth.cells().dim();
+
+        // everything seen free, through the walls
+        let free = Array2::from_elem(dim, CellState::SeenFree);
+        let quality = truth.evaluate(&free);
+        assert_eq!(quality.explored, 1.);
+        assert!(quality.false_free > 0.5);
+        assert_eq!(quality.false_blocked, 0.);
+
+        // the walls seen a cell off are right
+        let mut shifted = truth.cells().clone();
+        shifted[cell_at(0.5, 0.2)] = CellState::SeenFree;
+        shifted[cell_at(0.49, 0.2)] = CellState::Blocked;
+        let quality = truth.evaluate(&shifted);
+        assert_eq!((quality.false_free, quality.false_blocked), (0., 0.));
+
+        // a wall in the middle of the room
+        let mut wrong = Array2::default(dim);
+        wrong[cell_at(0.3, 0.3)] = CellState::Blocked;
+        wrong[cell_at(0.3, 0.2)] = CellState::SeenFree;
+        let quality = truth.evaluate(&wrong);
+        assert!(quality.explored > 0. && quality.explored < 0.01);
+        assert_eq!((quality.false_free, quality.false_blocked), (0., 1.));
+
+        let swarm = truth.evaluate_swarm(vec![(1, &shifted), (2, &wrong)]);
+        assert_eq!(swarm.explored, 1.);
+        assert_eq!(swarm.false_blocked, 0.5);
+        assert_eq!(swarm.consistency, 0.5);
+        assert_eq!(swarm.robots[&2], quality);
+    }
+
+    #[test]
+    fn consistency_test() {
+        let uncharted = Array2::default((2, 2));
+        assert_eq!(consistency(&uncharted, &uncharted), None);
+        let mut a = uncharted.clone();
+        a[(0, 0)] = CellState::SeenFree;
+        a[(0, 1)] = CellState::Blocked;
+        let mut b = a.clone();
+        assert_eq!(consistency(&a, &b), Some(1.));
+        b[(0, 1)] = CellState::SeenFree;
+        b[(1, 1)] = CellState::Blocked;
+        assert_eq!(consistency(&a, &b), Some(0.5));
+    }
+}
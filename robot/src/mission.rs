@@ -0,0 +1,145 @@
+//! State of the mission shared by the swarm, changed by commands agreed on through Raft
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::map::Point;
+
+/// Commands of the operators, or of the robots themselves
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Command {
+    /// Explore the whole map
+    Explore,
+    /// Explore the frontiers inside a rectangle, given by two opposite corners
+    TargetArea(Point, Point),
+    /// The exploration is complete, the robots stop
+    Complete,
+    /// The robots go back to their start positions
+    ReturnHome,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    /// Parses commands such as `explore`, `area -0.5 0 0.5 1`, `complete` or `return-home`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words.as_slice() {
+            ["explore"] => Ok(Command::Explore),
+            ["complete"] => Ok(Command::Complete),
+            ["return-home"] => Ok(Command::ReturnHome),
+            ["area", coordinates @ ..] if coordinates.len() == 4 => {
+                let c: Vec<f32> = coordinates
+                    .iter()
+                    .map(|c| c.parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| format!("invalid area {}, {}", s, e))?;
+                Ok(Command::TargetArea(
+                    Point { x: c[0], y: c[1] },
+                    Point { x: c[2], y: c[3] },
+                ))
+            }
+            _ => Err(format!(
+                "unknown command {}, expected explore, area x0 y0 x1 y1, complete or return-home",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Explore => write!(f, "explore"),
+            Command::TargetArea(a, b) => write!(f, "area {} {} {} {}", a.x, a.y, b.x, b.y),
+            Command::Complete => write!(f, "complete"),
+            Command::ReturnHome => write!(f, "return-home"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Phase {
+    Exploring,
+    Complete,
+    ReturningHome,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mission {
+    pub phase: Phase,
+    /// The exploration is restricted to this rectangle, as min and max corners
+    pub area: Option<(Point, Point)>,
+}
+
+impl Default for Mission {
+    fn default() -> Self {
+        Mission {
+            phase: Phase::Exploring,
+            area: None,
+        }
+    }
+}
+
+impl Mission {
+    pub fn apply(&mut self, command: Command) {
+        match command {
+            Command::Explore => {
+                self.phase = Phase::Exploring;
+                self.area = None;
+            }
+            Command::TargetArea(a, b) => {
+                self.phase = Phase::Exploring;
+                let min = Point {
+                    x: a.x.min(b.x),
+                    y: a.y.min(b.y),
+                };
+                let max = Point {
+                    x: a.x.max(b.x),
+                    y: a.y.max(b.y),
+                };
+                self.area = Some((min, max));
+            }
+            Command::Complete => self.phase = Phase::Complete,
+            Command::ReturnHome => self.phase = Phase::ReturningHome,
+        }
+    }
+
+    /// The point is in the area to explore
+    pub fn contains(&self, p: Point) -> bool {
+        match self.area {
+            Some((min, max)) => min.x <= p.x && p.x <= max.x && min.y <= p.y && p.y <= max.y,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mission_test() {
+        let commands = ["area 0.5 1 -0.5 0", "complete", "return-home", "explore"];
+        let commands: Vec<Command> = commands.iter().map(|c| c.parse().unwrap()).collect();
+        for command in commands.iter() {
+            assert_eq!(command.to_string().parse(), Ok(*command));
+        }
+        assert!("area 1 2".parse::<Command>().is_err());
+        assert!("land".parse::<Command>().is_err());
+
+        let mut mission = Mission::default();
+        mission.apply(commands[0]);
+        assert_eq!(mission.phase, Phase::Exploring);
+        assert!(mission.contains(Point { x: 0., y: 0.5 }));
+        assert!(!mission.contains(Point { x: 0., y: -0.5 }));
+        mission.apply(commands[1]);
+        assert_eq!(mission.phase, Phase::Complete);
+        mission.apply(commands[2]);
+        assert_eq!(mission.phase, Phase::ReturningHome);
+        mission.apply(commands[3]);
+        assert_eq!(mission, Mission::default());
+    }
+}
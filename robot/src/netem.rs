@@ -0,0 +1,401 @@
+//! Network emulation: messages sent between robots are delayed, lost, duplicated or reordered
+//! following the parameters of each link, and scripted partitions cut the swarm in groups.
+//! The emulator works with any clock, so it can drive robots in-process with a virtual time,
+//! or relay the messages of robot processes (see the `relay` binary).
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::app::AppId;
+use crate::radio::RadioParams;
+
+/// Seconds since the start of the emulation
+pub type Time = f64;
+
+/// Quality of a link, a perfect one by default
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(default)]
+pub struct LinkParams {
+    /// Delay of the messages (s)
+    pub latency: f32,
+    /// The delay varies uniformly by up to this (s)
+    pub jitter: f32,
+    /// Probability for a message to be lost
+    pub loss: f32,
+    /// Bytes per second, unlimited if None
+    pub bandwidth: Option<f32>,
+    /// Probability for a message to be delivered twice
+    pub duplication: f32,
+    /// Probability for a message to be delayed by twice the latency, overtaken by the next ones
+    pub reordering: f32,
+}
+
+impl Default for LinkParams {
+    fn default() -> Self {
+        LinkParams {
+            latency: 0.,
+            jitter: 0.,
+            loss: 0.,
+            bandwidth: None,
+            duplication: 0.,
+            reordering: 0.,
+        }
+    }
+}
+
+/// Parameters of the link from a robot to another, links are not symmetric
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Link {
+    pub from: AppId,
+    pub to: AppId,
+    #[serde(flatten)]
+    pub params: LinkParams,
+}
+
+/// Between `start` and `end`, only the robots of the same group can communicate,
+/// the robots in no group are isolated
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Partition {
+    pub start: Time,
+    pub end: Time,
+    pub groups: Vec<Vec<AppId>>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(default)]
+pub struct NetemConfig {
+    /// Parameters of the links not given in `links`
+    pub default: LinkParams,
+    pub links: Vec<Link>,
+    pub partitions: Vec<Partition>,
+    /// Range-limited radio, the messages otherwise reach all the robots
+    pub radio: Option<RadioParams>,
+}
+
+impl NetemConfig {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn link(&self, from: AppId, to: AppId) -> LinkParams {
+        self.links
+            .iter()
+            .find(|link| link.from == from && link.to == to)
+            .map_or(self.default, |link| link.params)
+    }
+
+    /// No partition separates the robots at `time`
+    pub fn connected(&self, time: Time, from: AppId, to: AppId) -> bool {
+        self.partitions
+            .iter()
+            .filter(|partition| partition.start <= time && time < partition.end)
+            .all(|partition| {
+                partition
+                    .groups
+                    .iter()
+                    .any(|group| group.contains(&from) && group.contains(&to))
+            })
+    }
+}
+
+/// A message on its way
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delivery<T> {
+    pub at: Time,
+    pub from: AppId,
+    pub to: AppId,
+    pub payload: T,
+}
+
+pub struct Emulator<T> {
+    config: NetemConfig,
+    rng: StdRng,
+    // Messages on their way, with their sending order to break ties
+    queue: Vec<(u64, Delivery<T>)>,
+    sent: u64,
+    // When each link is done transmitting the previous messages
+    busy_until: HashMap<(AppId, AppId), Time>,
+    // Last delivery on each link, the messages are delivered in order unless reordered
+    last_delivery: HashMap<(AppId, AppId), Time>,
+}
+
+impl<T: Clone> Emulator<T> {
+    pub fn new(config: NetemConfig, seed: u64) -> Self {
+        Emulator {
+            config,
+            rng: StdRng::seed_from_u64(seed),
+            queue: Vec::new(),
+            sent: 0,
+            busy_until: HashMap::new(),
+            last_delivery: HashMap::new(),
+        }
+    }
+
+    /// Sends `payload`, of `size` bytes, from a robot to another at `now`
+    pub fn send(&mut self, now: Time, from: AppId, to: AppId, payload: T, size: usize) {
+        if !self.config.connected(now, from, to) {
+            return;
+        }
+        let params = self.config.link(from, to);
+        let busy_until = self.busy_until.entry((from, to)).or_insert(now);
+        let mut sent_at = busy_until.max(now);
+        if let Some(bandwidth) = params.bandwidth {
+            sent_at += size as Time / Time::from(bandwidth);
+        }
+        *busy_until = sent_at;
+
+        if self.rng.gen::<f32>() < params.loss {
+            return;
+        }
+        let copies = if self.rng.gen::<f32>() < params.duplication {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let mut delay = params.latency;
+            if params.jitter > 0. {
+                delay += self.rng.gen_range(-params.jitter, params.jitter);
+            }
+            let last = self.last_delivery.get(&(from, to)).cloned().unwrap_or(0.);
+            let mut at = sent_at + Time::from(delay.max(0.));
+            if self.rng.gen::<f32>() < params.reordering {
+                at += 2. * Time::from(params.latency);
+            } else {
+                at = at.max(last);
+                self.last_delivery.insert((from, to), at);
+            }
+            self.sent += 1;
+            self.queue.push((
+                self.sent,
+                Delivery {
+                    at,
+                    from,
+                    to,
+                    payload: payload.clone(),
+                },
+            ));
+        }
+    }
+
+    /// Sends `payload` to all the given robots but the sender
+    pub fn broadcast(&mut self, now: Time, from: AppId, to: &[AppId], payload: T, size: usize) {
+        for &robot in to.iter().filter(|&&robot| robot != from) {
+            self.send(now, from, robot, payload.clone(), size);
+        }
+    }
+
+    /// When the next message is due, if any
+    pub fn next_delivery(&self) -> Option<Time> {
+        self.queue
+            .iter()
+            .map(|(_, delivery)| delivery.at)
+            .min_by(|a, b| a.partial_cmp(b).expect("NaN delivery time"))
+    }
+
+    /// The messages due at `now`, in the order of delivery.
+    /// Messages whose robots were separated by a partition meanwhile are lost.
+    pub fn poll(&mut self, now: Time) -> Vec<Delivery<T>> {
+        let (mut due, queue): (Vec<_>, Vec<_>) = self
+            .queue
+            .drain(..)
+            .partition(|(_, delivery)| delivery.at <= now);
+        self.queue = queue;
+        due.sort_by(|(a_sent, a), (b_sent, b)| {
+            a.at.partial_cmp(&b.at)
+                .expect("NaN delivery time")
+                .then(a_sent.cmp(b_sent))
+        });
+        due.into_iter()
+            .map(|(_, delivery)| delivery)
+            .filter(|delivery| {
+                self.config
+                    .connected(delivery.at, delivery.from, delivery.to)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raft::{Message, Raft};
+
+    fn link(params: LinkParams) -> Emulator<u32> {
+        Emulator::new(
+            NetemConfig {
+                default: params,
+                ..NetemConfig::default()
+            },
+            0,
+        )
+    }
+
+    fn payloads(deliveries: Vec<Delivery<u32>>) -> Vec<u32> {
+        deliveries.into_iter().map(|d| d.payload).collect()
+    }
+
+    #[test]
+    fn latency_bandwidth_test() {
+        let mut emulator = link(LinkParams {
+            latency: 0.125,
+            bandwidth: Some(1000.),
+            ..LinkParams::default()
+        });
+        emulator.send(0., 1, 2, 1, 125);
+        emulator.send(0., 1, 2, 2, 125);
+        // 0.125 s to transmit each message, then 0.125 s of latency
+        assert_eq!(emulator.next_delivery(), Some(0.25));
+        assert!(emulator.poll(0.2).is_empty());
+        assert_eq!(payloads(emulator.poll(0.25)), [1]);
+        assert_eq!(payloads(emulator.poll(0.375)), [2]);
+        assert_eq!(emulator.next_delivery(), None);
+    }
+
+    #[test]
+    fn loss_duplication_reordering_test() {
+        let mut emulator = link(LinkParams {
+            latency: 0.1,
+            jitter: 0.05,
+            loss: 0.2,
+            duplication: 0.1,
+            reordering: 0.1,
+            ..LinkParams::default()
+        });
+        for i in 0..1000 {
+            emulator.send(Time::from(i) * 0.01, 1, 2, i, 10);
+        }
+        let received = payloads(emulator.poll(100.));
+        let mut unique = received.clone();
+        unique.sort();
+        unique.dedup();
+        assert!((700..=900).contains(&unique.len()), "{}", unique.len());
+        assert!(received.len() > unique.len(), "no duplicates");
+        let reordered = received.windows(2).filter(|w| w[0] > w[1]).count();
+        assert!(reordered > 10, "{}", reordered);
+
+        // without reordering, the jitter does not reorder the messages
+        let mut emulator = link(LinkParams {
+            latency: 0.1,
+            jitter: 0.05,
+            ..LinkParams::default()
+        });
+        for i in 0..100 {
+            emulator.send(Time::from(i) * 0.01, 1, 2, i, 10);
+        }
+        assert_eq!(payloads(emulator.poll(100.)), (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn partition_test() {
+        let config: NetemConfig = serde_json::from_str(
+            r#"{
+                "default": {"latency": 0.1},
+                "links": [{"from": 1, "to": 3, "latency": 1}],
+                "partitions": [{"start": 10, "end": 20, "groups": [[1, 2], [3]]}]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.link(1, 3).latency, 1.);
+        assert_eq!(config.link(3, 1).latency, 0.1);
+        assert!(config.connected(5., 1, 3));
+        assert!(config.connected(15., 1, 2));
+        assert!(!config.connected(15., 1, 3));
+        assert!(!config.connected(15., 4, 1));
+        assert!(config.connected(20., 1, 3));
+
+        let mut emulator = Emulator::new(config, 0);
+        emulator.send(9.5, 1, 3, 1, 10);
+        emulator.send(9.5, 1, 2, 2, 10);
+        emulator.send(15., 1, 3, 3, 10);
+        emulator.send(20., 1, 3, 4, 10);
+        // the first message arrives during the partition
+        assert_eq!(payloads(emulator.poll(30.)), [2, 4]);
+    }
+
+    /// Raft keeps the robots consistent over a lossy network, and converges once it heals
+    #[test]
+    fn raft_test() {
+        let ids: Vec<AppId> = (1..=5).collect();
+        let config = NetemConfig {
+            default: LinkParams {
+                latency: 0.02,
+                jitter: 0.01,
+                loss: 0.1,
+                duplication: 0.05,
+                reordering: 0.05,
+                ..LinkParams::default()
+            },
+            links: Vec::new(),
+            partitions: vec![Partition {
+                start: 10.,
+                end: 20.,
+                groups: vec![vec![1, 2], vec![3, 4, 5]],
+            }],
+            radio: None,
+        };
+        let mut emulator = Emulator::new(config, 1);
+        let mut robots: Vec<Raft<u32>> = ids
+            .iter()
+            .map(|&id| {
+                let mut raft = Raft::new(id);
+                raft.set_cluster_size(Some(ids.len()));
+                raft
+            })
+            .collect();
+        let mut applied = vec![Vec::new(); ids.len()];
+
+        let tick = 0.2;
+        for step in 0..200 {
+            let now = step as Time * tick;
+            let mut outgoing: Vec<(AppId, Message<u32>)> = Vec::new();
+            // one command every second, proposed by each robot in turn
+            if step % 5 == 0 {
+                let proposer = &mut robots[step / 5 % ids.len()];
+                let id = ids[step / 5 % ids.len()];
+                outgoing.extend(proposer.propose(step as u32).into_iter().map(|m| (id, m)));
+            }
+            for (&id, robot) in ids.iter().zip(robots.iter_mut()) {
+                outgoing.extend(robot.tick().into_iter().map(|m| (id, m)));
+            }
+            // the messages of this tick, and the answers they trigger
+            let end = now + tick;
+            let mut time = now;
+            loop {
+                for (from, message) in outgoing.drain(..) {
+                    emulator.broadcast(time, from, &ids, message, 100);
+                }
+                match emulator.next_delivery() {
+                    Some(at) if at < end => time = at,
+                    _ => break,
+                }
+                for Delivery {
+                    from, to, payload, ..
+                } in emulator.poll(time)
+                {
+                    let replies = robots[to as usize - 1].handle(from, payload);
+                    outgoing.extend(replies.into_iter().map(|m| (to, m)));
+                }
+            }
+            for (robot, applied) in robots.iter_mut().zip(applied.iter_mut()) {
+                applied.extend(robot.take_committed());
+            }
+            for (a, b) in applied.iter().zip(applied.iter().skip(1)) {
+                let common = a.len().min(b.len());
+                assert_eq!(a[..common], b[..common], "diverging logs");
+            }
+        }
+
+        // a few commands may be lost with their proposal, most are applied everywhere
+        assert!(applied.iter().all(|a| a == &applied[0]));
+        assert!(applied[0].len() > 20, "{:?}", applied[0]);
+    }
+}
@@ -0,0 +1,52 @@
+//! Named pipes between the robots and the processes routing their messages.
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::app::AppId;
+
+/// Creates the pipe if nothing exists at `path`
+pub fn mkfifo(path: &Path) {
+    if path.exists() {
+        return;
+    }
+    let status = Command::new("mkfifo")
+        .arg(path)
+        .status()
+        .unwrap_or_else(|e| panic!("could not run mkfifo, {}", e));
+    assert!(status.success(), "could not create the pipe {:?}", path);
+}
+
+/// Forwards the lines written by a robot. Its output is reopened when the robot closes it
+/// if it is a pipe, a file is read once.
+pub fn listen(id: AppId, output: PathBuf, tx: mpsc::Sender<(AppId, String)>) {
+    let is_fifo = fs::metadata(&output)
+        .map(|metadata| metadata.file_type().is_fifo())
+        .unwrap_or_else(|e| panic!("could not find the output of {}, {}", id, e));
+    thread::spawn(move || loop {
+        // blocks until the robot opens the pipe again
+        let file = File::open(&output)
+            .unwrap_or_else(|e| panic!("could not open the output of {}, {}", id, e));
+        for line in BufReader::new(file).lines() {
+            match line {
+                Ok(line) => {
+                    if tx.send((id, line)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    log::warn!("could not read from {}, {}", id, e);
+                    break;
+                }
+            }
+        }
+        if !is_fifo {
+            return;
+        }
+    });
+}
@@ -0,0 +1,206 @@
+//! Radio model deciding which robots receive a message, from their positions.
+//! The power of the signal decreases with the distance following a log-distance path loss,
+//! and each wall of the map crossed by the signal attenuates it further.
+//! The margin is the difference between the received power and the sensitivity of the radio,
+//! null at the maximum range. With fading, the power varies following a Rayleigh distribution
+//! and a message is received with probability exp(-10^(-margin/10)).
+
+use std::collections::HashMap;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::app::AppId;
+use crate::map::{Distance, Point, PolyMap, Segment};
+
+/// Attenuation of the signal in dB
+pub type Decibel = f32;
+
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(default)]
+pub struct RadioParams {
+    /// Messages are never received beyond this, in free space (m)
+    pub range: Distance,
+    /// The power decreases as the distance to this power, 2 in free space
+    pub path_loss_exponent: f32,
+    /// Attenuation of each wall crossed
+    pub wall_loss: Decibel,
+    /// Rayleigh fading, the messages are otherwise received whenever the margin is positive
+    pub fading: bool,
+}
+
+impl Default for RadioParams {
+    /// The range of the robots of the Qt simulator, a concrete wall, and no fading
+    fn default() -> Self {
+        RadioParams {
+            range: 1.,
+            path_loss_exponent: 2.,
+            wall_loss: 10.,
+            fading: false,
+        }
+    }
+}
+
+pub struct Radio {
+    params: RadioParams,
+    map: PolyMap,
+    rng: StdRng,
+}
+
+impl Radio {
+    pub fn new(params: RadioParams, map: PolyMap, seed: u64) -> Self {
+        Radio {
+            params,
+            map,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Margin of the signal sent at `from` and received at `to`, None beyond the range
+    pub fn margin(&self, from: Point, to: Point) -> Option<Decibel> {
+        let distance = from.sq_dist(to).sqrt();
+        if distance > self.params.range {
+            return None;
+        }
+        let walls = self.map.count_intersections(&Segment(from, to));
+        let path_loss =
+            10. * self.params.path_loss_exponent * (distance / self.params.range).log10();
+        Some(-path_loss - walls as Decibel * self.params.wall_loss)
+    }
+
+    /// Probability for a message sent at `from` to be received at `to`
+    pub fn delivery_probability(&self, from: Point, to: Point) -> f32 {
+        match self.margin(from, to) {
+            None => 0.,
+            Some(margin) if self.params.fading => (-(10f32).powf(-margin / 10.)).exp(),
+            Some(margin) if margin >= 0. => 1.,
+            Some(_) => 0.,
+        }
+    }
+
+    /// Draws whether a message sent at `from` is received at `to`
+    pub fn delivers(&mut self, from: Point, to: Point) -> bool {
+        let probability = self.delivery_probability(from, to);
+        probability > 0. && self.rng.gen::<f32>() < probability
+    }
+
+    /// The robots receiving a message of `sender`, from the current positions of the robots
+    pub fn receivers(&mut self, sender: AppId, positions: &HashMap<AppId, Point>) -> Vec<AppId> {
+        let from = match positions.get(&sender) {
+            Some(&from) => from,
+            None => return Vec::new(),
+        };
+        let mut robots: Vec<(AppId, Point)> = positions
+            .iter()
+            .filter(|(&id, _)| id != sender)
+            .map(|(&id, &p)| (id, p))
+            .collect();
+        // the same draws for the same positions
+        robots.sort_by_key(|&(id, _)| id);
+        robots
+            .into_iter()
+            .filter(|&(_, to)| self.delivers(from, to))
+            .map(|(id, _)| id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Polygon;
+
+    /// A wall along x = 0
+    fn radio(fading: bool) -> Radio {
+        let wall = Polygon {
+            points: vec![Point { x: 0., y: -1. }, Point { x: 0., y: 1. }],
+            is_closed: false,
+        };
+        let params = RadioParams {
+            fading,
+            ..RadioParams::default()
+        };
+        Radio::new(params, PolyMap::new(vec![wall]), 0)
+    }
+
+    #[test]
+    fn margin_test() {
+        let radio = radio(false);
+        let origin = Point { x: -0.5, y: 0. };
+        let at = |x, y| origin + Point { x, y };
+        // 6 dB at half the range, 20 dB at a tenth
+        assert!((radio.margin(origin, at(0., 0.5)).unwrap() - 6.02).abs() < 0.01);
+        assert!((radio.margin(origin, at(0., -0.1)).unwrap() - 20.).abs() < 0.01);
+        assert_eq!(radio.margin(origin, at(0., 1.01)), None);
+        // behind the wall
+        assert!((radio.margin(origin, at(0.6, 0.)).unwrap() - (4.44 - 10.)).abs() < 0.01);
+
+        assert_eq!(radio.delivery_probability(origin, at(0., 0.9)), 1.);
+        assert_eq!(radio.delivery_probability(origin, at(0.6, 0.)), 0.);
+        assert_eq!(radio.delivery_probability(origin, at(1.1, 0.)), 0.);
+    }
+
+    #[test]
+    fn free_space_test() {
+        // the positions of the example of the README
+        let positions = [
+            Point { x: -0.5, y: 0.3 },
+            Point { x: 0.4, y: 0.3 },
+            Point { x: 0.4, y: -0.3 },
+        ];
+        // without fading, the messages are received in range unless a wall blocks them
+        let params = RadioParams {
+            fading: false,
+            ..RadioParams::default()
+        };
+        let radio = Radio::new(params, PolyMap::new(vec![]), 0);
+        let mut in_range = 0;
+        for &from in positions.iter() {
+            for &to in positions.iter() {
+                if from.sq_dist(to).sqrt() <= params.range {
+                    in_range += 1;
+                    assert!(radio.delivery_probability(from, to) > 0.);
+                }
+            }
+        }
+        // each robot itself, and both ways between the first and second, second and third
+        assert_eq!(in_range, 7);
+    }
+
+    #[test]
+    fn fading_test() {
+        let mut radio = radio(true);
+        let origin = Point { x: -0.5, y: 0. };
+        let near = Point { x: -0.5, y: 0.1 };
+        let far = Point { x: -0.5, y: 0.9 };
+        let walled = Point { x: 0.1, y: 0. };
+        assert!(radio.delivery_probability(origin, near) > 0.98);
+        assert!(
+            radio.delivery_probability(origin, far) > radio.delivery_probability(origin, walled)
+        );
+
+        let positions: HashMap<AppId, Point> = [(1, origin), (2, near), (3, far), (4, walled)]
+            .iter()
+            .cloned()
+            .collect();
+        let mut received = HashMap::new();
+        for _ in 0..1000 {
+            for id in radio.receivers(1, &positions) {
+                *received.entry(id).or_insert(0) += 1;
+            }
+        }
+        let expected = |to| radio.delivery_probability(origin, to) * 1000.;
+        for &(id, to) in [(2, near), (3, far), (4, walled)].iter() {
+            let count = received.get(&id).cloned().unwrap_or(0) as f32;
+            assert!(
+                (count - expected(to)).abs() < 50.,
+                "{}: {} {}",
+                id,
+                count,
+                expected(to)
+            );
+        }
+        assert!(radio.receivers(5, &positions).is_empty());
+    }
+}
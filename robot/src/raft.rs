@@ -0,0 +1,915 @@
+//! Raft consensus over the flood network, to apply the same commands in the same order
+//! on every robot.
+//! The robots are followers of the leader as long as they receive its heartbeats.
+//! When they do not, after a random number of ticks, they become candidates for a new term,
+//! and the first one to receive the votes of a majority becomes the leader.
+//! The leader appends the proposed commands to its log and replicates it,
+//! an entry is committed, and can be applied, once a majority of the robots have it.
+//! The size of the swarm can be given, otherwise the majority is computed
+//! among the robots heard of recently, which is only safe without partitions.
+//! The term, the vote and the log can be saved in a file before answering, so that a restarted
+//! robot does not vote twice in the same term nor forget the entries it acknowledged.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::app::AppId;
+
+pub type Term = u64;
+/// Position in the log, starting at 1, 0 is before the first entry
+pub type Index = usize;
+
+/// Ticks without heartbeat before starting an election, randomised up to twice this
+const ELECTION_TIMEOUT: u32 = 5;
+/// Ticks after which a robot not heard of is not counted in the majority
+const PEER_TIMEOUT: u64 = 25;
+/// Maximum number of entries sent at once
+const MAX_ENTRIES: usize = 20;
+
+/// A command of the log, the leaders append an empty entry when elected
+/// to commit the entries of the previous terms
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Entry<C> {
+    pub term: Term,
+    pub command: Option<C>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Message<C> {
+    /// The sender is candidate for the term
+    RequestVote {
+        term: Term,
+        last_index: Index,
+        last_term: Term,
+    },
+    /// Vote of the sender for a candidate
+    Vote {
+        term: Term,
+        candidate: AppId,
+        granted: bool,
+    },
+    /// Entries of the leader for a follower, following the one at `prev_index`.
+    /// Also the heartbeat of the leader.
+    Append {
+        term: Term,
+        to: AppId,
+        prev_index: Index,
+        prev_term: Term,
+        entries: Vec<Entry<C>>,
+        commit: Index,
+    },
+    /// Answer of a follower to the leader, with the last index matching its log on success,
+    /// and the index to retry from otherwise
+    Appended {
+        term: Term,
+        to: AppId,
+        success: bool,
+        index: Index,
+    },
+    /// A command proposed to the leader
+    Propose { to: AppId, command: C },
+}
+
+impl<C> Message<C> {
+    fn term(&self) -> Option<Term> {
+        match *self {
+            Message::RequestVote { term, .. }
+            | Message::Vote { term, .. }
+            | Message::Append { term, .. }
+            | Message::Appended { term, .. } => Some(term),
+            Message::Propose { .. } => None,
+        }
+    }
+}
+
+/// What a robot must remember across restarts
+#[derive(Serialize, Deserialize, Debug)]
+struct HardState<C> {
+    term: Term,
+    voted_for: Option<AppId>,
+    log: Vec<Entry<C>>,
+}
+
+impl<C: DeserializeOwned> HardState<C> {
+    fn load(path: &Path) -> io::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// `HardState` borrowing the log of the robot, to save it without copying it
+#[derive(Serialize)]
+struct HardStateRef<'a, C> {
+    term: Term,
+    voted_for: Option<AppId>,
+    log: &'a [Entry<C>],
+}
+
+impl<C: Serialize> HardStateRef<'_, C> {
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string(self)?;
+        // for atomic writes
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, json)?;
+        fs::rename(&temp, path)
+    }
+}
+
+/// Term, vote, and index and term of the last entry: two logs ending with the same entry
+/// are the same, so the state changed if this did
+type Version = (Term, Option<AppId>, Index, Term);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+#[derive(Debug)]
+pub struct Raft<C> {
+    id: AppId,
+    term: Term,
+    role: Role,
+    voted_for: Option<AppId>,
+    votes: HashSet<AppId>,
+    leader: Option<AppId>,
+    // Ticks since the last heartbeat, or since the start of the election
+    elapsed: u32,
+    timeout: u32,
+    // Number of ticks since the start, and when each robot was last heard of
+    ticks: u64,
+    peers: HashMap<AppId, u64>,
+    cluster_size: Option<usize>,
+    rng: StdRng,
+    log: Vec<Entry<C>>,
+    // Last committed and last applied entries
+    commit: Index,
+    applied: Index,
+    // Leader only: next entry to send to each follower, and last one known to match
+    next_index: HashMap<AppId, Index>,
+    match_index: HashMap<AppId, Index>,
+    // Commands proposed while no leader is known
+    pending: Vec<C>,
+    // Where the term, the vote and the log are saved, and the version last saved there
+    state_file: Option<PathBuf>,
+    saved: Version,
+}
+
+impl<C: Clone + Serialize + DeserializeOwned> Raft<C> {
+    pub fn new(id: AppId) -> Self {
+        let mut raft = Raft {
+            id,
+            term: 0,
+            role: Role::Follower,
+            voted_for: None,
+            votes: HashSet::new(),
+            leader: None,
+            elapsed: 0,
+            timeout: ELECTION_TIMEOUT,
+            ticks: 0,
+            peers: HashMap::new(),
+            cluster_size: None,
+            rng: StdRng::seed_from_u64(u64::from(id)),
+            log: Vec::new(),
+            commit: 0,
+            applied: 0,
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            pending: Vec::new(),
+            state_file: None,
+            saved: (0, None, 0, 0),
+        };
+        raft.reset_timeout();
+        raft
+    }
+
+    /// Number of robots in the swarm, the majority is computed among the live robots if None
+    pub fn set_cluster_size(&mut self, size: Option<usize>) {
+        self.cluster_size = size;
+    }
+
+    /// Saves the term, the vote and the log in `path` before answering,
+    /// restoring them if it exists
+    pub fn persist_to(&mut self, path: PathBuf) -> io::Result<()> {
+        if path.exists() {
+            let state = HardState::load(&path)?;
+            log::info!(
+                "restored term {} and {} entries from {:?}",
+                state.term,
+                state.log.len(),
+                path
+            );
+            self.term = state.term;
+            self.voted_for = state.voted_for;
+            self.log = state.log;
+            self.saved = self.version();
+        }
+        self.state_file = Some(path);
+        Ok(())
+    }
+
+    fn version(&self) -> Version {
+        let last_index = self.last_index();
+        (
+            self.term,
+            self.voted_for,
+            last_index,
+            self.term_at(last_index),
+        )
+    }
+
+    /// The messages are dropped if the state changed and could not be saved
+    fn persisted(&mut self, messages: Vec<Message<C>>) -> Vec<Message<C>> {
+        let version = self.version();
+        let path = match &self.state_file {
+            Some(path) if version != self.saved => path,
+            _ => return messages,
+        };
+        let state = HardStateRef {
+            term: self.term,
+            voted_for: self.voted_for,
+            log: &self.log,
+        };
+        match state.save(path) {
+            Ok(()) => {
+                self.saved = version;
+                messages
+            }
+            Err(e) => {
+                log::error!("could not save the term to {:?}, {}", path, e);
+                Vec::new()
+            }
+        }
+    }
+
+    pub fn leader(&self) -> Option<AppId> {
+        self.leader
+    }
+
+    #[allow(dead_code)]
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    #[allow(dead_code)]
+    pub fn term(&self) -> Term {
+        self.term
+    }
+
+    /// A message was received from `robot`
+    pub fn observe(&mut self, robot: AppId) {
+        if robot != self.id {
+            self.peers.insert(robot, self.ticks);
+        }
+    }
+
+    fn quorum(&self) -> usize {
+        let size = self.cluster_size.unwrap_or_else(|| {
+            let live = self
+                .peers
+                .values()
+                .filter(|&&heard| heard + PEER_TIMEOUT >= self.ticks)
+                .count();
+            live + 1
+        });
+        size / 2 + 1
+    }
+
+    fn reset_timeout(&mut self) {
+        self.elapsed = 0;
+        self.timeout = self.rng.gen_range(ELECTION_TIMEOUT, 2 * ELECTION_TIMEOUT);
+    }
+
+    fn last_index(&self) -> Index {
+        self.log.len()
+    }
+
+    fn term_at(&self, index: Index) -> Term {
+        if index == 0 {
+            0
+        } else {
+            self.log[index - 1].term
+        }
+    }
+
+    /// Proposes a command, returns the messages to broadcast.
+    /// The command is forwarded to the leader, or kept until one is known.
+    pub fn propose(&mut self, command: C) -> Vec<Message<C>> {
+        let messages = self.submit(command);
+        self.persisted(messages)
+    }
+
+    fn submit(&mut self, command: C) -> Vec<Message<C>> {
+        match self.leader {
+            Some(leader) if leader == self.id => {
+                self.log.push(Entry {
+                    term: self.term,
+                    command: Some(command),
+                });
+                self.advance_commit();
+                self.append_all()
+            }
+            Some(leader) => vec![Message::Propose {
+                to: leader,
+                command,
+            }],
+            None => {
+                self.pending.push(command);
+                Vec::new()
+            }
+        }
+    }
+
+    /// The commands committed since the last call, in the order of the log
+    pub fn take_committed(&mut self) -> Vec<C> {
+        let committed = self.log[self.applied..self.commit]
+            .iter()
+            .filter_map(|entry| entry.command.clone())
+            .collect();
+        self.applied = self.commit;
+        committed
+    }
+
+    /// Called periodically, returns the messages to broadcast
+    pub fn tick(&mut self) -> Vec<Message<C>> {
+        let messages = self.elapse();
+        self.persisted(messages)
+    }
+
+    fn elapse(&mut self) -> Vec<Message<C>> {
+        self.ticks += 1;
+        if self.role == Role::Leader {
+            // heartbeats
+            return self.append_all();
+        }
+        let mut messages = Vec::new();
+        if self.leader.is_some() {
+            for command in std::mem::take(&mut self.pending) {
+                messages.extend(self.submit(command));
+            }
+        }
+        self.elapsed += 1;
+        if self.elapsed < self.timeout {
+            return messages;
+        }
+
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.voted_for = Some(self.id);
+        self.votes = [self.id].iter().cloned().collect();
+        self.leader = None;
+        self.reset_timeout();
+        log::info!("candidate for term {}", self.term);
+        if self.votes.len() >= self.quorum() {
+            messages.extend(self.become_leader());
+        } else {
+            messages.push(Message::RequestVote {
+                term: self.term,
+                last_index: self.last_index(),
+                last_term: self.term_at(self.last_index()),
+            });
+        }
+        messages
+    }
+
+    fn become_leader(&mut self) -> Vec<Message<C>> {
+        log::info!("leader for term {}", self.term);
+        self.role = Role::Leader;
+        self.leader = Some(self.id);
+        self.next_index.clear();
+        self.match_index.clear();
+        self.log.push(Entry {
+            term: self.term,
+            command: None,
+        });
+        for command in std::mem::take(&mut self.pending) {
+            self.log.push(Entry {
+                term: self.term,
+                command: Some(command),
+            });
+        }
+        self.advance_commit();
+        self.append_all()
+    }
+
+    /// Entries for each known robot, heartbeats if they are up to date
+    fn append_all(&mut self) -> Vec<Message<C>> {
+        let mut peers: Vec<AppId> = self.peers.keys().cloned().collect();
+        // in order, for reproducible simulations
+        peers.sort_unstable();
+        peers.into_iter().map(|peer| self.append(peer)).collect()
+    }
+
+    fn append(&mut self, peer: AppId) -> Message<C> {
+        let last_index = self.last_index();
+        let next = *self.next_index.entry(peer).or_insert(last_index + 1);
+        let prev_index = next - 1;
+        let end = self.log.len().min(prev_index + MAX_ENTRIES);
+        Message::Append {
+            term: self.term,
+            to: peer,
+            prev_index,
+            prev_term: self.term_at(prev_index),
+            entries: self.log[prev_index..end].to_vec(),
+            commit: self.commit,
+        }
+    }
+
+    /// Commits the last entry of the current term which a majority of the robots have
+    fn advance_commit(&mut self) {
+        let quorum = self.quorum();
+        for index in (self.commit + 1..=self.last_index()).rev() {
+            if self.term_at(index) != self.term {
+                break;
+            }
+            let replicas = 1 + self
+                .match_index
+                .values()
+                .filter(|&&matched| matched >= index)
+                .count();
+            if replicas >= quorum {
+                self.commit = index;
+                break;
+            }
+        }
+    }
+
+    /// Handles a message sent by `sender`, returns the messages to broadcast
+    pub fn handle(&mut self, sender: AppId, message: Message<C>) -> Vec<Message<C>> {
+        let messages = self.receive(sender, message);
+        self.persisted(messages)
+    }
+
+    fn receive(&mut self, sender: AppId, message: Message<C>) -> Vec<Message<C>> {
+        self.observe(sender);
+        if let Some(term) = message.term() {
+            if term > self.term {
+                self.term = term;
+                self.role = Role::Follower;
+                self.voted_for = None;
+                self.leader = None;
+            }
+        }
+
+        match message {
+            Message::RequestVote {
+                term,
+                last_index,
+                last_term,
+            } => {
+                // only vote for candidates having all our entries
+                let up_to_date =
+                    (last_term, last_index) >= (self.term_at(self.last_index()), self.last_index());
+                let granted = term == self.term
+                    && self.role == Role::Follower
+                    && up_to_date
+                    && (self.voted_for.is_none() || self.voted_for == Some(sender));
+                if granted {
+                    self.voted_for = Some(sender);
+                    self.reset_timeout();
+                }
+                vec![Message::Vote {
+                    term: self.term,
+                    candidate: sender,
+                    granted,
+                }]
+            }
+            Message::Vote {
+                term,
+                candidate,
+                granted,
+            } => {
+                if granted
+                    && candidate == self.id
+                    && term == self.term
+                    && self.role == Role::Candidate
+                {
+                    self.votes.insert(sender);
+                    if self.votes.len() >= self.quorum() {
+                        return self.become_leader();
+                    }
+                }
+                Vec::new()
+            }
+            Message::Append {
+                term,
+                to,
+                prev_index,
+                prev_term,
+                entries,
+                commit,
+            } => {
+                if to != self.id {
+                    return Vec::new();
+                }
+                if term < self.term {
+                    return vec![Message::Appended {
+                        term: self.term,
+                        to: sender,
+                        success: false,
+                        index: prev_index,
+                    }];
+                }
+                // two leaders of the same term can only be elected with a dynamic majority,
+                // the highest id wins
+                if self.role == Role::Leader && sender < self.id {
+                    return Vec::new();
+                }
+                if self.leader != Some(sender) {
+                    log::info!("{} is the leader for term {}", sender, term);
+                }
+                self.role = Role::Follower;
+                self.leader = Some(sender);
+                self.reset_timeout();
+
+                if prev_index > self.last_index() || self.term_at(prev_index) != prev_term {
+                    return vec![Message::Appended {
+                        term: self.term,
+                        to: sender,
+                        success: false,
+                        index: self.last_index().min(prev_index.saturating_sub(1)),
+                    }];
+                }
+                let matched = prev_index + entries.len();
+                for (index, entry) in (prev_index + 1..).zip(entries) {
+                    if index <= self.last_index() && self.term_at(index) != entry.term {
+                        // conflicting entries which were never committed
+                        self.log.truncate(index - 1);
+                    }
+                    if index > self.last_index() {
+                        self.log.push(entry);
+                    }
+                }
+                self.commit = self.commit.max(commit.min(matched));
+                vec![Message::Appended {
+                    term: self.term,
+                    to: sender,
+                    success: true,
+                    index: matched,
+                }]
+            }
+            Message::Appended {
+                term,
+                to,
+                success,
+                index,
+            } => {
+                if to != self.id || term != self.term || self.role != Role::Leader {
+                    return Vec::new();
+                }
+                if success {
+                    // not the max, the follower may have lost entries when restarted
+                    self.match_index.insert(sender, index);
+                    self.next_index.insert(sender, index + 1);
+                    self.advance_commit();
+                    Vec::new()
+                } else {
+                    let next = self.next_index.entry(sender).or_insert(1);
+                    *next = (*next - 1).min(index + 1).max(1);
+                    vec![self.append(sender)]
+                }
+            }
+            Message::Propose { to, command } => {
+                if to == self.id {
+                    self.submit(command)
+                } else {
+                    Vec::new()
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Robots exchanging messages instantly. The robots only receive the messages
+    /// of the robots in the same partition, and down robots are in none.
+    struct Network {
+        robots: Vec<Raft<u32>>,
+        partitions: Vec<Option<u32>>,
+        applied: Vec<Vec<u32>>,
+        leaders: HashMap<Term, AppId>,
+        // Where the robots save their state, if they do
+        state_files: Vec<PathBuf>,
+    }
+
+    impl Network {
+        fn new(size: usize, cluster_size: Option<usize>) -> Self {
+            let robots = (0..size)
+                .map(|i| {
+                    let mut raft = Raft::new(i as AppId + 1);
+                    raft.set_cluster_size(cluster_size);
+                    raft
+                })
+                .collect();
+            Network {
+                robots,
+                partitions: vec![Some(0); size],
+                applied: vec![Vec::new(); size],
+                leaders: HashMap::new(),
+                state_files: Vec::new(),
+            }
+        }
+
+        /// The robots save their state, to be restarted
+        fn persist(&mut self, name: &str) {
+            for robot in self.robots.iter_mut() {
+                let file = format!("raft_{}_{}_{}.json", name, std::process::id(), robot.id);
+                let path = std::env::temp_dir().join(file);
+                robot.persist_to(path.clone()).unwrap();
+                self.state_files.push(path);
+            }
+        }
+
+        /// The robot forgets everything but its saved state
+        fn restart(&mut self, id: AppId) {
+            let i = Self::index(id);
+            let mut raft = Raft::new(id);
+            raft.set_cluster_size(self.robots[i].cluster_size);
+            raft.persist_to(self.state_files[i].clone()).unwrap();
+            self.robots[i] = raft;
+            self.applied[i].clear();
+        }
+
+        fn index(id: AppId) -> usize {
+            id as usize - 1
+        }
+
+        fn broadcast(&mut self, sender: usize, messages: Vec<Message<u32>>) {
+            let mut queue: Vec<(usize, Message<u32>)> =
+                messages.into_iter().map(|m| (sender, m)).collect();
+            while let Some((sender, message)) = queue.pop() {
+                let id = self.robots[sender].id;
+                for i in 0..self.robots.len() {
+                    if i != sender
+                        && self.partitions[i].is_some()
+                        && self.partitions[i] == self.partitions[sender]
+                    {
+                        let replies = self.robots[i].handle(id, message.clone());
+                        queue.extend(replies.into_iter().map(|m| (i, m)));
+                    }
+                }
+            }
+        }
+
+        fn propose(&mut self, id: AppId, command: u32) {
+            let i = Self::index(id);
+            let messages = self.robots[i].propose(command);
+            self.broadcast(i, messages);
+        }
+
+        /// Runs `ticks` ticks, checking that there is at most one leader per term,
+        /// and that the robots apply the same commands in the same order
+        fn run(&mut self, ticks: usize) {
+            for _ in 0..ticks {
+                for i in 0..self.robots.len() {
+                    if self.partitions[i].is_some() {
+                        let messages = self.robots[i].tick();
+                        self.broadcast(i, messages);
+                    }
+                }
+                for (robot, applied) in self.robots.iter_mut().zip(self.applied.iter_mut()) {
+                    applied.extend(robot.take_committed());
+                    if robot.role() == Role::Leader {
+                        let leader = self.leaders.entry(robot.term()).or_insert(robot.id);
+                        assert_eq!(*leader, robot.id, "two leaders in term {}", robot.term());
+                    }
+                }
+                for (a, b) in self.applied.iter().zip(self.applied.iter().skip(1)) {
+                    let common = a.len().min(b.len());
+                    assert_eq!(a[..common], b[..common]);
+                }
+            }
+        }
+
+        fn live(&self) -> impl Iterator<Item = &Raft<u32>> {
+            self.robots
+                .iter()
+                .zip(self.partitions.iter())
+                .filter(|(_, partition)| partition.is_some())
+                .map(|(robot, _)| robot)
+        }
+
+        /// The leader all the live robots agree on
+        fn leader(&self) -> Option<AppId> {
+            let leaders: HashSet<Option<AppId>> = self.live().map(Raft::leader).collect();
+            assert_eq!(leaders.len(), 1, "{:?}", leaders);
+            leaders.into_iter().next().unwrap()
+        }
+    }
+
+    #[test]
+    fn election_test() {
+        let mut network = Network::new(5, Some(5));
+        network.run(30);
+        let leader = network.leader().expect("a leader should be elected");
+        assert_eq!(
+            network
+                .live()
+                .filter(|robot| robot.role() == Role::Leader)
+                .count(),
+            1
+        );
+
+        // the leader stays as long as it sends heartbeats
+        let term = network.robots[0].term();
+        network.run(30);
+        assert_eq!(network.leader(), Some(leader));
+        assert_eq!(network.robots[0].term(), term);
+    }
+
+    #[test]
+    fn leader_failure_test() {
+        let mut network = Network::new(5, Some(5));
+        network.run(30);
+        let leader = network.leader().unwrap();
+        let term = network.robots[0].term();
+
+        network.partitions[Network::index(leader)] = None;
+        network.run(30);
+        let new_leader = network.leader().expect("a new leader should be elected");
+        assert_ne!(new_leader, leader);
+        assert!(network.live().all(|robot| robot.term() > term));
+
+        // the old leader comes back and follows the new one
+        network.partitions[Network::index(leader)] = Some(0);
+        network.run(5);
+        assert_eq!(network.leader(), Some(new_leader));
+    }
+
+    #[test]
+    fn no_majority_test() {
+        let mut network = Network::new(5, Some(5));
+        for partition in network.partitions.iter_mut().skip(2) {
+            *partition = None;
+        }
+        network.run(50);
+        assert_eq!(network.leader(), None);
+    }
+
+    #[test]
+    fn dynamic_majority_test() {
+        let mut network = Network::new(2, None);
+        // the greetings
+        network.robots[0].observe(2);
+        network.robots[1].observe(1);
+        network.run(30);
+        let leader = network.leader().expect("a leader should be elected");
+
+        // the last robot elects itself once the failed one is forgotten
+        network.partitions[Network::index(leader)] = None;
+        network.run(PEER_TIMEOUT as usize);
+        assert_eq!(network.leader(), None);
+        network.run(30);
+        assert_eq!(network.leader(), Some(3 - leader));
+    }
+
+    #[test]
+    fn two_leaders_test() {
+        // elected alone before hearing of each other
+        let mut robots: Vec<Raft<u32>> = (1..=2).map(Raft::new).collect();
+        for robot in robots.iter_mut() {
+            while robot.role() != Role::Leader {
+                robot.tick();
+            }
+        }
+        assert_eq!(robots[0].term(), robots[1].term());
+        robots[0].observe(2);
+        robots[1].observe(1);
+
+        let (a, b) = (robots[0].tick(), robots[1].tick());
+        for message in b {
+            robots[0].handle(2, message);
+        }
+        for message in a {
+            robots[1].handle(1, message);
+        }
+        assert_eq!(robots[0].role(), Role::Follower);
+        assert_eq!(robots[0].leader(), Some(2));
+        assert_eq!(robots[1].role(), Role::Leader);
+    }
+
+    #[test]
+    fn replication_test() {
+        let mut network = Network::new(5, Some(5));
+        // proposed before any leader is known
+        network.propose(1, 10);
+        network.run(30);
+        let leader = network.leader().unwrap();
+        let follower = leader % 5 + 1;
+        network.propose(leader, 11);
+        network.propose(follower, 12);
+        network.propose(leader, 13);
+        network.run(5);
+        for applied in network.applied.iter() {
+            assert_eq!(applied, &[10, 11, 12, 13]);
+        }
+    }
+
+    #[test]
+    fn partition_test() {
+        let mut network = Network::new(5, Some(5));
+        network.propose(1, 1);
+        network.run(30);
+        let old_leader = network.leader().unwrap();
+        let minority = [old_leader, old_leader % 5 + 1];
+        for &id in minority.iter() {
+            network.partitions[Network::index(id)] = Some(1);
+        }
+
+        // the old leader cannot commit without a majority
+        network.propose(old_leader, 2);
+        network.run(30);
+        for &id in minority.iter() {
+            assert_eq!(network.applied[Network::index(id)], [1]);
+        }
+        // the majority elects a new leader and goes on
+        let new_leader = (1..=5)
+            .find(|id| {
+                !minority.contains(id) && network.robots[Network::index(*id)].role() == Role::Leader
+            })
+            .expect("the majority should elect a leader");
+        network.propose(new_leader, 3);
+        network.run(5);
+        assert_eq!(network.applied[Network::index(new_leader)], [1, 3]);
+
+        // once healed, the minority drops its uncommitted entry and catches up
+        for partition in network.partitions.iter_mut() {
+            *partition = Some(0);
+        }
+        network.run(10);
+        assert_eq!(network.leader(), Some(new_leader));
+        for (robot, applied) in network.robots.iter().zip(network.applied.iter()) {
+            assert_eq!(applied, &[1, 3]);
+            assert_eq!(robot.log, network.robots[Network::index(new_leader)].log);
+        }
+    }
+
+    #[test]
+    fn crash_test() {
+        let mut network = Network::new(3, Some(3));
+        network.persist("crash");
+        network.run(30);
+        let leader = network.leader().unwrap();
+        let (a, b) = (leader % 3 + 1, (leader + 1) % 3 + 1);
+
+        // committed by the leader and a, b misses it
+        network.partitions[Network::index(b)] = None;
+        network.propose(leader, 7);
+        network.run(5);
+        assert_eq!(network.applied[Network::index(leader)], [7]);
+
+        // a restarts, then the leader fails: b, which does not have the entry,
+        // must not be elected with the vote of a
+        network.restart(a);
+        network.partitions[Network::index(leader)] = None;
+        network.partitions[Network::index(b)] = Some(0);
+        network.run(50);
+        assert_eq!(network.leader(), Some(a));
+        assert_eq!(network.applied[Network::index(a)], [7]);
+        assert_eq!(network.applied[Network::index(b)], [7]);
+        for path in network.state_files.iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn restart_test() {
+        let path = std::env::temp_dir().join(format!("raft_{}.json", std::process::id()));
+        let request = |term| Message::<u32>::RequestVote {
+            term,
+            last_index: 0,
+            last_term: 0,
+        };
+        let mut raft = Raft::<u32>::new(3);
+        raft.persist_to(path.clone()).unwrap();
+        match raft.handle(1, request(4)).as_slice() {
+            [Message::Vote { granted: true, .. }] => (),
+            replies => panic!("unexpected replies {:?}", replies),
+        }
+
+        // restarted, it remembers its vote in this term
+        let mut raft = Raft::<u32>::new(3);
+        raft.persist_to(path.clone()).unwrap();
+        assert_eq!(raft.term(), 4);
+        match raft.handle(2, request(4)).as_slice() {
+            [Message::Vote { granted: false, .. }] => (),
+            replies => panic!("unexpected replies {:?}", replies),
+        }
+        match raft.handle(2, request(5)).as_slice() {
+            [Message::Vote { granted: true, .. }] => (),
+            replies => panic!("unexpected replies {:?}", replies),
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
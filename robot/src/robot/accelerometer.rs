@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+
+use rand::distributions::{Distribution, Normal};
+use rand::rngs::StdRng;
+use rand::{FromEntropy, SeedableRng};
+
+use crate::map::Acceleration;
+
+/// Number of acceleration norms kept, and returned by `Robot::lacc`
+const HISTORY_LEN: usize = 10;
+/// Standard deviation of the measurement noise, m/s²
+const MEASUREMENT_NOISE: f64 = 0.05;
+
+/// Parameters of the collision detection, see `Robot::tune`
+#[derive(Copy, Clone, Debug)]
+pub struct CollisionParams {
+    pub nb_acc_for_mean: usize,
+    pub nb_consec_mean: usize,
+    pub mean_threshold: Acceleration,
+}
+
+impl Default for CollisionParams {
+    fn default() -> Self {
+        // the robot accelerates at 2 m/s² at most,
+        // hitting a wall at 10 cm/s makes a spike of 10 m/s²
+        CollisionParams {
+            nb_acc_for_mean: 3,
+            nb_consec_mean: 2,
+            mean_threshold: 3.,
+        }
+    }
+}
+
+/// Simulated accelerometer, detects collisions from the acceleration norms
+pub struct Accelerometer {
+    params: CollisionParams,
+    // Last acceleration norms, the most recent at the back
+    history: VecDeque<Acceleration>,
+    // Number of consecutive means above the threshold
+    consec_means: usize,
+    // Measures to ignore after a detection, so that an impact is detected once
+    refractory: usize,
+    // Speed of the robot at the last measure
+    last_speed: f32,
+    rng: StdRng,
+}
+
+impl Accelerometer {
+    pub fn new(params: CollisionParams) -> Self {
+        Accelerometer {
+            params,
+            history: VecDeque::new(),
+            consec_means: 0,
+            refractory: 0,
+            last_speed: 0.,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Makes the measurement noise reproducible
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    #[allow(dead_code)]
+    pub fn tune(&mut self, params: CollisionParams) {
+        self.params = params;
+        self.consec_means = 0;
+    }
+
+    /// The last acceleration norms, the most recent last
+    pub fn last(&self) -> Vec<Acceleration> {
+        let skip = self.history.len().saturating_sub(HISTORY_LEN);
+        self.history.iter().skip(skip).cloned().collect()
+    }
+
+    /// Measures the acceleration of a robot moving at `speed` and turning at `angular_speed`
+    /// after `dt` seconds, returns true when a collision is detected.
+    /// Stopping against a wall makes a spike of `speed / dt`.
+    pub fn measure(&mut self, speed: f32, angular_speed: f32, dt: f32) -> bool {
+        let tangential = (speed - self.last_speed) / dt;
+        let centripetal = speed * angular_speed;
+        self.last_speed = speed;
+
+        let noise = Normal::new(0., MEASUREMENT_NOISE);
+        let x = tangential + noise.sample(&mut self.rng) as f32;
+        let y = centripetal + noise.sample(&mut self.rng) as f32;
+        self.push((x * x + y * y).sqrt())
+    }
+
+    fn push(&mut self, acc: Acceleration) -> bool {
+        self.history.push_back(acc);
+        while self.history.len() > HISTORY_LEN.max(self.params.nb_acc_for_mean) {
+            self.history.pop_front();
+        }
+
+        let n = self.params.nb_acc_for_mean.max(1);
+        if self.history.len() < n || self.refractory > 0 {
+            self.refractory = self.refractory.saturating_sub(1);
+            return false;
+        }
+        let mean = self.history.iter().rev().take(n).sum::<Acceleration>() / n as Acceleration;
+        if mean > self.params.mean_threshold {
+            self.consec_means += 1;
+        } else {
+            self.consec_means = 0;
+        }
+
+        if self.consec_means >= self.params.nb_consec_mean {
+            self.consec_means = 0;
+            self.refractory = n - 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The robot is not moving anymore
+    pub fn reset(&mut self) {
+        self.last_speed = 0.;
+        self.consec_means = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detection_test() {
+        let mut acc = Accelerometer::new(CollisionParams::default());
+        // accelerating at 2 m/s² is not a collision
+        for i in 1..=20 {
+            assert!(!acc.measure(i as f32 * 0.02, 0., 0.01));
+        }
+        assert_eq!(acc.last().len(), HISTORY_LEN);
+        assert!(acc.last().iter().all(|&a| (a - 2.).abs() < 0.5));
+
+        // stopping from 40 cm/s in one step is
+        assert!(!acc.measure(0., 0., 0.01));
+        assert!(acc.measure(0., 0., 0.01));
+        assert!(*acc.last().iter().rev().nth(1).unwrap() > 30.);
+        // the impact is detected once
+        for _ in 0..10 {
+            assert!(!acc.measure(0., 0., 0.01));
+        }
+    }
+
+    #[test]
+    fn tune_test() {
+        let mut acc = Accelerometer::new(CollisionParams::default());
+        acc.tune(CollisionParams {
+            nb_acc_for_mean: 1,
+            nb_consec_mean: 1,
+            mean_threshold: 1.,
+        });
+        assert!(!acc.measure(0., 0., 0.01));
+        assert!(acc.measure(0.1, 0., 0.01));
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::VecDeque;
+
+use super::Event::{self, *};
+use crate::map::{normalize_angle, Angle, Distance, Point, PolyMap, Position, Segment};
+
+const PI: Angle = std::f32::consts::PI;
+/// Distance kept between the robot and the wall it bumped into
+const COLLISION_BACKOFF: Distance = 0.005;
+/// Distance covered by the wheels against a wall before the bumpers report the collision,
+/// when it was not detected from the accelerations
+const STUCK_LIMIT: Distance = 0.2;
+/// Under this, a turn or a move is considered done
+const EPSILON: f32 = 1e-5;
+
+/// Physical limits of the robot
+#[derive(Copy, Clone, Debug)]
+pub struct DriveParams {
+    /// m/s
+    pub max_speed: Distance,
+    /// m/s²
+    pub max_acceleration: Distance,
+    /// rad/s
+    pub max_angular_speed: Angle,
+}
+
+impl Default for DriveParams {
+    fn default() -> Self {
+        DriveParams {
+            max_speed: 1.,
+            max_acceleration: 2.,
+            max_angular_speed: PI,
+        }
+    }
+}
+
+/// Elementary motions a differential drive robot can do
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Primitive {
+    /// Turn in place, signed angle in radians
+    Turn(Angle),
+    /// Move along the current heading, signed distance in meters
+    Move(Distance),
+}
+
+use Primitive::*;
+
+/// Differential drive model: the robot either turns in place
+/// or moves straight, following a trapezoidal speed profile.
+/// When it hits a wall the robot stops but its wheels keep turning,
+/// until the collision is detected or the bumpers report it.
+#[derive(Debug)]
+pub struct DiffDrive {
+    pub pose: Position,
+    /// Current linear speed of the wheels, m/s, negative when going backward
+    pub speed: Distance,
+    /// Current angular speed, rad/s
+    pub angular_speed: Angle,
+    /// Distance and angle covered by the wheels during the last step
+    pub wheel_motion: (Distance, Angle),
+    // Progress of the current move when the robot hit a wall
+    stuck: Option<Distance>,
+    params: DriveParams,
+    // Motions left to do, the front one is in progress
+    plan: VecDeque<Primitive>,
+    // Pose at the start of the current primitive
+    origin: Position,
+    // Absolute progress of the current primitive (m or rad)
+    done: f32,
+}
+
+/// Unit vector pointing in front of a robot with heading `a`
+pub fn heading(a: Angle) -> Point {
+    Point { x: 0., y: 1. }.rotate(a)
+}
+
+impl DiffDrive {
+    pub fn new(pose: Position, params: DriveParams) -> Self {
+        DiffDrive {
+            pose,
+            speed: 0.,
+            angular_speed: 0.,
+            wheel_motion: (0., 0.),
+            stuck: None,
+            params,
+            plan: VecDeque::new(),
+            origin: pose,
+            done: 0.,
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.plan.is_empty()
+    }
+
+    /// Replaces the current motion by a turn towards `dest` followed by a straight move,
+    /// planned from `from`, the pose the robot believes it has
+    pub fn go_to(&mut self, from: Position, dest: Point) -> Vec<Event> {
+        let events = self.cancel();
+        let delta = dest - from.p;
+        let turn = normalize_angle(delta.rotate_deg(-90.).angle() - from.a);
+        if delta.norm() > EPSILON && turn.abs() > EPSILON {
+            self.plan.push_back(Turn(turn));
+        }
+        self.plan.push_back(Move(delta.norm()));
+        events
+    }
+
+    /// Replaces the current motion by a straight move, backward if `dist` is negative
+    pub fn forward(&mut self, dist: Distance) -> Vec<Event> {
+        let events = self.cancel();
+        self.plan.push_back(Move(dist));
+        events
+    }
+
+    /// Replaces the current motion by a turn in place
+    #[allow(dead_code)]
+    pub fn turn(&mut self, angle: Angle) -> Vec<Event> {
+        let events = self.cancel();
+        self.plan.push_back(Turn(angle));
+        events
+    }
+
+    /// Stops on a detected collision
+    pub fn collide(&mut self) -> Vec<Event> {
+        let mut events = self.cancel();
+        events.push(Collision(self.pose));
+        events
+    }
+
+    /// Stops immediately, reporting what has been done of the current primitive
+    pub fn cancel(&mut self) -> Vec<Event> {
+        let events = match self.plan.front() {
+            Some(&Turn(a)) => vec![Turned(a.signum() * self.done)],
+            Some(&Move(d)) => vec![Moved(d.signum() * self.done)],
+            None => vec![],
+        };
+        self.halt();
+        events
+    }
+
+    fn halt(&mut self) {
+        self.plan.clear();
+        self.origin = self.pose;
+        self.stuck = None;
+        self.done = 0.;
+        self.speed = 0.;
+        self.angular_speed = 0.;
+    }
+
+    /// Starts the next primitive, the destination is reached if there is none
+    fn next_primitive(&mut self, events: &mut Vec<Event>) {
+        self.plan.pop_front();
+        self.origin = self.pose;
+        self.stuck = None;
+        self.done = 0.;
+        self.speed = 0.;
+        self.angular_speed = 0.;
+        if self.plan.is_empty() {
+            events.push(Reached(self.pose));
+        }
+    }
+
+    /// Integrates the pose over `dt` seconds, the robot is stopped by the walls of `map`
+    pub fn step(&mut self, dt: f32, map: &PolyMap) -> Vec<Event> {
+        let mut events = Vec::new();
+        self.wheel_motion = (0., 0.);
+        match self.plan.front() {
+            None => (),
+            Some(&Turn(angle)) => {
+                let remaining = angle.abs() - self.done;
+                let delta = (self.params.max_angular_speed * dt).min(remaining);
+                self.angular_speed = angle.signum() * delta / dt;
+                self.wheel_motion = (0., angle.signum() * delta);
+                self.done += delta;
+                self.pose.a = normalize_angle(self.origin.a + angle.signum() * self.done);
+                if angle.abs() - self.done < EPSILON {
+                    self.pose.a = normalize_angle(self.origin.a + angle);
+                    events.push(Turned(angle));
+                    self.next_primitive(&mut events);
+                }
+            }
+            Some(&Move(dist)) => {
+                let remaining = dist.abs() - self.done;
+                let acc = self.params.max_acceleration;
+                // highest speed from which we can still stop before the end,
+                // i.e. v such that v * dt + v² / 2acc = remaining
+                let stoppable = (acc * acc * dt * dt + 2. * acc * remaining).sqrt() - acc * dt;
+                let target = self.params.max_speed.min(stoppable);
+                let speed = (self.speed.abs() + acc * dt).min(target);
+                let ds = (speed * dt).min(remaining);
+                let dir = heading(self.origin.a) * dist.signum();
+                let dest = self.origin.p + dir * (self.done + ds);
+
+                if self.stuck.is_none() {
+                    if let Some(wall) = map.first_intersection(&Segment(self.pose.p, dest)) {
+                        let before_wall = (wall - self.origin.p).norm() - COLLISION_BACKOFF;
+                        self.pose.p = self.origin.p + dir * before_wall.max(0.);
+                        self.stuck = Some(self.done);
+                    } else {
+                        self.pose.p = dest;
+                    }
+                }
+
+                self.speed = dist.signum() * speed;
+                self.wheel_motion = (dist.signum() * ds, 0.);
+                self.done += ds;
+                let finished = dist.abs() - self.done < EPSILON;
+                if let Some(hit) = self.stuck {
+                    if finished || self.done - hit >= STUCK_LIMIT {
+                        events.extend(self.collide());
+                    }
+                } else if finished {
+                    self.pose.p = self.origin.p + dir * dist.abs();
+                    events.push(Moved(dist));
+                    self.next_primitive(&mut events);
+                }
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.01;
+
+    fn empty_map() -> PolyMap {
+        PolyMap::new(vec![])
+    }
+
+    /// Steps until the plan is over, returns the events and the simulated time
+    fn run(drive: &mut DiffDrive, map: &PolyMap) -> (Vec<Event>, f32) {
+        let mut events = Vec::new();
+        let mut t = 0.;
+        while !drive.is_idle() && t < 100. {
+            events.extend(drive.step(DT, map));
+            t += DT;
+        }
+        (events, t)
+    }
+
+    #[test]
+    fn go_to_test() {
+        let map = empty_map();
+        let mut drive = DiffDrive::new(Position::default(), DriveParams::default());
+        let dest = Point { x: 1., y: 0. };
+        drive.go_to(drive.pose, dest);
+        let (events, t) = run(&mut drive, &map);
+
+        assert_eq!(drive.pose.p, dest);
+        // facing +x
+        assert!((drive.pose.a + PI / 2.).abs() < 1e-4);
+        match events.as_slice() {
+            [Turned(a), Moved(d), Reached(p)] => {
+                assert!((a + PI / 2.).abs() < 1e-4);
+                assert!((d - 1.).abs() < 1e-4);
+                assert_eq!(p.p, dest);
+            }
+            _ => panic!("unexpected events {:?}", events),
+        }
+        // turning takes 0.5s, moving 1m with a triangular profile takes ~1.41s
+        assert!(t > 1.8 && t < 2.1, "took {}s", t);
+    }
+
+    #[test]
+    fn speed_limits_test() {
+        let map = empty_map();
+        let params = DriveParams::default();
+        let mut drive = DiffDrive::new(Position::default(), params);
+        drive.forward(-3.);
+        let mut prev_speed: f32 = 0.;
+        while !drive.is_idle() {
+            drive.step(DT, &map);
+            if drive.is_idle() {
+                // the last step may end with a small jerk
+                assert!(prev_speed.abs() <= 2. * params.max_acceleration * DT);
+                break;
+            }
+            assert!(drive.speed.abs() <= params.max_speed + 1e-4);
+            assert!((drive.speed - prev_speed).abs() <= params.max_acceleration * DT + 1e-4);
+            prev_speed = drive.speed;
+        }
+        assert_eq!(drive.pose.p, Point { x: 0., y: -3. });
+    }
+
+    #[test]
+    fn stuck_test() {
+        let map = PolyMap::default();
+        let start = Position {
+            p: Point { x: 2., y: -1. },
+            a: 0.,
+        };
+        let mut drive = DiffDrive::new(start, DriveParams::default());
+        drive.forward(2.);
+        let (events, _) = run(&mut drive, &map);
+        // the collision was not detected from the accelerations, the bumpers report it
+        // once the wheels have turned against the wall
+        match events.as_slice() {
+            [Moved(d), Collision(p)] => {
+                assert!((d - (1. - COLLISION_BACKOFF + STUCK_LIMIT)).abs() < 0.02);
+                assert_eq!(p.p, drive.pose.p);
+            }
+            _ => panic!("unexpected events {:?}", events),
+        }
+        assert!((drive.pose.p.y + COLLISION_BACKOFF).abs() < 1e-4);
+        assert!(drive.is_idle());
+
+        // and at the end of a move ending against the wall
+        let mut drive = DiffDrive::new(start, DriveParams::default());
+        drive.forward(1.1);
+        let (events, _) = run(&mut drive, &map);
+        match events.as_slice() {
+            [Moved(d), Collision(_)] => assert!((d - 1.1).abs() < 1e-4),
+            _ => panic!("unexpected events {:?}", events),
+        }
+
+        let mut drive = DiffDrive::new(start, DriveParams::default());
+        drive.forward(2.);
+        for _ in 0..135 {
+            drive.step(DT, &map);
+        }
+        match drive.collide().as_slice() {
+            [Moved(d), Collision(p)] => {
+                assert!(*d > 1.);
+                assert!((p.p.y + COLLISION_BACKOFF).abs() < 1e-4);
+            }
+            events => panic!("unexpected events {:?}", events),
+        }
+        assert!(drive.is_idle());
+    }
+
+    #[test]
+    fn cancel_test() {
+        let map = empty_map();
+        let mut drive = DiffDrive::new(Position::default(), DriveParams::default());
+        drive.forward(1.);
+        for _ in 0..50 {
+            drive.step(DT, &map);
+        }
+        let moved = drive.pose.p.norm();
+        match drive.turn(1.).as_slice() {
+            [Moved(d)] => assert!((d - moved).abs() < 1e-4),
+            events => panic!("unexpected events {:?}", events),
+        }
+        assert_eq!(drive.speed, 0.);
+        let (events, _) = run(&mut drive, &map);
+        assert_eq!(drive.pose.p.norm(), moved);
+        assert!((drive.pose.a - 1.).abs() < 1e-4);
+        assert_eq!(events.len(), 2);
+    }
+}
@@ -0,0 +1,184 @@
+use rand::distributions::{Distribution, Normal};
+use rand::rngs::StdRng;
+use rand::{FromEntropy, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use super::kinematics::heading;
+use super::Event::{self, *};
+use crate::map::{normalize_angle, Angle, Distance, Position};
+
+/// Odometry and actuation errors of the simulated robot, none by default
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct NoiseParams {
+    /// Standard deviation of the translation error over one meter, in meters
+    pub translation: Distance,
+    /// Standard deviation of the rotation error over one radian, in radians
+    pub rotation: Angle,
+    /// Systematic heading error, in radians per meter travelled
+    pub drift: Angle,
+    /// Maximum distance counted by the wheels slipping on a collision, in meters
+    pub slip: Distance,
+}
+
+/// Dead reckoning of the robot, i.e. the pose it believes it has
+pub struct Odometry {
+    pub pose: Position,
+    params: NoiseParams,
+    rng: StdRng,
+    // Measured since the last `Moved` or `Turned` event
+    moved: Distance,
+    turned: Angle,
+}
+
+impl Odometry {
+    pub fn new(pose: Position, params: NoiseParams, seed: Option<u64>) -> Self {
+        Odometry {
+            pose,
+            params,
+            rng: seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+            moved: 0.,
+            turned: 0.,
+        }
+    }
+
+    fn gaussian(&mut self, std_dev: f32) -> f32 {
+        if std_dev > 0. {
+            Normal::new(0., f64::from(std_dev)).sample(&mut self.rng) as f32
+        } else {
+            0.
+        }
+    }
+
+    /// Integrates the motion of the wheels, `ds` along the heading and `da` around
+    /// the center of the robot, with errors
+    pub fn integrate(&mut self, (ds, da): (Distance, Angle)) {
+        let ds = ds + self.gaussian(self.params.translation * ds.abs().sqrt());
+        let da = da
+            + self.gaussian(self.params.rotation * da.abs().sqrt())
+            + self.params.drift * ds.abs();
+        self.advance(ds, da);
+    }
+
+    fn advance(&mut self, ds: Distance, da: Angle) {
+        // midpoint integration
+        self.pose.p = self.pose.p + heading(self.pose.a + da / 2.) * ds;
+        self.pose.a = normalize_angle(self.pose.a + da);
+        self.moved += ds;
+        self.turned += da;
+    }
+
+    /// The wheels keep spinning for a while after hitting a wall,
+    /// `direction` is the sign of the speed before the collision
+    pub fn slip(&mut self, direction: f32) {
+        if self.params.slip > 0. {
+            let ds = self.rng.gen_range(0., self.params.slip);
+            self.advance(direction * ds, 0.);
+        }
+    }
+
+    /// Replaces the true values of an event by the measured ones
+    pub fn report(&mut self, event: Event) -> Event {
+        match event {
+            Moved(_) => {
+                let moved = self.moved;
+                self.moved = 0.;
+                self.turned = 0.;
+                Moved(moved)
+            }
+            Turned(_) => {
+                let turned = self.turned;
+                self.moved = 0.;
+                self.turned = 0.;
+                Turned(turned)
+            }
+            Collision(_) => Collision(self.pose),
+            Reached(_) => Reached(self.pose),
+            Curr(_) => Curr(self.pose),
+            event => event,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Point;
+
+    #[test]
+    fn perfect_odometry_test() {
+        let mut odometry = Odometry::new(Position::default(), NoiseParams::default(), Some(0));
+        let moved = Position {
+            p: Point { x: 0., y: 1. },
+            a: 0.,
+        };
+        odometry.integrate((1., 0.));
+        assert_eq!(odometry.pose, moved);
+        match odometry.report(Moved(1.)) {
+            Moved(d) => assert!((d - 1.).abs() < 1e-6),
+            e => panic!("unexpected event {:?}", e),
+        }
+
+        let turned = Position { a: 1., ..moved };
+        odometry.integrate((0., 1.));
+        assert_eq!(odometry.pose, turned);
+        match odometry.report(Turned(1.)) {
+            Turned(a) => assert!((a - 1.).abs() < 1e-6),
+            e => panic!("unexpected event {:?}", e),
+        }
+    }
+
+    #[test]
+    fn drift_test() {
+        let params = NoiseParams {
+            drift: 0.1,
+            ..NoiseParams::default()
+        };
+        let mut odometry = Odometry::new(Position::default(), params, Some(0));
+        for _ in 0..10 {
+            odometry.integrate((0.1, 0.));
+        }
+        // the robot went 1m straight but believes it turned
+        assert!((odometry.pose.a - 0.1).abs() < 1e-5);
+        assert!(odometry.pose.p.x < 0.);
+    }
+
+    #[test]
+    fn gaussian_noise_test() {
+        let params = NoiseParams {
+            translation: 0.05,
+            ..NoiseParams::default()
+        };
+        let n = 1000;
+        let errors: Vec<f32> = (0..n)
+            .map(|seed| {
+                let mut odometry = Odometry::new(Position::default(), params, Some(seed));
+                odometry.integrate((1., 0.));
+                odometry.pose.p.y - 1.
+            })
+            .collect();
+        let mean = errors.iter().sum::<f32>() / n as f32;
+        let var = errors.iter().map(|e| (e - mean) * (e - mean)).sum::<f32>() / n as f32;
+        assert!(mean.abs() < 0.01, "mean {}", mean);
+        assert!((var.sqrt() - 0.05).abs() < 0.01, "std dev {}", var.sqrt());
+    }
+
+    #[test]
+    fn slip_with_noise_test() {
+        let params = NoiseParams {
+            translation: 0.05,
+            rotation: 0.05,
+            slip: 0.02,
+            ..NoiseParams::default()
+        };
+        let mut odometry = Odometry::new(Position::default(), params, Some(0));
+        for _ in 0..100 {
+            odometry.integrate((0.01, 0.01));
+            let before = odometry.pose;
+            odometry.slip(1.);
+            let slipped = before.p.sq_dist(odometry.pose.p).sqrt();
+            assert!(slipped <= 0.02 + 1e-6, "slipped {}", slipped);
+            odometry.integrate((-0.01, 0.));
+        }
+    }
+}
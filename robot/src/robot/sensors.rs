@@ -0,0 +1,181 @@
+use rand::distributions::{Distribution, Normal};
+use rand::rngs::StdRng;
+use rand::{FromEntropy, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use super::kinematics::heading;
+use crate::map::{Angle, Distance, PolyMap, Position, Segment};
+
+const PI: Angle = std::f32::consts::PI;
+/// Angles of the bumpers, relative to the heading of the robot
+const BUMPERS: [Angle; 3] = [PI / 4., 0., -PI / 4.];
+/// A bumper is pressed when a wall is closer than this
+const BUMPER_REACH: Distance = 0.01;
+
+/// Range sensors spread evenly around the robot, e.g. a lidar or a sonar ring
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RangeSensorParams {
+    /// No scan is done if there is no beam
+    pub nb_beams: usize,
+    /// m
+    pub max_range: Distance,
+    /// Standard deviation of the range error, m
+    pub noise: Distance,
+    /// Time between two scans, s
+    pub period: f32,
+}
+
+impl Default for RangeSensorParams {
+    fn default() -> Self {
+        RangeSensorParams {
+            nb_beams: 32,
+            max_range: 0.5,
+            noise: 0.005,
+            period: 0.2,
+        }
+    }
+}
+
+/// Ranges measured by a sensor ring, the first beam points
+/// in front of the robot and the next ones are counterclockwise
+#[derive(Clone, Debug, PartialEq)]
+pub struct RangeScan {
+    /// Nothing was hit by the beams measuring this range
+    pub max_range: Distance,
+    pub ranges: Vec<Distance>,
+}
+
+impl RangeScan {
+    /// Angles relative to the heading of the robot, along with the measured ranges
+    pub fn beams(&self) -> impl Iterator<Item = (Angle, Distance)> + '_ {
+        let increment = 2. * PI / self.ranges.len() as Angle;
+        self.ranges
+            .iter()
+            .enumerate()
+            .map(move |(i, &range)| (i as Angle * increment, range))
+    }
+}
+
+/// Simulated range sensors and bumpers
+pub struct Sensors {
+    params: RangeSensorParams,
+    // Simulated time since the last scan
+    since_scan: f32,
+    // Bumpers pressed at the last step
+    bumped: Vec<Angle>,
+    rng: StdRng,
+}
+
+impl Sensors {
+    pub fn new(params: RangeSensorParams) -> Self {
+        Sensors {
+            params,
+            since_scan: 0.,
+            bumped: Vec::new(),
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Makes the measurement noise reproducible
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn set_params(&mut self, params: RangeSensorParams) {
+        self.params = params;
+    }
+
+    /// Scans the map if a period has elapsed after `dt` seconds
+    pub fn scan(&mut self, pose: Position, map: &PolyMap, dt: f32) -> Option<RangeScan> {
+        self.since_scan += dt;
+        if self.params.nb_beams == 0 || self.since_scan < self.params.period {
+            return None;
+        }
+        self.since_scan = 0.;
+
+        let max_range = self.params.max_range;
+        let noise = Normal::new(0., f64::from(self.params.noise));
+        let increment = 2. * PI / self.params.nb_beams as Angle;
+        let ranges = (0..self.params.nb_beams)
+            .map(|i| {
+                let end = pose.p + heading(pose.a + i as Angle * increment) * max_range;
+                match map.first_intersection(&Segment(pose.p, end)) {
+                    Some(hit) => {
+                        let error = noise.sample(&mut self.rng) as Distance;
+                        ((hit - pose.p).norm() + error).max(0.).min(max_range)
+                    }
+                    None => max_range,
+                }
+            })
+            .collect();
+        Some(RangeScan { max_range, ranges })
+    }
+
+    /// Returns the angles of the pressed bumpers when they change
+    pub fn bumpers(&mut self, pose: Position, map: &PolyMap) -> Option<Vec<Angle>> {
+        let bumped: Vec<Angle> = BUMPERS
+            .iter()
+            .cloned()
+            .filter(|&angle| {
+                let end = pose.p + heading(pose.a + angle) * BUMPER_REACH;
+                map.first_intersection(&Segment(pose.p, end)).is_some()
+            })
+            .collect();
+        if bumped != self.bumped {
+            self.bumped = bumped.clone();
+            Some(bumped)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Point;
+
+    #[test]
+    fn scan_test() {
+        let map = PolyMap::default();
+        let mut sensors = Sensors::new(RangeSensorParams {
+            nb_beams: 4,
+            max_range: 2.,
+            noise: 0.,
+            period: 0.2,
+        });
+        let pose = Position {
+            p: Point { x: 1., y: -1. },
+            a: 0.,
+        };
+        assert_eq!(sensors.scan(pose, &map, 0.1), None);
+        assert_eq!(sensors.scan(pose, &map, 0.05), None);
+        let scan = sensors.scan(pose, &map, 0.06).expect("a scan is due");
+        // front, left, back, right
+        let expected = [1., 2., 2., 2.];
+        for (range, expected) in scan.ranges.iter().zip(expected.iter()) {
+            assert!((range - expected).abs() < 1e-5, "{:?}", scan.ranges);
+        }
+        let angles: Vec<Angle> = scan.beams().map(|(a, _)| a).collect();
+        assert_eq!(angles, vec![0., PI / 2., PI, 3. * PI / 2.]);
+    }
+
+    #[test]
+    fn bumpers_test() {
+        let map = PolyMap::default();
+        let mut sensors = Sensors::new(RangeSensorParams::default());
+        let free = Position {
+            p: Point { x: 1., y: -1. },
+            a: 0.,
+        };
+        assert_eq!(sensors.bumpers(free, &map), None);
+        let against_wall = Position {
+            p: Point { x: 1., y: -0.008 },
+            a: 0.,
+        };
+        assert_eq!(sensors.bumpers(against_wall, &map), Some(vec![0.]));
+        assert_eq!(sensors.bumpers(against_wall, &map), None);
+        assert_eq!(sensors.bumpers(free, &map), Some(vec![]));
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use super::Event::{self, *};
+use super::{RangeScan, RobotBackend};
+use crate::map::{Angle, Distance, Point, Position};
+
+/// Commands understood by the real robots, one per line
+#[derive(Debug, PartialEq)]
+#[allow(dead_code)]
+enum Command {
+    Init(Position),
+    GoTo(Point),
+    Forward(Distance),
+    Turn(Angle),
+    Stop,
+    Lacc,
+    Tune(usize, usize, f32),
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Init(pos) => write!(f, "init {} {} {}", pos.p.x, pos.p.y, pos.a),
+            Command::GoTo(p) => write!(f, "goto {} {}", p.x, p.y),
+            Command::Forward(dist) => write!(f, "forward {}", dist),
+            Command::Turn(angle) => write!(f, "turn {}", angle),
+            Command::Stop => write!(f, "stop"),
+            Command::Lacc => write!(f, "lacc"),
+            Command::Tune(nb_acc_for_mean, nb_consec_mean, mean_threshold) => write!(
+                f,
+                "tune {} {} {}",
+                nb_acc_for_mean, nb_consec_mean, mean_threshold
+            ),
+        }
+    }
+}
+
+/// Parses an event sent by a real robot, e.g. `reached 0.5 1.2 0.1`
+fn parse_event(line: &str) -> Option<Event> {
+    let mut words = line.split_whitespace();
+    let name = words.next()?;
+    let values = words
+        .map(str::parse)
+        .collect::<Result<Vec<f32>, _>>()
+        .ok()?;
+    let position = || match values.as_slice() {
+        &[x, y, a] => Some(Position {
+            p: Point { x, y },
+            a,
+        }),
+        _ => None,
+    };
+
+    match (name, values.as_slice()) {
+        ("collision", _) => position().map(Collision),
+        ("reached", _) => position().map(Reached),
+        ("curr", _) => position().map(Curr),
+        ("moved", &[dist]) => Some(Moved(dist)),
+        ("turned", &[angle]) => Some(Turned(angle)),
+        ("lacc", _) => Some(Lacc(values.clone())),
+        ("scan", &[max_range, ..]) => Some(Scan(RangeScan {
+            max_range,
+            ranges: values[1..].to_vec(),
+        })),
+        ("bump", _) => Some(Bump(values.clone())),
+        _ => None,
+    }
+}
+
+/// Real robot, speaking a line protocol over a serial port
+pub struct SerialRobot {
+    // Commands are written there
+    port: Mutex<Box<dyn Write + Send>>,
+    // Last position sent by the robot, updated by the listening thread
+    pos: Arc<Mutex<Position>>,
+    // Until the robot is started, events are not read
+    reader: Option<Box<dyn BufRead + Send>>,
+    // Tx to speak to the app, moved to the listening thread so that the app sees it end
+    app_tx: Option<mpsc::Sender<Event>>,
+}
+
+impl SerialRobot {
+    /// Opens a serial port, or any file behaving like one (e.g. a pty).
+    /// The port must have been configured beforehand, e.g. with `stty`.
+    pub fn open(path: &PathBuf) -> io::Result<(Self, mpsc::Receiver<Event>)> {
+        let port = OpenOptions::new().read(true).write(true).open(path)?;
+        let reader = BufReader::new(port.try_clone()?);
+        Ok(Self::from_streams(reader, port))
+    }
+
+    pub fn from_streams<R, W>(reader: R, port: W) -> (Self, mpsc::Receiver<Event>)
+    where
+        R: BufRead + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let (app_tx, rx) = mpsc::channel();
+        (
+            SerialRobot {
+                port: Mutex::new(Box::new(port)),
+                pos: Arc::new(Mutex::new(Position::default())),
+                reader: Some(Box::new(reader)),
+                app_tx: Some(app_tx),
+            },
+            rx,
+        )
+    }
+
+    fn send(&self, command: Command) {
+        log::debug!("robot command: {}", command);
+        let mut port = self.port.lock().unwrap();
+        if let Err(e) = writeln!(port, "{}", command).and_then(|_| port.flush()) {
+            log::error!("Failed to send {:?} to the robot, {}", command, e);
+        }
+    }
+}
+
+impl RobotBackend for SerialRobot {
+    /// Starts listening to the robot
+    fn start(&mut self) {
+        let (reader, app_tx) = match (self.reader.take(), self.app_tx.take()) {
+            (Some(reader), Some(app_tx)) => (reader, app_tx),
+            _ => return,
+        };
+        let pos = Arc::clone(&self.pos);
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => {
+                        // the events channel is closed with the thread
+                        log::error!("could not read from the robot, {}", e);
+                        break;
+                    }
+                };
+                match parse_event(&line) {
+                    Some(event) => {
+                        if let Collision(p) | Reached(p) | Curr(p) = event {
+                            *pos.lock().unwrap() = p;
+                        }
+                        if app_tx.send(event).is_err() {
+                            break;
+                        }
+                    }
+                    None => log::error!("could not decode robot event {:?}", line),
+                }
+            }
+        });
+    }
+
+    fn init(&mut self, position: Position) {
+        *self.pos.lock().unwrap() = position;
+        self.send(Command::Init(position));
+    }
+
+    fn pos(&self) -> Position {
+        *self.pos.lock().unwrap()
+    }
+
+    fn go_to(&mut self, dest: Point) {
+        self.send(Command::GoTo(dest));
+    }
+
+    fn forward(&mut self, dist: Distance) {
+        self.send(Command::Forward(dist));
+    }
+
+    fn turn(&mut self, angle: Angle) {
+        self.send(Command::Turn(angle));
+    }
+
+    fn stop(&mut self) {
+        self.send(Command::Stop);
+    }
+
+    fn lacc(&self) {
+        self.send(Command::Lacc);
+    }
+
+    fn tune(&mut self, nb_acc_for_mean: usize, nb_consec_mean: usize, mean_threshold: f32) {
+        self.send(Command::Tune(
+            nb_acc_for_mean,
+            nb_consec_mean,
+            mean_threshold,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Writer whose content can be read after being moved to the robot
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parse_event_test() {
+        match parse_event("reached 0.5 -1 0.25") {
+            Some(Reached(p)) => assert_eq!(
+                p,
+                Position {
+                    p: Point { x: 0.5, y: -1. },
+                    a: 0.25
+                }
+            ),
+            e => panic!("unexpected event {:?}", e),
+        }
+        match parse_event("lacc 1 2.5 3") {
+            Some(Lacc(acc)) => assert_eq!(acc, vec![1., 2.5, 3.]),
+            e => panic!("unexpected event {:?}", e),
+        }
+        match parse_event("scan 0.5 0.1 0.5") {
+            Some(Scan(scan)) => {
+                assert_eq!(scan.max_range, 0.5);
+                assert_eq!(scan.ranges, vec![0.1, 0.5]);
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+        assert!(parse_event("moved 0.1").is_some());
+        assert!(parse_event("bump").is_some());
+        assert!(parse_event("scan").is_none());
+        assert!(parse_event("moved").is_none());
+        assert!(parse_event("curr 1 2").is_none());
+        assert!(parse_event("turned a").is_none());
+        assert!(parse_event("teleported 1 2 3").is_none());
+    }
+
+    #[test]
+    fn serial_robot_test() {
+        let input = Cursor::new("curr 0 0.1 0\ngarbage\ncollision 0 0.2 0\n");
+        let output = SharedBuffer::default();
+        let (mut robot, rx) = SerialRobot::from_streams(input, output.clone());
+
+        robot.go_to(Point { x: 1., y: 2. });
+        robot.forward(-0.1);
+        robot.tune(3, 2, 1.5);
+        let sent = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(sent, "goto 1 2\nforward -0.1\ntune 3 2 1.5\n");
+
+        robot.start();
+        match rx.recv() {
+            Ok(Curr(p)) => assert_eq!(p.p, Point { x: 0., y: 0.1 }),
+            e => panic!("unexpected event {:?}", e),
+        }
+        match rx.recv() {
+            Ok(Collision(p)) => assert_eq!(p.p, Point { x: 0., y: 0.2 }),
+            e => panic!("unexpected event {:?}", e),
+        }
+        assert_eq!(robot.pos().p, Point { x: 0., y: 0.2 });
+    }
+
+    #[test]
+    fn read_error_test() {
+        // not UTF-8
+        let input = Cursor::new(b"curr 0 0.1 0\n\xff\ncurr 0 0.2 0\n".to_vec());
+        let (mut robot, rx) = SerialRobot::from_streams(input, SharedBuffer::default());
+        robot.start();
+        assert!(matches!(rx.recv(), Ok(Curr(_))));
+        // the listening thread ended without panicking
+        assert!(rx.recv().is_err());
+        assert_eq!(robot.pos().p, Point { x: 0., y: 0.1 });
+    }
+}
@@ -0,0 +1,260 @@
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::accelerometer::{Accelerometer, CollisionParams};
+use super::kinematics::{DiffDrive, DriveParams};
+use super::noise::{NoiseParams, Odometry};
+use super::sensors::{RangeSensorParams, Sensors};
+use super::Event::{self, *};
+use super::RobotBackend;
+use crate::map::{normalize_angle, Angle, Distance, MapError, Point, PolyMap, Position};
+
+/// Simulated time between two integrations of the robot's pose, in seconds
+pub const SIMULATION_STEP: f32 = 0.01;
+/// Simulated time between two `Curr` events while moving, in seconds
+const CURR_PERIOD: f32 = 0.1;
+
+/// State of the simulated world, advanced step by step
+struct Simulation {
+    // Tx to speak to the app
+    app_tx: mpsc::Sender<Event>,
+    // Motion model of the robot, holds its actual position
+    drive: DiffDrive,
+    // Position the robot believes it has, the one reported to the app
+    odometry: Odometry,
+    // Detects the collisions
+    accelerometer: Accelerometer,
+    // Range sensors and bumpers
+    sensors: Sensors,
+    // Actual map used for the simuation
+    actual_map: PolyMap,
+    // Simulated time since the last `Curr` event
+    since_curr: f32,
+}
+
+impl Simulation {
+    fn send_to_app(&self, events: Vec<Event>) -> Result<(), mpsc::SendError<Event>> {
+        for event in events {
+            self.app_tx.send(event)?;
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, dt: f32) -> Result<(), mpsc::SendError<Event>> {
+        let pose = self.drive.pose;
+        if let Some(scan) = self.sensors.scan(pose, &self.actual_map, dt) {
+            if !self.drive.is_idle() {
+                // so that the app knows where the scan was made
+                self.app_tx.send(Curr(self.odometry.pose))?;
+            }
+            self.app_tx.send(Scan(scan))?;
+        }
+        if let Some(bumped) = self.sensors.bumpers(pose, &self.actual_map) {
+            self.app_tx.send(Bump(bumped))?;
+        }
+
+        if self.drive.is_idle() {
+            self.since_curr = 0.;
+            self.accelerometer.reset();
+            return Ok(());
+        }
+        let before = self.drive.pose;
+        let mut events = self.drive.step(dt, &self.actual_map);
+        self.odometry.integrate(self.drive.wheel_motion);
+        if events.iter().any(|e| matches!(e, Collision(_))) {
+            // reported by the bumpers
+            self.odometry.slip(self.drive.wheel_motion.0.signum());
+        }
+
+        // actual speed of the robot, null when it is stuck against a wall
+        let speed = (self.drive.pose.p - before.p).norm() / dt;
+        if self
+            .accelerometer
+            .measure(speed, self.drive.angular_speed, dt)
+            && !self.drive.is_idle()
+        {
+            self.odometry.slip(self.drive.speed.signum());
+            events.extend(self.drive.collide());
+        }
+        let events = self.report(events);
+
+        self.since_curr += dt;
+        if self.since_curr >= CURR_PERIOD && !self.drive.is_idle() {
+            self.since_curr = 0.;
+            self.app_tx.send(Curr(self.odometry.pose))?;
+        }
+        self.send_to_app(events)
+    }
+
+    /// Converts events holding the ground truth to the values measured by the robot
+    fn report(&mut self, events: Vec<Event>) -> Vec<Event> {
+        events
+            .into_iter()
+            .map(|event| {
+                if let Reached(_) | Collision(_) = event {
+                    let (truth, odometry) = (self.drive.pose, self.odometry.pose);
+                    log::debug!(
+                        "odometry error: {} m, {} rad",
+                        (truth.p - odometry.p).norm(),
+                        normalize_angle(truth.a - odometry.a).abs()
+                    );
+                }
+                self.odometry.report(event)
+            })
+            .collect()
+    }
+}
+
+/// Simulated robot, moving in a map loaded from a file.
+/// Its clones share the same simulation.
+#[derive(Clone)]
+pub struct SimRobot {
+    // Shared with the thread running the simulation
+    sim: Arc<Mutex<Simulation>>,
+    // Runs the simulation in a thread once started, it is otherwise advanced with `step`
+    real_time: bool,
+}
+
+impl SimRobot {
+    pub fn new() -> (Self, mpsc::Receiver<Event>) {
+        let (app_tx, rx) = mpsc::channel();
+
+        let sim = Simulation {
+            app_tx,
+            drive: DiffDrive::new(Position::default(), DriveParams::default()),
+            odometry: Odometry::new(Position::default(), NoiseParams::default(), None),
+            accelerometer: Accelerometer::new(CollisionParams::default()),
+            sensors: Sensors::new(RangeSensorParams::default()),
+            actual_map: PolyMap::new(vec![]),
+            since_curr: 0.,
+        };
+        (
+            SimRobot {
+                sim: Arc::new(Mutex::new(sim)),
+                real_time: true,
+            },
+            rx,
+        )
+    }
+
+    fn sim(&self) -> std::sync::MutexGuard<'_, Simulation> {
+        self.sim.lock().expect("the simulation thread panicked")
+    }
+
+    pub fn load_map(&mut self, path: &PathBuf) -> Result<(), MapError> {
+        self.sim().actual_map = PolyMap::from_file(path)?;
+        Ok(())
+    }
+
+    /// Sets the odometry errors, they are reproducible when a `seed` is given
+    pub fn set_noise(&mut self, noise: NoiseParams, seed: Option<u64>) {
+        let mut sim = self.sim();
+        let pose = sim.odometry.pose;
+        sim.odometry = Odometry::new(pose, noise, seed);
+        if let Some(seed) = seed {
+            sim.accelerometer.reseed(seed);
+            sim.sensors.reseed(seed);
+        }
+    }
+
+    pub fn set_range_sensor(&mut self, params: RangeSensorParams) {
+        self.sim().sensors.set_params(params);
+    }
+
+    /// Actual position of the robot in the simulation
+    pub fn ground_truth(&self) -> Position {
+        self.sim().drive.pose
+    }
+
+    /// The simulation is advanced by `step` instead of a thread, e.g. on a virtual clock
+    pub fn set_real_time(&mut self, real_time: bool) {
+        self.real_time = real_time;
+    }
+
+    /// Advances the simulation by `dt` seconds, the events being sent as in real time
+    pub fn step(&self, dt: f32) {
+        // the app may be gone at the end of a simulation
+        let _ = self.sim().step(dt);
+    }
+}
+
+impl RobotBackend for SimRobot {
+    /// Starts the simulation, in real time
+    fn start(&mut self) {
+        if !self.real_time {
+            return;
+        }
+        println!("Hello from Robot :)");
+        let sim = Arc::clone(&self.sim);
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_millis((SIMULATION_STEP * 1000.) as u64));
+            // stops when the app is not listening anymore
+            if sim.lock().unwrap().step(SIMULATION_STEP).is_err() {
+                break;
+            }
+        });
+    }
+
+    /// Sets both the actual and the believed position of the robot
+    fn init(&mut self, position: Position) {
+        let mut sim = self.sim();
+        if !sim.actual_map.is_free(position.p) {
+            log::warn!("the robot starts inside an obstacle, at {:?}", position.p);
+        }
+        log::debug!(
+            "the robot starts {:.2} m from the closest wall",
+            sim.actual_map.clearance(position.p)
+        );
+        sim.drive.pose = position;
+        sim.odometry.pose = position;
+    }
+
+    /// Position of the robot, as measured by its odometry
+    fn pos(&self) -> Position {
+        self.sim().odometry.pose
+    }
+
+    fn go_to(&mut self, dest: Point) {
+        let mut sim = self.sim();
+        let from = sim.odometry.pose;
+        let events = sim.drive.go_to(from, dest);
+        let events = sim.report(events);
+        sim.send_to_app(events).unwrap();
+    }
+
+    fn forward(&mut self, dist: Distance) {
+        let mut sim = self.sim();
+        let events = sim.drive.forward(dist);
+        let events = sim.report(events);
+        sim.send_to_app(events).unwrap();
+    }
+
+    fn turn(&mut self, angle: Angle) {
+        let mut sim = self.sim();
+        let events = sim.drive.turn(angle);
+        let events = sim.report(events);
+        sim.send_to_app(events).unwrap();
+    }
+
+    fn stop(&mut self) {
+        let mut sim = self.sim();
+        let events = sim.drive.cancel();
+        let events = sim.report(events);
+        sim.send_to_app(events).unwrap();
+    }
+
+    fn lacc(&self) {
+        let sim = self.sim();
+        sim.app_tx.send(Lacc(sim.accelerometer.last())).unwrap();
+    }
+
+    fn tune(&mut self, nb_acc_for_mean: usize, nb_consec_mean: usize, mean_threshold: f32) {
+        self.sim().accelerometer.tune(CollisionParams {
+            nb_acc_for_mean,
+            nb_consec_mean,
+            mean_threshold,
+        });
+    }
+}
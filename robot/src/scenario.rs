@@ -0,0 +1,244 @@
+//! Description of a run of the swarm, so that it can be reproduced: the world, the robots,
+//! their sensors and noises, the network, how long the run lasts and when it succeeds.
+//! The supervisor and the robots load the same scenario, each robot taking its own part.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::ai::MergePolicy;
+use crate::app::AppId;
+use crate::map::{Angle, Point, Position};
+use crate::netem::NetemConfig;
+use crate::robot::{NoiseParams, RangeSensorParams};
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct RobotSpec {
+    pub id: AppId,
+    /// Start position
+    #[serde(flatten)]
+    pub start: Point,
+    /// Start heading, in radians
+    #[serde(default)]
+    pub heading: Angle,
+    /// Log level of this robot, the one of the scenario if not given
+    #[serde(default)]
+    pub log_level: Option<String>,
+    /// Noise of this robot, the one of the scenario if not given
+    #[serde(default)]
+    pub noise: Option<NoiseParams>,
+    /// Range sensor of this robot, the one of the scenario if not given
+    #[serde(default)]
+    pub sensor: Option<RangeSensorParams>,
+    /// Arguments of this robot, after the ones of the scenario
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// How the robots explore and share their maps, as given by their flags otherwise
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(default)]
+pub struct Strategy {
+    pub merge_policy: MergePolicy,
+    /// Period of the anti-entropy, in seconds, 0 disables it
+    pub sync_period: f32,
+    /// The robots register the maps of the others, not knowing their relative start positions
+    pub align_maps: bool,
+    /// Number of robots for the consensus, the robots heard of recently count if not given
+    pub swarm_size: Option<usize>,
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy {
+            merge_policy: MergePolicy::default(),
+            sync_period: 1.,
+            align_maps: false,
+            swarm_size: None,
+        }
+    }
+}
+
+/// The run succeeds when all the given criteria are met
+#[derive(Clone, Default, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(default)]
+pub struct SuccessCriteria {
+    /// Minimum fraction of the free space of the map explored by the swarm
+    pub coverage: Option<f32>,
+    /// Maximum number of collisions of all the robots
+    pub max_collisions: Option<usize>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Scenario {
+    /// Map of the world, relative to the scenario file
+    pub map: PathBuf,
+    pub robots: Vec<RobotSpec>,
+    /// Odometry noise of the robots
+    #[serde(default)]
+    pub noise: NoiseParams,
+    /// Range sensor of the robots
+    #[serde(default)]
+    pub sensor: RangeSensorParams,
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// Network between the robots, a perfect one with the range of the router by default
+    #[serde(default)]
+    pub network: NetemConfig,
+    /// Duration of the run, in seconds, until the robots exit if not given
+    #[serde(default)]
+    pub time_limit: Option<f32>,
+    #[serde(default)]
+    pub success: SuccessCriteria,
+    /// Seed of all the random draws, the robots adding their ids to it
+    #[serde(default)]
+    pub seed: u64,
+    /// Log level of the robots, as RUST_LOG
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    /// Arguments of all the robots
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+/// Sets a value in a JSON object given its dotted path, creating the missing objects
+fn set_path(json: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    let mut node = json;
+    for key in path.split('.') {
+        if node.is_null() {
+            *node = Value::Object(Default::default());
+        }
+        node = match node {
+            Value::Object(object) => object.entry(key).or_insert(Value::Null),
+            _ => return Err(format!("{} is not in an object", path)),
+        };
+    }
+    *node = value;
+    Ok(())
+}
+
+impl Scenario {
+    /// Loads a scenario, the paths it contains becoming relative to the current directory
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::load_with(path, &[])
+    }
+
+    /// Loads a scenario, replacing some of its values, given by their dotted paths
+    /// like `network.radio.range`
+    pub fn load_with(path: &Path, overrides: &[(String, Value)]) -> Result<Self, Box<dyn Error>> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut json: Value = serde_json::from_reader(reader)?;
+        for (key, value) in overrides.iter() {
+            set_path(&mut json, key, value.clone())?;
+        }
+        let mut scenario: Scenario = serde_json::from_value(json)?;
+        if let Some(dir) = path.parent() {
+            scenario.map = dir.join(&scenario.map);
+        }
+        Ok(scenario)
+    }
+
+    pub fn robot(&self, id: AppId) -> Option<&RobotSpec> {
+        self.robots.iter().find(|robot| robot.id == id)
+    }
+
+    pub fn start(&self, robot: &RobotSpec) -> Position {
+        Position {
+            p: robot.start,
+            a: robot.heading,
+        }
+    }
+
+    pub fn log_level(&self, robot: &RobotSpec) -> String {
+        robot
+            .log_level
+            .clone()
+            .unwrap_or_else(|| self.log_level.clone())
+    }
+
+    pub fn noise(&self, robot: &RobotSpec) -> NoiseParams {
+        robot.noise.unwrap_or(self.noise)
+    }
+
+    pub fn sensor(&self, robot: &RobotSpec) -> RangeSensorParams {
+        robot.sensor.unwrap_or(self.sensor)
+    }
+
+    /// Seed of the simulation of a robot, different for each robot
+    pub fn seed(&self, robot: &RobotSpec) -> u64 {
+        self.seed.wrapping_add(u64::from(robot.id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scenario_test() {
+        let scenario = Scenario::load(Path::new("scenario.json")).expect("invalid scenario");
+        assert_eq!(scenario.map, PathBuf::from("map.json"));
+        assert!(scenario.robots.len() >= 2);
+
+        let json = r#"{
+            "map": "maps/arena.json",
+            "robots": [
+                {"id": 1, "x": 0.5, "y": -0.5, "log_level": "debug", "noise": {"drift": 0.1}},
+                {"id": 2, "x": 0, "y": 0, "heading": 1.5}
+            ],
+            "sensor": {"nb_beams": 8},
+            "strategy": {"merge_policy": "lww"},
+            "network": {"default": {"loss": 0.1}, "radio": {"range": 2}},
+            "success": {"coverage": 0.8},
+            "seed": 42
+        }"#;
+        let scenario: Scenario = serde_json::from_str(json).unwrap();
+        let (first, second) = (&scenario.robots[0], &scenario.robots[1]);
+        assert_eq!(scenario.start(first).p, Point { x: 0.5, y: -0.5 });
+        assert_eq!(scenario.start(second).a, 1.5);
+        assert_eq!(scenario.robot(2), Some(second));
+        assert_eq!(scenario.robot(3), None);
+
+        assert_eq!(scenario.log_level(first), "debug");
+        assert_eq!(scenario.log_level(second), "info");
+        assert_eq!(scenario.noise(first).drift, 0.1);
+        assert_eq!(scenario.noise(second), NoiseParams::default());
+        assert_eq!(scenario.sensor(first).nb_beams, 8);
+        assert_eq!(
+            scenario.sensor(second).max_range,
+            RangeSensorParams::default().max_range
+        );
+        assert_ne!(scenario.seed(first), scenario.seed(second));
+
+        assert_eq!(scenario.strategy.merge_policy, MergePolicy::LastWriterWins);
+        assert_eq!(scenario.strategy.sync_period, 1.);
+        assert_eq!(scenario.network.default.loss, 0.1);
+        assert_eq!(scenario.network.radio.map(|radio| radio.range), Some(2.));
+        assert_eq!(scenario.time_limit, None);
+        assert_eq!(scenario.success.coverage, Some(0.8));
+    }
+
+    #[test]
+    fn overrides_test() {
+        let overrides = [
+            ("network.radio.range".to_string(), Value::from(2.5)),
+            ("strategy.merge_policy".to_string(), Value::from("voting")),
+            ("seed".to_string(), Value::from(7)),
+        ];
+        let scenario = Scenario::load_with(Path::new("scenario.json"), &overrides).unwrap();
+        assert_eq!(scenario.network.radio.map(|radio| radio.range), Some(2.5));
+        assert_eq!(scenario.strategy.merge_policy, MergePolicy::Voting);
+        assert_eq!(scenario.seed, 7);
+
+        let overrides = [("seed.value".to_string(), Value::from(7))];
+        assert!(Scenario::load_with(Path::new("scenario.json"), &overrides).is_err());
+    }
+}
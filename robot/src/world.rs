@@ -0,0 +1,341 @@
+//! In-process simulation of a whole swarm on a virtual clock, for experiments.
+//! The apps of the robots run on simulated robots stepped together, and their messages
+//! go through the radio and the emulated network of the scenario, much faster than real time.
+//! The runs are reproducible given the seed of the scenario.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::ai::CellState;
+use crate::app::{App, AppId, Event, Timer};
+use crate::map::{Point, PolyMap};
+use crate::metrics::{GroundTruth, SwarmQuality, DEFAULT_TOLERANCE};
+use crate::netem::{Emulator, Time};
+use crate::radio::Radio;
+use crate::robot::{self, SimRobot, SIMULATION_STEP};
+use crate::scenario::Scenario;
+
+/// Duration of the runs of the scenarios without time limit, in seconds
+pub const DEFAULT_TIME_LIMIT: f32 = 300.;
+
+/// Messages written by an app, shared with the world delivering them
+#[derive(Clone, Default)]
+struct Outbox(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Outbox {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Outbox {
+    fn take_lines(&self) -> Vec<String> {
+        let bytes = std::mem::take(&mut *self.0.borrow_mut());
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+/// A robot of the simulated swarm
+struct Member {
+    id: AppId,
+    app: App,
+    robot: SimRobot,
+    robot_rx: mpsc::Receiver<robot::Event>,
+    outbox: Outbox,
+    /// Timers of the app, with their periods and when they next expire
+    timers: Vec<(Timer, f32, f32)>,
+    last: Point,
+}
+
+/// What a run measured, the coverage being the fraction of the free space known by any robot
+#[derive(Clone, Default, Serialize, Debug, PartialEq)]
+pub struct Metrics {
+    /// Simulated duration of the run, in seconds
+    pub duration: f32,
+    pub coverage: f32,
+    /// Coverage sampled during the run, as (time, coverage)
+    pub coverage_over_time: Vec<(f32, f32)>,
+    /// Distance travelled by all the robots, in meters
+    pub distance: f32,
+    pub collisions: usize,
+    /// Messages sent by all the robots, and their size
+    pub messages: usize,
+    pub bytes: usize,
+    /// When the coverage of the success criteria was reached
+    pub completion_time: Option<f32>,
+    /// All the success criteria are met
+    pub success: bool,
+    /// Quality of the maps at the end of the run
+    pub quality: SwarmQuality,
+    /// Positions of the robots at each sample and at the end of the run
+    pub trajectories: BTreeMap<AppId, Vec<Point>>,
+}
+
+pub struct World {
+    scenario: Scenario,
+    members: Vec<Member>,
+    index: HashMap<AppId, usize>,
+    radio: Radio,
+    emulator: Emulator<String>,
+    truth: GroundTruth,
+    time: f32,
+    metrics: Metrics,
+}
+
+impl World {
+    pub fn new(scenario: &Scenario) -> Result<Self, Box<dyn Error>> {
+        let map = PolyMap::from_file(&scenario.map)?;
+        let mut members = Vec::new();
+        for spec in scenario.robots.iter() {
+            let (mut robot, robot_rx) = SimRobot::new();
+            robot.load_map(&scenario.map)?;
+            robot.set_noise(scenario.noise(spec), Some(scenario.seed(spec)));
+            robot.set_range_sensor(scenario.sensor(spec));
+            robot.set_real_time(false);
+
+            let outbox = Outbox::default();
+            let mut app =
+                App::in_process(spec.id, Box::new(robot.clone()), Box::new(outbox.clone()));
+            let strategy = &scenario.strategy;
+            app.set_map_alignment(strategy.align_maps);
+            app.set_merge_policy(strategy.merge_policy, &[]);
+            app.set_sync_period(strategy.sync_period);
+            app.set_swarm_size(strategy.swarm_size);
+            app.init(scenario.start(spec));
+            let timers = app
+                .timers()
+                .into_iter()
+                .map(|(timer, period)| (timer, period.as_secs_f32(), period.as_secs_f32()))
+                .collect();
+            members.push(Member {
+                id: spec.id,
+                app,
+                robot,
+                robot_rx,
+                outbox,
+                timers,
+                last: spec.start,
+            });
+        }
+        let index = members.iter().enumerate().map(|(i, m)| (m.id, i)).collect();
+
+        let starts: Vec<Point> = scenario.robots.iter().map(|spec| spec.start).collect();
+        let truth = GroundTruth::new(&map, &starts, DEFAULT_TOLERANCE);
+        let radio = Radio::new(
+            scenario.network.radio.unwrap_or_default(),
+            map,
+            scenario.seed,
+        );
+        let emulator = Emulator::new(scenario.network.clone(), scenario.seed);
+        let mut world = World {
+            scenario: scenario.clone(),
+            members,
+            index,
+            radio,
+            emulator,
+            truth,
+            time: 0.,
+            metrics: Metrics::default(),
+        };
+        for member in world.members.iter_mut() {
+            member.app.set_time(Duration::default());
+            member.app.start();
+        }
+        world.handle_robot_events();
+        Ok(world)
+    }
+
+    /// Simulated time, in seconds
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// The robots with their ground truth positions
+    pub fn positions(&self) -> HashMap<AppId, Point> {
+        self.members
+            .iter()
+            .map(|m| (m.id, m.robot.ground_truth().p))
+            .collect()
+    }
+
+    /// The maps of the robots
+    pub fn maps(&self) -> impl Iterator<Item = (AppId, &ndarray::Array2<CellState>)> {
+        self.members.iter().map(|m| (m.id, m.app.map()))
+    }
+
+    /// Fraction of the free space known by at least one robot
+    pub fn coverage(&self) -> f32 {
+        self.truth
+            .explored(self.members.iter().map(|m| m.app.map()))
+    }
+
+    /// Quality of the maps of the robots against the world
+    pub fn quality(&self) -> SwarmQuality {
+        self.truth.evaluate_swarm(self.maps())
+    }
+
+    /// Gives the events of the simulated robots to their apps
+    fn handle_robot_events(&mut self) {
+        for member in self.members.iter_mut() {
+            // the apps command their robots, which answer immediately
+            while let Ok(event) = member.robot_rx.try_recv() {
+                if let robot::Event::Collision(_) = event {
+                    self.metrics.collisions += 1;
+                }
+                member.app.handle(Event::RobotMessage(event));
+            }
+        }
+    }
+
+    /// Sends the new messages of the apps through the network, and delivers the due ones
+    fn route_messages(&mut self) {
+        let now = Time::from(self.time);
+        let positions = self.positions();
+        for member in self.members.iter() {
+            for line in member.outbox.take_lines() {
+                self.metrics.messages += 1;
+                let size = line.len() + 1;
+                self.metrics.bytes += size;
+                let receivers = self.radio.receivers(member.id, &positions);
+                self.emulator
+                    .broadcast(now, member.id, &receivers, line, size);
+            }
+        }
+        for delivery in self.emulator.poll(now) {
+            let member = &mut self.members[self.index[&delivery.to]];
+            member.app.handle(Event::DistantInput(delivery.payload));
+        }
+    }
+
+    /// Advances the simulation by one step
+    pub fn step(&mut self) {
+        self.time += SIMULATION_STEP;
+        for member in self.members.iter_mut() {
+            member.app.set_time(Duration::from_secs_f32(self.time));
+            member.robot.step(SIMULATION_STEP);
+            let p = member.robot.ground_truth().p;
+            self.metrics.distance += (p - member.last).norm();
+            member.last = p;
+        }
+        self.handle_robot_events();
+
+        for member in self.members.iter_mut() {
+            for (timer, period, next) in member.timers.iter_mut() {
+                if *next <= self.time {
+                    *next += *period;
+                    member.app.handle(Event::Timer(*timer));
+                }
+            }
+        }
+        self.handle_robot_events();
+
+        self.route_messages();
+        self.handle_robot_events();
+    }
+
+    /// Appends the actual position of each robot to its trajectory
+    fn record_positions(&mut self) {
+        for member in self.members.iter() {
+            let trajectory = self.metrics.trajectories.entry(member.id).or_default();
+            trajectory.push(member.robot.ground_truth().p);
+        }
+    }
+
+    /// Runs the scenario until its time limit, or until its coverage is reached,
+    /// sampling the coverage every `sample_period`
+    pub fn run(mut self, sample_period: Duration) -> Metrics {
+        let time_limit = self.scenario.time_limit.unwrap_or(DEFAULT_TIME_LIMIT);
+        let sample_period = sample_period.as_secs_f32();
+        let mut next_sample = 0.;
+        let target = self.scenario.success.coverage;
+        loop {
+            if self.time >= next_sample {
+                next_sample += sample_period;
+                let quality = self.quality();
+                let coverage = quality.explored;
+                self.metrics.coverage_over_time.push((self.time, coverage));
+                self.record_positions();
+                log::info!(
+                    "{:.0}s: explored {:.1}%, false free {:.2}%, false blocked {:.2}%, consistency {:.1}%",
+                    self.time,
+                    coverage * 100.,
+                    quality.false_free * 100.,
+                    quality.false_blocked * 100.,
+                    quality.consistency * 100.
+                );
+                if target.is_some_and(|target| coverage >= target) {
+                    self.metrics.completion_time = Some(self.time);
+                    break;
+                }
+            }
+            if self.time >= time_limit {
+                break;
+            }
+            self.step();
+        }
+
+        self.record_positions();
+        self.metrics.duration = self.time;
+        self.metrics.quality = self.quality();
+        self.metrics.coverage = self.metrics.quality.explored;
+        let success = &self.scenario.success;
+        self.metrics.success = (target.is_none() || self.metrics.completion_time.is_some())
+            && success
+                .max_collisions
+                .is_none_or(|max| self.metrics.collisions <= max);
+        self.metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn world_test() {
+        let mut scenario = Scenario::load(Path::new("scenario.json")).unwrap();
+        scenario.time_limit = Some(3.);
+        scenario.success.coverage = None;
+        let world = World::new(&scenario).unwrap();
+        assert_eq!(world.positions().len(), scenario.robots.len());
+        let start = world.coverage();
+
+        let metrics = world.run(Duration::from_secs(1));
+        assert!(metrics.duration >= 3.);
+        assert_eq!(metrics.coverage_over_time.len(), 4);
+        assert!(metrics.coverage > start);
+        assert!(metrics.distance > 0.);
+        assert!(metrics.messages >= scenario.robots.len());
+        assert!(metrics.bytes > metrics.messages);
+        assert_eq!(metrics.completion_time, None);
+        assert_eq!(metrics.quality.robots.len(), scenario.robots.len());
+        assert!(metrics.quality.false_free < 0.1);
+        // the 4 samples and the end
+        assert_eq!(metrics.trajectories.len(), scenario.robots.len());
+        assert!(metrics.trajectories.values().all(|t| t.len() == 5));
+    }
+
+    #[test]
+    fn reproducible_test() {
+        let mut scenario = Scenario::load(Path::new("scenario.json")).unwrap();
+        scenario.time_limit = Some(3.);
+        scenario.success.coverage = None;
+        let run = || World::new(&scenario).unwrap().run(Duration::from_secs(1));
+        assert_eq!(run(), run());
+    }
+}